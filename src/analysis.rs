@@ -0,0 +1,156 @@
+// What changed in each modified room, from both versions of its XML, computed on background
+// threads. Results are cached by the blob ids of the two versions, so that refreshing the
+// modified room list only analyzes rooms whose contents changed. The modified room list's
+// badges all come from here.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use git2::Oid;
+use hashbrown::HashMap;
+
+use crate::{
+    changes::{RoomChangeKind, classify_room_changes},
+    room::{blob_id, parse_room},
+    tile_search::{bts_grid, layer1_grid},
+};
+
+// Blob ids of the working copy and reference versions of a room
+pub type AnalysisKey = (Oid, Option<Oid>);
+
+pub type AnalysisCache = Arc<Mutex<HashMap<AnalysisKey, RoomAnalysis>>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomAnalysis {
+    // What changed, or None if the room is new (or the reference couldn't be parsed)
+    pub change_kinds: Option<BTreeSet<RoomChangeKind>>,
+    // Number of 16x16 tiles (over all states) whose layer 1 or BTS data changed, or None if
+    // the versions can't be compared tile by tile (e.g. the room was resized)
+    pub changed_tiles: Option<usize>,
+}
+
+impl RoomAnalysis {
+    // Whether the files differ without any change to what they describe (e.g. only in
+    // formatting or the order of attributes).
+    pub fn is_no_op(&self) -> bool {
+        self.change_kinds.as_ref().is_some_and(|k| k.is_empty())
+    }
+
+    // Summary for the modified room list, e.g. " [L1, BTS] 12 tiles".
+    pub fn badges(&self) -> String {
+        let Some(kinds) = &self.change_kinds else {
+            return " [new]".to_string();
+        };
+        if self.is_no_op() {
+            return " [no-op]".to_string();
+        }
+        let kinds: Vec<String> = kinds.iter().map(|x| x.to_string()).collect();
+        match self.changed_tiles {
+            Some(n) if n > 0 => format!(" [{}] {} tiles", kinds.join(", "), n),
+            _ => format!(" [{}]", kinds.join(", ")),
+        }
+    }
+}
+
+fn count_changed_tiles(working: &[u8], reference: &[u8]) -> Option<usize> {
+    let path = Path::new("");
+    let working = parse_room(working, path).ok()?;
+    let reference = parse_room(reference, path).ok()?;
+    if (working.width, working.height) != (reference.width, reference.height) {
+        return None;
+    }
+    let states = working.states.state.len().max(reference.states.state.len());
+    let mut count = 0;
+    for i in 0..states {
+        let grids = |room| (layer1_grid(room, i), bts_grid(room, i));
+        let (working_layer1, working_bts) = grids(&working);
+        let (reference_layer1, reference_bts) = grids(&reference);
+        count += (0..working_layer1.len())
+            .filter(|&j| {
+                working_layer1[j] != reference_layer1[j] || working_bts[j] != reference_bts[j]
+            })
+            .count();
+    }
+    Some(count)
+}
+
+pub fn analyze_room(working: &[u8], reference: Option<&[u8]>) -> RoomAnalysis {
+    let Some(reference) = reference else {
+        return RoomAnalysis {
+            change_kinds: None,
+            changed_tiles: None,
+        };
+    };
+    RoomAnalysis {
+        change_kinds: classify_room_changes(working, reference).ok(),
+        changed_tiles: count_changed_tiles(working, reference),
+    }
+}
+
+// Analyze a room, reusing the result for the same two versions if there is one.
+pub fn analyze_room_cached(
+    cache: &AnalysisCache,
+    working: &[u8],
+    reference: Option<&[u8]>,
+) -> RoomAnalysis {
+    let key = (blob_id(working), reference.map(blob_id));
+    if let Some(analysis) = cache.lock().unwrap().get(&key) {
+        return analysis.clone();
+    }
+    let analysis = analyze_room(working, reference);
+    cache.lock().unwrap().insert(key, analysis.clone());
+    analysis
+}
+
+#[derive(Default)]
+pub struct AnalysisStore {
+    cache: AnalysisCache,
+    // Latest analysis of each modified room, by path. Results from before the last request
+    // stay available until they are replaced.
+    rooms: HashMap<PathBuf, RoomAnalysis>,
+    generation: usize,
+    requested: bool,
+    pending: usize,
+}
+
+impl AnalysisStore {
+    pub fn get(&self, path: &Path) -> Option<&RoomAnalysis> {
+        self.rooms.get(path)
+    }
+
+    // Number of rooms still being analyzed
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    // Ask for the modified rooms to be analyzed again, e.g. after the list was refreshed.
+    pub fn request(&mut self, rooms: usize) {
+        self.generation += 1;
+        self.requested = true;
+        self.pending = rooms;
+    }
+
+    // The generation and cache for analyzing the rooms, if they were requested since this
+    // was last called.
+    pub fn take_request(&mut self) -> Option<(usize, AnalysisCache)> {
+        if !std::mem::take(&mut self.requested) {
+            return None;
+        }
+        Some((self.generation, self.cache.clone()))
+    }
+
+    // Record the analysis of a room (or None if it couldn't be loaded), unless it is from an
+    // earlier request.
+    pub fn insert(&mut self, generation: usize, path: PathBuf, analysis: Option<RoomAnalysis>) {
+        if generation != self.generation {
+            return;
+        }
+        self.pending = self.pending.saturating_sub(1);
+        if let Some(analysis) = analysis {
+            self.rooms.insert(path, analysis);
+        }
+    }
+}
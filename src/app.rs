@@ -0,0 +1,6378 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use git2::Repository;
+use hashbrown::{HashMap, HashSet};
+use iced::{
+    Element, Font, Length, Point, Rectangle, Size, Subscription, Task, Theme, keyboard,
+    widget::{
+        Scrollable, button, canvas, checkbox, column, combo_box, container, image, pick_list,
+        rich_text, row,
+        scrollable::{self, Scrollbar},
+        slider, span, text, text_editor, text_input,
+    },
+    window,
+};
+use iced_aw::SelectionList;
+use log::{error, info, warn};
+
+use crate::analysis::{AnalysisStore, RoomAnalysis, analyze_room_cached};
+use crate::asset_usage::{AssetUsage, SharedAsset};
+use crate::changes::{ChangeCategory, ChangedFile, DiffLine, categorize, file_patch};
+use crate::config::{
+    CONFIG_FILENAME, ColorPreset, Config, DEFAULT_WINDOW_TITLE, DiffColors, ExternalTool,
+    HookEvent, NumberFormat, PREFERENCES_FILENAME, Panel, Preferences, ProjectDiscovery,
+    RoomSortOrder, WindowLayout,
+};
+use crate::control;
+use crate::door_graph::{DoorGraph, EdgeStatus, build_door_graph};
+use crate::enemy_set::{EnemySetChange, compare_enemy_sets};
+use crate::export::{composite, crop, planned_png, upscale};
+use crate::guarded_write::{PlannedWrite, WriteGuard};
+use crate::hex_diff::{HEX_ROW_WIDTH, HexRow, hex_diff_rows};
+use crate::library::{list_backgrounds, render_background};
+use crate::log_capture::{self, LogRecord};
+use crate::map::{list_areas, render_area_map};
+use crate::project_settings::{SettingChange, compare_project_settings};
+use crate::render_session::RenderSession;
+use crate::report::{self, ReportArgs};
+use crate::rom::RomFileSystem;
+use crate::room::{
+    self, ColorMath, IDENTITY_PALETTE_REMAP, MalformedAsset, PaletteRemap, RenderOptions,
+    decode_color, list_rooms, list_tilesets, load_palette_entries, load_room, malformed_asset,
+    render_cre_gfx, render_tileset,
+};
+use crate::room_notes::{load_note, save_note};
+use crate::session::{self, Navigation, SESSION_FILENAME, SavedSource};
+use crate::session_log::SessionLog;
+use crate::snapshot::{self, SnapshotArgs};
+use crate::thumbnails::{THUMBNAIL_SIZE, ThumbnailCache, ThumbnailKey, downscale, downscale_by};
+use crate::tile_search::{
+    DoorTiles, SearchHit, SearchKind, SearchTarget, bts_grid, door_tiles, layer1_tile_grid,
+    search_room,
+};
+use crate::video::{VideoFormat, export_comparison_video};
+use crate::workspace::{RootFileSystem, Workspace, WorkspaceRoot, project_key};
+use crate::{
+    file_system::{
+        FileSystem, GitTreeFileSystem, HttpFileSystem, LocalFileSystem, find_dirs_containing,
+        is_not_found, list_files_with_extension,
+    },
+    hooks::{HookInput, HookOutput, HookRoomState, run_hook},
+    overlay::{OverlayRegistry, RoomContext, changed_tiles},
+    room::RoomImages,
+};
+
+// Most log records listed in the log panel (all matching records are copied)
+const LOG_PANEL_RECORDS: usize = 200;
+// Below 1x, the zoom halves at each step (for overviews of large rooms).
+pub const MIN_PIXEL_SIZE: f32 = 0.125;
+pub const MAX_PIXEL_SIZE: f32 = 8.0;
+// Width of the scrollbars around zoomable images, which is reserved next to the image so
+// that the scrollbars don't cover it.
+const SCROLLBAR_WIDTH: f32 = 10.0;
+// Width of the matte drawn around the room image
+const ROOM_MATTE: f32 = 8.0;
+// Magnification of the tile previews shown when hovering over a difference
+const HOVER_PREVIEW_SCALE: f32 = 4.0;
+// Thickness of the rulers along the room view
+const RULER_SIZE: f32 = 20.0;
+const SESSION_LOG_JSON_FILENAME: &str = ".smartdiff/review.json";
+const SESSION_LOG_MARKDOWN_FILENAME: &str = ".smartdiff/review.md";
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    reference: Option<String>,
+    /// Load the reference from a raw-file URL prefix instead of from git
+    #[arg(long)]
+    remote_url: Option<String>,
+    /// Extract the reference from a built ROM instead of from git
+    #[arg(long, conflicts_with = "remote_url")]
+    rom: Option<PathBuf>,
+    /// IPS or BPS patch to apply to the ROM given by --rom before extracting from it
+    #[arg(long, requires = "rom")]
+    patch: Option<PathBuf>,
+    /// Accept commands (e.g. "open PROJECT ROOM") on this local TCP port
+    #[arg(long)]
+    listen: Option<u16>,
+    /// Only list projects whose path matches this glob (may be repeated)
+    #[arg(long, global = true)]
+    include_projects: Vec<String>,
+    /// Don't search directories whose path matches this glob (may be repeated)
+    #[arg(long, global = true)]
+    exclude_projects: Vec<String>,
+    /// Search for projects at most this many directories deep
+    #[arg(long, global = true)]
+    max_depth: Option<usize>,
+    /// Also search directories ignored by git
+    #[arg(long, global = true)]
+    no_gitignore: bool,
+    /// Initial window size, e.g. 1920x1080
+    #[arg(long, value_parser = parse_window_size)]
+    window_size: Option<(f32, f32)>,
+    /// Start with the window maximized
+    #[arg(long)]
+    maximized: bool,
+    /// Width of the sidebar, in pixels
+    #[arg(long)]
+    sidebar_width: Option<f32>,
+    /// Initial zoom (pixel size) of the room view
+    #[arg(long)]
+    zoom: Option<f32>,
+    /// Hide a panel at startup (may be repeated)
+    #[arg(long, value_enum)]
+    hide_panel: Vec<Panel>,
+    /// Only show what would be written to files (e.g. room notes, exports, and reports)
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Run the hook scripts listed in .smartdiff.toml (they run arbitrary commands from the
+    /// repository, so they are never run otherwise)
+    #[arg(long)]
+    allow_hooks: bool,
+    /// Find projects in the roots listed in this workspace file, each with its own
+    /// repository and reference, instead of only under the current directory
+    #[arg(long)]
+    workspace: Option<PathBuf>,
+}
+
+fn parse_window_size(s: &str) -> Result<(f32, f32), String> {
+    let parse = || {
+        let (width, height) = s.split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?))
+    };
+    parse().ok_or_else(|| format!("expected WIDTHxHEIGHT, got {:?}", s))
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render every room and update or verify baseline snapshots
+    Snapshot(SnapshotArgs),
+    /// Open a room as changed by a commit, comparing against the commit's parent
+    Open(OpenArgs),
+    /// Write an illustrated report of the rooms changed by each commit in a range
+    Report(ReportArgs),
+}
+
+#[derive(clap::Args)]
+struct OpenArgs {
+    /// Commit whose parent is used as the reference
+    #[arg(long)]
+    commit: String,
+    /// Room to open
+    #[arg(long)]
+    room: String,
+    /// Project containing the room (by default, the project in which the commit changed it)
+    #[arg(long)]
+    project: Option<String>,
+}
+
+// The project to open a room in for `smartdiff open`: the only one with the room, or else
+// the one in which the commit changed it.
+fn project_for_commit_room(state: &State, open_args: &OpenArgs) -> Result<Project> {
+    if let Some(name) = &open_args.project {
+        return find_project(state, name).with_context(|| format!("Unknown project: {}", name));
+    }
+    let room_path = |p: &Project| {
+        p.0.join("Export/Rooms")
+            .join(format!("{}.xml", open_args.room))
+    };
+    let candidates: Vec<&Project> = state
+        .project_list
+        .options()
+        .iter()
+        .filter(|p| room_path(p).exists())
+        .collect();
+    if candidates.len() > 1 {
+        let repo = state.repo()?;
+        let commit = repo.revparse_single(&open_args.commit)?.peel_to_commit()?;
+        let parent_tree = commit.parent(0)?.tree()?;
+        let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&commit.tree()?), None)?;
+        let changed: Vec<PathBuf> = diff
+            .deltas()
+            .filter_map(|d| d.new_file().path().map(|p| state.config.path_key(p)))
+            .collect();
+        if let Some(project) = candidates.iter().find(|p| {
+            let path = state.config.path_key(&room_path(p));
+            changed.iter().any(|c| path.ends_with(c))
+        }) {
+            return Ok((*project).clone());
+        }
+    }
+    candidates
+        .first()
+        .map(|p| (*p).clone())
+        .with_context(|| format!("No project has a room named {}", open_args.room))
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, Hash)]
+struct Project(PathBuf);
+type Room = String;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, Hash)]
+struct RoomState(usize, String);
+
+impl Display for RoomState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.0, self.1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SourceSelection {
+    WorkingCopy,
+    GitReference(String),
+    Difference,
+}
+
+impl Display for SourceSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceSelection::WorkingCopy => write!(f, "Working copy"),
+            SourceSelection::GitReference(s) => write!(f, "{}", s),
+            SourceSelection::Difference => write!(f, "Difference"),
+        }
+    }
+}
+
+// Source of a single layer, which can differ from the selected source to show e.g. the
+// working copy's layer 1 over the reference's layer 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum LayerSource {
+    #[default]
+    Selected,
+    WorkingCopy,
+    Reference,
+    Difference,
+}
+
+impl Display for LayerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerSource::Selected => write!(f, "Selected source"),
+            LayerSource::WorkingCopy => write!(f, "Working copy"),
+            LayerSource::Reference => write!(f, "Reference"),
+            LayerSource::Difference => write!(f, "Difference"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ViewMode {
+    Room,
+    Project,
+    Tileset,
+    AreaMap,
+    Library,
+    DoorGraph,
+    AssetUsage,
+}
+
+impl Display for ViewMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ViewMode::Room => write!(f, "Room"),
+            ViewMode::Project => write!(f, "Project"),
+            ViewMode::Tileset => write!(f, "Tilesets"),
+            ViewMode::AreaMap => write!(f, "Area maps"),
+            ViewMode::Library => write!(f, "BG library"),
+            ViewMode::DoorGraph => write!(f, "Door graph"),
+            ViewMode::AssetUsage => write!(f, "Asset usage"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+struct ModifiedRoom {
+    project: Project,
+    room_name: String,
+    // Name shown for the project: its directory name, or its full relative path if
+    // another project has a directory of the same name.
+    project_label: String,
+    // Number of lines added or removed
+    change_size: usize,
+    // Modification time of the working copy file
+    modified_time: Option<SystemTime>,
+}
+
+impl ModifiedRoom {
+    fn path(&self) -> PathBuf {
+        self.project
+            .0
+            .join(format!("Export/Rooms/{}.xml", self.room_name))
+    }
+}
+
+impl Display for ModifiedRoom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.project_label, self.room_name)
+    }
+}
+
+fn project_short_name(project: &Project) -> String {
+    project
+        .0
+        .file_name()
+        .unwrap_or(project.0.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+// Labels distinguishing the given projects in lists that otherwise show only their
+// directory names.
+fn project_labels(projects: &[Project]) -> HashMap<Project, String> {
+    let mut short_name_counts: HashMap<String, usize> = HashMap::new();
+    for project in projects {
+        *short_name_counts
+            .entry(project_short_name(project))
+            .or_default() += 1;
+    }
+    projects
+        .iter()
+        .map(|project| {
+            let short_name = project_short_name(project);
+            let label = if short_name_counts[&short_name] > 1 {
+                project.to_string()
+            } else {
+                short_name
+            };
+            (project.clone(), label)
+        })
+        .collect()
+}
+
+struct State {
+    config: Config,
+    preferences: Preferences,
+    session_log: SessionLog,
+    // The current room's note from the repository, as being edited
+    room_note: text_editor::Content,
+    room_note_saved: String,
+    room_note_room: Option<(Project, String)>,
+    // In a dry run, unsaved edits to the notes of rooms other than the current one
+    unsaved_notes: HashMap<(Project, String), String>,
+    // The repository containing the projects, if any
+    repo: Option<git2::Repository>,
+    // Where projects were found, if given a workspace file
+    workspace: Option<Workspace>,
+    git_reference: String,
+    // Text searched for in the reference picker, and the names of all references in the
+    // repository, which are only listed once a search is started
+    reference_search: String,
+    reference_names: Option<Vec<String>>,
+    remote: Option<HttpFileSystem>,
+    rom: Option<Arc<RomFileSystem>>,
+    control_port: Option<u16>,
+    allow_hooks: bool,
+    project_list: combo_box::State<Project>,
+    project: Project,
+    room_list: combo_box::State<String>,
+    room: String,
+    // Room of the working copy to compare against instead of the reference (e.g. the room
+    // that the current one was copied from)
+    compare_room: Option<String>,
+    compare_room_list: combo_box::State<String>,
+    room_state_list: combo_box::State<RoomState>,
+    room_state: RoomState,
+    state_filter: StateFilter,
+    // Compare each working copy state against the reference state with the same
+    // condition, rather than the one at the same position.
+    match_states_by_condition: bool,
+    tileset_normalization: TilesetNormalization,
+    view_mode: ViewMode,
+    modified_room_list: Vec<ModifiedRoom>,
+    modified_room_idx: Option<usize>,
+    // Rooms selected (with Ctrl/Shift-click) for batch export, and the labels shown for
+    // the modified room list, which mark them
+    selected_rooms: BTreeSet<usize>,
+    modified_room_labels: Vec<String>,
+    modifiers: keyboard::Modifiers,
+    export_dir: String,
+    // Also export difference images in which unchanged pixels are transparent
+    export_alpha_diff: bool,
+    video_format: VideoFormat,
+    // Rooms remaining to be exported (the last being in progress), and the number
+    // exported so far
+    export_queue: Vec<ModifiedRoom>,
+    exported_count: usize,
+    export_status: Option<String>,
+    // Room names that exist in more than one project, with the projects containing them
+    duplicate_rooms: HashMap<String, Vec<Project>>,
+    changed_files: Vec<ChangedFile>,
+    file_diff: Option<(PathBuf, Vec<DiffLine>)>,
+    hex_diff: Option<(PathBuf, Vec<HexRow>)>,
+    tileset_list: Vec<usize>,
+    // Shared with background threads rendering thumbnails and exports
+    render_session: Arc<RenderSession>,
+    thumbnails: ThumbnailCache,
+    overview_images: Option<OverviewImages>,
+    analysis: AnalysisStore,
+    comparison: Option<ComparisonImages>,
+    area_list: Vec<usize>,
+    background_list: Vec<usize>,
+    door_graph: Option<DoorGraph>,
+    asset_usage: Option<AssetUsage>,
+    shared_asset: Option<SharedAsset>,
+    project_settings: Option<Vec<SettingChange>>,
+    show_unchanged_settings: bool,
+    show_layer_1: bool,
+    show_layer_2: bool,
+    show_fx: bool,
+    show_doors: bool,
+    // Names of the rooms of a project by area and index, for describing door targets
+    room_ids: Option<(Project, RoomIds)>,
+    highlight_transparency: bool,
+    // Sources of layers 1 and 2
+    layer_sources: [LayerSource; 2],
+    // Palette lines to draw the working copy with, to preview a reorganization of the palette
+    palette_remap: PaletteRemap,
+    // Frame of the tile animations to draw, and whether to step through the frames on a
+    // timer (only the timer started last, identified by `animation_timer`, keeps running)
+    animation_frame: usize,
+    animate: bool,
+    animation_timer: usize,
+    // Color math to simulate between layers 1 and 2 (in every version)
+    color_math: ColorMath,
+    difference_baseline: DifferenceBaseline,
+    pixel_size: f32,
+    hook_output: HookOutput,
+    // Hooks to run for the current room, and a counter which is increased whenever the room
+    // is rendered, so that output for an earlier rendering is ignored
+    hook_jobs: Vec<(Vec<String>, HookInput)>,
+    hook_generation: usize,
+    // Rooms to compare with the remote in the background, and a counter which is increased
+    // whenever the list of modified rooms is refreshed
+    remote_jobs: Vec<ModifiedRoom>,
+    remote_generation: usize,
+    layout: WindowLayout,
+    hidden_panels: HashSet<Panel>,
+    // Filters for the log panel: the least severe level shown, and text to search for
+    log_level: log::Level,
+    log_search: String,
+    source_selection: SourceSelection,
+    // Whether the flip key is held
+    flipped: bool,
+    // Region of the room selected by dragging on it
+    region: Option<Region>,
+    // Shown by the state selector, e.g. when only event states of the room differ
+    state_hint: Option<String>,
+    // 16x16 tile being searched for in layer 1, and the selected occurrence
+    tile_search: Option<u16>,
+    tile_search_text: String,
+    tile_search_idx: usize,
+    project_search_kind: SearchKind,
+    project_search_text: String,
+    project_search: Option<ProjectSearch>,
+    working_images: Option<RoomImages>,
+    other_images: Option<RoomImages>,
+    working_image_handles: Option<RoomData>,
+    other_image_handles: Option<RoomData>,
+    diff_image_handles: Option<RoomData>,
+    handle_pool: HandlePool,
+    overlays: OverlayRegistry,
+    overlay_image_handles: Vec<Option<image::Handle>>,
+    // Message about a problem refreshing the view, shown below the controls
+    status: Option<String>,
+    // Asset which could not be decoded when opening a view other than a room
+    asset_error: Option<MalformedAsset>,
+    // Whether the current project doesn't exist in the reference at all
+    project_is_new: bool,
+    // Ratio of physical to logical pixels of the window
+    scale_factor: f32,
+    // Scroll position of the room view, restored after switching rooms
+    room_scroll: scrollable::AbsoluteOffset,
+    room_viewport: iced::Size,
+    // Leave layer 2 out of the difference for states where only one version has it
+    diff_common_layers: bool,
+    // Where the previous run was, if it didn't exit cleanly, until restored or dismissed
+    restorable_session: Option<Navigation>,
+    // What was last saved of the current navigation, and when
+    saved_navigation: Option<(Navigation, Instant)>,
+    write_guard: WriteGuard,
+}
+
+impl State {
+    // Images of the current room from the given source, if they are available for the
+    // selected room state.
+    fn room_images(&self, source: &SourceSelection) -> Option<&RoomData> {
+        let overview = self
+            .overview_images
+            .as_ref()
+            .filter(|o| o.factor == overview_factor(self.pixel_size));
+        let images = match (source, overview) {
+            (SourceSelection::WorkingCopy, None) => self.working_image_handles.as_ref(),
+            (SourceSelection::GitReference(_), None) => self.other_image_handles.as_ref(),
+            (SourceSelection::Difference, None) => self.diff_image_handles.as_ref(),
+            (SourceSelection::WorkingCopy, Some(o)) => o.working.as_ref(),
+            (SourceSelection::GitReference(_), Some(o)) => o.other.as_ref(),
+            (SourceSelection::Difference, Some(o)) => o.diff.as_ref(),
+        }?;
+        let idx = self.room_state.0;
+        (idx < images.layer1.len() && idx < images.layer2.len()).then_some(images)
+    }
+
+    // The source to draw, which is flipped between the working copy and the reference while
+    // the flip key is held.
+    fn displayed_source(&self) -> SourceSelection {
+        if !self.flipped {
+            return self.source_selection.clone();
+        }
+        match self.source_selection {
+            SourceSelection::WorkingCopy => SourceSelection::GitReference(self.other_name()),
+            SourceSelection::GitReference(_) | SourceSelection::Difference => {
+                SourceSelection::WorkingCopy
+            }
+        }
+    }
+
+    // Size of the room view: that of the working copy, or of both versions together if the room
+    // was resized.
+    fn room_size(&self) -> Option<(usize, usize)> {
+        let working = self.working_image_handles.as_ref()?;
+        Some(match &self.other_image_handles {
+            Some(other) => (
+                working.width.max(other.width),
+                working.height.max(other.height),
+            ),
+            None => (working.width, working.height),
+        })
+    }
+
+    // The backdrop color of the current state in a source (black for the difference).
+    fn backdrop_color(&self, source: &SourceSelection) -> iced::Color {
+        let images = match source {
+            SourceSelection::WorkingCopy => self.working_images.as_ref(),
+            SourceSelection::GitReference(_) => self.other_images.as_ref(),
+            SourceSelection::Difference => None,
+        };
+        match images.and_then(|i| i.backdrop.get(self.room_state.0)) {
+            Some(&[r, g, b]) => iced::Color::from_rgb8(r, g, b),
+            None => iced::Color::BLACK,
+        }
+    }
+
+    // The source to draw the given layer (1 or 2) from.
+    fn layer_source(&self, layer: usize) -> SourceSelection {
+        match self.layer_sources[layer - 1] {
+            LayerSource::Selected => self.displayed_source(),
+            LayerSource::WorkingCopy => SourceSelection::WorkingCopy,
+            LayerSource::Reference => SourceSelection::GitReference(self.other_name()),
+            LayerSource::Difference => SourceSelection::Difference,
+        }
+    }
+
+    fn shows(&self, panel: Panel) -> bool {
+        !self.hidden_panels.contains(&panel)
+    }
+
+    // Logical size of `len` image pixels at the current zoom, rounded to a whole number of
+    // physical pixels so that image bounds line up with the scrollable viewport.
+    fn zoomed(&self, len: usize) -> f32 {
+        (len as f32 * self.pixel_size * self.scale_factor).round() / self.scale_factor
+    }
+
+    fn reference_source(&self, project: &Project) -> ReferenceSource {
+        match (&self.remote, &self.rom) {
+            (Some(remote), _) => ReferenceSource::Remote(remote.url_prefix.clone()),
+            (None, Some(rom)) => ReferenceSource::Rom(rom.clone()),
+            (None, None) => {
+                let reference = self.git_reference_for(project).to_string();
+                match self.workspace_root(project) {
+                    Some(root) => ReferenceSource::WorkspaceGit(root.clone(), reference),
+                    None => ReferenceSource::Git(reference),
+                }
+            }
+        }
+    }
+
+    fn workspace_root(&self, project: &Project) -> Option<&WorkspaceRoot> {
+        self.workspace.as_ref()?.root_of(&project.0)
+    }
+
+    // Where a project's notes and exports go, relative to their directories
+    fn project_key(&self, project: &Path) -> PathBuf {
+        project_key(self.workspace.as_ref(), project)
+    }
+
+    // The git reference to compare a project against, which may be overridden per project
+    // in the config.
+    fn git_reference_for(&self, project: &Project) -> &str {
+        if let Some(reference) = self
+            .workspace_root(project)
+            .and_then(|r| r.reference.as_deref())
+        {
+            return reference;
+        }
+        let name = project.0.file_name().and_then(|x| x.to_str());
+        self.config
+            .project_references
+            .iter()
+            .find(|(k, _)| **k == project.to_string() || Some(k.as_str()) == name)
+            .map_or(&self.git_reference, |(_, v)| v)
+    }
+
+    // The project containing a path (relative to the repository root), if any.
+    fn project_of_path(&self, path: &Path) -> Option<&Project> {
+        let path = self.config.path_key(path);
+        self.project_list
+            .options()
+            .iter()
+            .filter(|p| path.starts_with(self.config.path_key(&p.0)))
+            .max_by_key(|p| p.0.components().count())
+    }
+
+    fn git_reference_for_path(&self, path: &Path) -> &str {
+        match self.project_of_path(path) {
+            Some(project) => self.git_reference_for(project),
+            None => &self.git_reference,
+        }
+    }
+
+    // Name of what the working copy room is compared against: the reference, or another
+    // room of the working copy.
+    fn other_name(&self) -> String {
+        match &self.compare_room {
+            Some(room) => format!("{} (working copy)", room),
+            None => self.reference_name(),
+        }
+    }
+
+    // Whether there is nothing to compare the working copy against, for lack of a git
+    // repository.
+    fn single_source(&self) -> bool {
+        self.repo.is_none()
+            && self.workspace.is_none()
+            && self.remote.is_none()
+            && self.rom.is_none()
+    }
+
+    fn repo(&self) -> Result<&Repository> {
+        match &self.repo {
+            Some(repo) => Ok(repo),
+            None => bail!("No git repository found"),
+        }
+    }
+
+    fn reference_name(&self) -> String {
+        match (&self.remote, &self.rom) {
+            (Some(remote), _) => remote.url_prefix.clone(),
+            (None, Some(rom)) => rom.path.display().to_string(),
+            (None, None) => self.git_reference_for(&self.project).to_string(),
+        }
+    }
+}
+
+// Asset (other than a room) that can be viewed as a single image per source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComparisonKind {
+    Tileset(usize),
+    CreGraphics,
+    AreaMap(usize),
+    Background(usize),
+}
+
+impl Display for ComparisonKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComparisonKind::Tileset(idx) => write!(f, "Tileset {:02X}", idx),
+            ComparisonKind::CreGraphics => write!(f, "CRE graphics"),
+            ComparisonKind::AreaMap(area) => write!(f, "Area {:02X} map", area),
+            ComparisonKind::Background(idx) => write!(f, "Background {:02X}", idx),
+        }
+    }
+}
+
+// An image with its size, for drawing it at its own size (e.g. when the versions differ).
+struct SizedHandle {
+    handle: image::Handle,
+    width: usize,
+    height: usize,
+}
+
+struct ComparisonImages {
+    kind: ComparisonKind,
+    project: Project,
+    working: SizedHandle,
+    reference: Option<SizedHandle>,
+    diff: Option<SizedHandle>,
+    // Raw palette entries of a tileset, in the working copy and the reference
+    palette: Option<(Vec<u16>, Option<Vec<u16>>)>,
+}
+
+#[derive(Clone)]
+struct RoomData {
+    width: usize,
+    height: usize,
+    layer1: Vec<image::Handle>,
+    layer2: Vec<image::Handle>,
+    layer2_front: Vec<image::Handle>,
+    fx: Vec<image::Handle>,
+}
+
+impl RoomData {
+    // The handles of every layer of every state.
+    fn handles(&self) -> impl Iterator<Item = &image::Handle> {
+        [&self.layer1, &self.layer2, &self.layer2_front, &self.fx]
+            .into_iter()
+            .flatten()
+    }
+}
+
+// Room images downscaled for zooming out below 1x, rather than leaving the GPU to minify
+// the full-size images (which is slow for large rooms, and aliases). The dimensions are
+// those of the full-size images, which they are drawn in place of.
+struct OverviewImages {
+    factor: usize,
+    working: Option<RoomData>,
+    other: Option<RoomData>,
+    diff: Option<RoomData>,
+}
+
+// How much to downscale room images by at a pixel size: the largest power of two that
+// doesn't make them smaller than they are drawn.
+fn overview_factor(pixel_size: f32) -> usize {
+    let mut factor = 1;
+    while (factor * 2) as f32 * pixel_size <= 1.0 {
+        factor *= 2;
+    }
+    factor
+}
+
+// Layers 1, 2, the part of layer 2 in front of layer 1, and layer 3 (FX) of each state
+type Layers = (
+    Vec<room::Image>,
+    Vec<room::Image>,
+    Vec<room::Image>,
+    Vec<room::Image>,
+);
+
+fn refresh_overview_images(state: &mut State, diff: Option<Layers>) {
+    let factor = overview_factor(state.pixel_size);
+    let Some(working) = state.working_images.as_ref().filter(|_| factor > 1) else {
+        state.overview_images = None;
+        return;
+    };
+    let shrink = |layer1: &[room::Image],
+                  layer2: &[room::Image],
+                  layer2_front: &[room::Image],
+                  fx: &[room::Image]| {
+        let handles = |images: &[room::Image]| {
+            images
+                .iter()
+                .map(|image| {
+                    let image = downscale_by(image, factor);
+                    image::Handle::from_rgba(image.width as u32, image.height as u32, image.pixels)
+                })
+                .collect()
+        };
+        // The full-size dimensions, which differ between the versions of a resized room
+        let (width, height) = layer1.first().map_or((0, 0), |x| (x.width, x.height));
+        RoomData {
+            width,
+            height,
+            layer1: handles(layer1),
+            layer2: handles(layer2),
+            layer2_front: handles(layer2_front),
+            fx: handles(fx),
+        }
+    };
+    state.overview_images = Some(OverviewImages {
+        factor,
+        working: Some(shrink(
+            &working.layer1,
+            &working.layer2,
+            &working.layer2_front,
+            &working.fx,
+        )),
+        other: state
+            .other_images
+            .as_ref()
+            .map(|other| shrink(&other.layer1, &other.layer2, &other.layer2_front, &other.fx)),
+        diff: diff
+            .map(|(layer1, layer2, layer2_front, fx)| shrink(&layer1, &layer2, &layer2_front, &fx)),
+    });
+}
+
+// The difference images for the overview, if zoomed out far enough to need them.
+fn overview_diff(state: &State) -> Option<Layers> {
+    (overview_factor(state.pixel_size) > 1)
+        .then(|| diff_layers(state))
+        .flatten()
+}
+
+impl Display for Project {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Event(iced::Event),
+    SelectProject(Project),
+    SelectRoom(Room),
+    SearchReferences(String),
+    SelectReference(String),
+    SelectRoomState(RoomState),
+    SelectRegion(Option<Region>),
+    SearchTile(String),
+    PickTile(usize, usize),
+    StepTileSearch(isize),
+    SelectProjectSearchKind(SearchKind),
+    EditProjectSearch(String),
+    SearchProject,
+    ProjectSearchProgress(u64, String, Result<Vec<SearchHit>, String>),
+    OpenSearchHit(usize),
+    SaveRegionImage,
+    FilterRoomStates(StateFilter),
+    MatchStatesByCondition(bool),
+    NormalizeTilesets(TilesetNormalization),
+    CompareRooms(bool),
+    SelectCompareRoom(String),
+    SelectSource(SourceSelection),
+    ShowLayer1(bool),
+    ShowLayer2(bool),
+    ShowFX(bool),
+    ShowDoors(bool),
+    SelectLayerSource(usize, LayerSource),
+    RemapPaletteLine(usize, usize),
+    ResetPaletteRemap,
+    SetAnimationFrame(usize),
+    ToggleAnimation(bool),
+    AnimationTick(usize),
+    SelectColorMath(ColorMath),
+    HighlightTransparency(bool),
+    AdjustDifferenceBaseline(f32),
+    PerChannelBaseline(bool),
+    AdjustChannelBaseline(usize, f32),
+    AdjustDifferenceGamma(f32),
+    SelectModifiedRoom(usize),
+    ToggleOverlay(usize, bool),
+    ControlCommand(control::Command),
+    RestoreSession,
+    DismissSession,
+    RunTool(ExternalTool),
+    SelectViewMode(ViewMode),
+    OpenChangedFile(usize),
+    // Retry refreshing the room after the working copy could not be read (attempt number)
+    RetryRefresh(usize),
+    CloseFileDiff,
+    CopyDiagnostics,
+    ScaleFactorChanged(f32),
+    RoomScrolled(scrollable::AbsoluteOffset, iced::Size),
+    DiffCommonLayers(bool),
+    SortModifiedRooms(RoomSortOrder),
+    SelectColorPreset(ColorPreset),
+    SelectNumberFormat(NumberFormat),
+    SetReviewNotes(String),
+    // Mark the screens covered by the selected region as needing attention
+    BookmarkScreens,
+    SetBookmarkNote(usize, String),
+    RemoveBookmark(usize),
+    SaveSessionLog,
+    EditRoomNote(text_editor::Action),
+    SaveRoomNote,
+    SetExportDir(String),
+    ExportAlphaDiff(bool),
+    SetVideoFormat(VideoFormat),
+    ExportSelected,
+    ExportVideo,
+    VideoExported(Result<PathBuf, String>),
+    RoomExported(Result<(), String>),
+    ThumbnailRendered(ThumbnailKey, Result<room::Image, String>),
+    RoomAnalyzed(usize, PathBuf, Option<RoomAnalysis>),
+    HooksRan(usize, HookOutput),
+    // Rooms that differ from the remote, and errors from fetching them
+    RemoteCompared(usize, Vec<ModifiedRoom>, Vec<String>),
+    ShowUnchangedSettings(bool),
+    ShowPanel(Panel, bool),
+    SetLogLevel(log::Level),
+    SetLogSearch(String),
+    CopyLog,
+    OpenComparison(Option<ComparisonKind>),
+    OpenRoom(Room, usize),
+    SelectSharedAsset(SharedAsset),
+}
+
+// Project discovery settings from the config, with command-line options applied.
+fn project_discovery(args: &Args, config: &Config) -> ProjectDiscovery {
+    let mut discovery = config.projects.clone();
+    discovery
+        .include
+        .extend(args.include_projects.iter().cloned());
+    discovery
+        .exclude
+        .extend(args.exclude_projects.iter().cloned());
+    if args.max_depth.is_some() {
+        discovery.max_depth = args.max_depth;
+    }
+    if args.no_gitignore {
+        discovery.respect_gitignore = false;
+    }
+    discovery
+}
+
+fn window_layout(args: &Args, config: &Config) -> WindowLayout {
+    let mut layout = config.window.clone();
+    if let Some((width, height)) = args.window_size {
+        layout.width = width;
+        layout.height = height;
+    }
+    if args.maximized {
+        layout.maximized = true;
+    }
+    if let Some(width) = args.sidebar_width {
+        layout.sidebar_width = width;
+    }
+    if let Some(zoom) = args.zoom {
+        layout.zoom = zoom;
+    }
+    layout.hidden_panels.extend(args.hide_panel.iter().copied());
+    layout
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<glob::Pattern>> {
+    patterns
+        .iter()
+        .map(|p| glob::Pattern::new(p).with_context(|| format!("Invalid project pattern {:?}", p)))
+        .collect()
+}
+
+fn find_projects(discovery: &ProjectDiscovery, repo: Option<&Repository>) -> Result<Vec<Project>> {
+    let ignored = |path: &Path| repo.is_some_and(|r| r.is_path_ignored(path).unwrap_or(false));
+    let projects = find_projects_in(discovery, Path::new("."), &ignored)?;
+    if projects.is_empty() {
+        bail!("No SMART projects found");
+    }
+    Ok(projects)
+}
+
+// Projects in each root of a workspace, with ignored files according to the root's repository.
+fn find_workspace_projects(
+    discovery: &ProjectDiscovery,
+    workspace: &Workspace,
+) -> Result<Vec<Project>> {
+    let mut projects: Vec<Project> = vec![];
+    for root in &workspace.roots {
+        let repo = root.open_repo().ok();
+        let prefix = repo.as_ref().and_then(|r| root.prefix(r).ok());
+        let ignored = |path: &Path| {
+            let (Some(repo), Some(prefix)) = (&repo, &prefix) else {
+                return false;
+            };
+            root.to_repo_path(prefix, path)
+                .is_some_and(|p| repo.is_path_ignored(&p).unwrap_or(false))
+        };
+        projects.extend(find_projects_in(discovery, &root.path, &ignored)?);
+    }
+    if projects.is_empty() {
+        bail!("No SMART projects found in the workspace");
+    }
+    projects.sort();
+    projects.dedup();
+    Ok(projects)
+}
+
+fn find_projects_in(
+    discovery: &ProjectDiscovery,
+    dir: &Path,
+    ignored: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<Project>> {
+    let include = compile_patterns(&discovery.include)?;
+    let exclude = compile_patterns(&discovery.exclude)?;
+    // Keep paths relative to the repository root (e.g. "Hack" rather than "./Hack").
+    let relative = |path: &Path| path.strip_prefix(".").unwrap_or(path).to_path_buf();
+    let skip_dir = |path: &Path| {
+        let path = relative(path);
+        exclude.iter().any(|p| p.matches_path(&path))
+            || (discovery.respect_gitignore && ignored(&path))
+    };
+    let mut projects: Vec<Project> = vec![];
+    for path in find_dirs_containing(dir, "project.xml", discovery.max_depth, &skip_dir)? {
+        let path = relative(&path);
+        if include.is_empty() || include.iter().any(|p| p.matches_path(&path)) {
+            projects.push(Project(path));
+        }
+    }
+    projects.sort();
+    Ok(projects)
+}
+
+// Reference to compare against when none is given: the current branch's upstream if it has
+// one (so that committed but unmerged changes are shown), otherwise HEAD.
+fn default_reference(repo: &Repository, config: &Config) -> String {
+    let upstream = || -> Option<String> {
+        let head = repo.head().ok()?;
+        if !head.is_branch() {
+            return None;
+        }
+        let branch = git2::Branch::wrap(head).upstream().ok()?;
+        branch.name().ok()?.map(str::to_string)
+    };
+    if let Some(upstream) = config.prefer_upstream.then(upstream).flatten() {
+        info!(
+            "Git reference not supplied, defaulting to upstream {}.",
+            upstream
+        );
+        return upstream;
+    }
+    info!("Git reference not supplied, defaulting to HEAD.");
+    "HEAD".to_string()
+}
+
+fn get_initial_state(args: Args, overlays: OverlayRegistry) -> Result<State> {
+    // Without a repository, the working copy can still be viewed and exported.
+    let repo = Repository::open(".").ok();
+    if repo.is_none() && args.workspace.is_none() && args.remote_url.is_none() && args.rom.is_none()
+    {
+        warn!("No git repository found; showing the working copy only.");
+    }
+
+    let config = Config::load(std::path::Path::new(CONFIG_FILENAME))?;
+    let workspace = args.workspace.as_deref().map(Workspace::load).transpose()?;
+    let projects = match &workspace {
+        Some(workspace) => find_workspace_projects(&project_discovery(&args, &config), workspace)?,
+        None => find_projects(&project_discovery(&args, &config), repo.as_ref())?,
+    };
+    let layout = window_layout(&args, &config);
+
+    let git_reference = match (args.reference, &repo) {
+        (Some(r), _) => r,
+        (None, Some(repo)) => default_reference(repo, &config),
+        (None, None) => "HEAD".to_string(),
+    };
+
+    let mut state = State {
+        config,
+        preferences: Preferences::load(Path::new(PREFERENCES_FILENAME)),
+        session_log: SessionLog::new(
+            repo.as_ref()
+                .and_then(|r| r.config().and_then(|c| c.get_string("user.name")).ok()),
+            String::new(),
+        ),
+        room_note: text_editor::Content::new(),
+        room_note_saved: String::new(),
+        room_note_room: None,
+        unsaved_notes: HashMap::new(),
+        repo,
+        workspace,
+        git_reference,
+        reference_search: String::new(),
+        reference_names: None,
+        remote: args.remote_url.as_deref().map(HttpFileSystem::new),
+        rom: match (&args.rom, &args.patch) {
+            (Some(rom), Some(patch)) => Some(Arc::new(RomFileSystem::open_patched(rom, patch)?)),
+            (Some(rom), None) => Some(Arc::new(RomFileSystem::open(rom)?)),
+            (None, _) => None,
+        },
+        control_port: args.listen,
+        allow_hooks: args.allow_hooks,
+        write_guard: WriteGuard {
+            dry_run: args.dry_run,
+        },
+        project: projects[0].clone(),
+        project_list: combo_box::State::new(projects),
+        room_list: combo_box::State::new(vec![]),
+        compare_room: None,
+        compare_room_list: combo_box::State::new(vec![]),
+        room: String::new(),
+        room_state_list: combo_box::State::new(vec![]),
+        room_state: RoomState(0, String::new()),
+        state_filter: StateFilter::All,
+        match_states_by_condition: false,
+        tileset_normalization: TilesetNormalization::Off,
+        view_mode: ViewMode::Room,
+        modified_room_list: vec![],
+        modified_room_idx: None,
+        selected_rooms: BTreeSet::new(),
+        modified_room_labels: vec![],
+        modifiers: keyboard::Modifiers::default(),
+        export_dir: "smartdiff-export".to_string(),
+        export_alpha_diff: false,
+        video_format: VideoFormat::default(),
+        export_queue: vec![],
+        exported_count: 0,
+        export_status: None,
+        duplicate_rooms: HashMap::new(),
+        changed_files: vec![],
+        file_diff: None,
+        hex_diff: None,
+        tileset_list: vec![],
+        render_session: Arc::new(RenderSession::default()),
+        thumbnails: ThumbnailCache::default(),
+        overview_images: None,
+        analysis: AnalysisStore::default(),
+        comparison: None,
+        area_list: vec![],
+        background_list: vec![],
+        door_graph: None,
+        asset_usage: None,
+        shared_asset: None,
+        project_settings: None,
+        show_unchanged_settings: false,
+        show_layer_1: true,
+        show_layer_2: true,
+        show_fx: false,
+        show_doors: false,
+        room_ids: None,
+        highlight_transparency: false,
+        layer_sources: [LayerSource::Selected; 2],
+        palette_remap: IDENTITY_PALETTE_REMAP,
+        animation_frame: 0,
+        animate: false,
+        animation_timer: 0,
+        color_math: ColorMath::Off,
+        difference_baseline: DifferenceBaseline::default(),
+        source_selection: SourceSelection::WorkingCopy,
+        flipped: false,
+        region: None,
+        state_hint: None,
+        tile_search: None,
+        tile_search_text: String::new(),
+        tile_search_idx: 0,
+        project_search_kind: SearchKind::Tile,
+        project_search_text: String::new(),
+        project_search: None,
+        pixel_size: layout.zoom.clamp(MIN_PIXEL_SIZE, MAX_PIXEL_SIZE),
+        hidden_panels: layout.hidden_panels.iter().copied().collect(),
+        log_level: log::Level::Info,
+        log_search: String::new(),
+        hook_output: HookOutput::default(),
+        hook_jobs: vec![],
+        hook_generation: 0,
+        remote_jobs: vec![],
+        remote_generation: 0,
+        layout,
+        working_images: None,
+        other_images: None,
+        working_image_handles: None,
+        other_image_handles: None,
+        diff_image_handles: None,
+        handle_pool: HandlePool::default(),
+        overlays,
+        overlay_image_handles: vec![],
+        status: None,
+        asset_error: None,
+        project_is_new: false,
+        scale_factor: 1.0,
+        room_scroll: scrollable::AbsoluteOffset::default(),
+        room_viewport: iced::Size::ZERO,
+        diff_common_layers: false,
+        restorable_session: session::load_unclean(Path::new(SESSION_FILENAME)),
+        saved_navigation: None,
+    };
+    state.session_log.reference = state.reference_name();
+    state
+        .difference_baseline
+        .set_colors(state.preferences.color_preset.colors());
+    refresh_modified_room_list(&mut state)?;
+    select_first_modified_room(&mut state);
+    refresh_room_list(&mut state)?;
+    refresh_room_images(&mut state)?;
+
+    Ok(state)
+}
+
+// Start on the first modified room (reviewing the changes being what smartdiff is usually
+// opened for), selected in the list so that the arrow keys go on from there.
+fn select_first_modified_room(state: &mut State) {
+    if let Some(first) = state.modified_room_list.first() {
+        state.project = first.project.clone();
+        state.room = first.room_name.clone();
+        state.modified_room_idx = Some(0);
+        state.selected_rooms = BTreeSet::from([0]);
+        refresh_modified_room_labels(state);
+    }
+}
+
+fn refresh_modified_room_list(state: &mut State) -> Result<()> {
+    // List modified rooms across all projects
+    let labels = project_labels(state.project_list.options());
+    let mut room_map: HashMap<PathBuf, ModifiedRoom> = HashMap::new();
+    let mut room_projects: HashMap<String, Vec<Project>> = HashMap::new();
+    for project in state.project_list.options() {
+        for room in list_files_with_extension(&project.0.join("Export/Rooms"), "xml")? {
+            let room_name = room.file_stem().unwrap().to_string_lossy().to_string();
+            room_projects
+                .entry(room_name.clone())
+                .or_default()
+                .push(project.clone());
+            room_map.insert(
+                state.config.path_key(&room),
+                ModifiedRoom {
+                    project: project.clone(),
+                    room_name,
+                    project_label: labels[project].clone(),
+                    change_size: 0,
+                    modified_time: std::fs::metadata(&room).and_then(|m| m.modified()).ok(),
+                },
+            );
+        }
+    }
+    room_projects.retain(|_, projects| projects.len() > 1);
+    if !room_projects.is_empty() && state.duplicate_rooms.is_empty() {
+        let mut names: Vec<&String> = room_projects.keys().collect();
+        names.sort();
+        warn!(
+            "{} room name(s) exist in more than one project: {}",
+            names.len(),
+            names
+                .iter()
+                .map(|x| x.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    state.duplicate_rooms = room_projects;
+
+    let mut modified_room_list: Vec<ModifiedRoom> = vec![];
+    let mut changed_files: Vec<ChangedFile> = vec![];
+    state.remote_generation += 1;
+    state.remote_jobs.clear();
+    if state.remote.is_some() {
+        // The rooms are fetched in the background, and listed once they have been compared.
+        state.remote_jobs = room_map.into_values().collect();
+        info!(
+            "Comparing {} rooms with the remote",
+            state.remote_jobs.len()
+        );
+    } else if let Some(workspace) = &state.workspace {
+        for root in &workspace.roots {
+            let repo = root.open_repo()?;
+            let prefix = root.prefix(&repo)?;
+            let projects: Vec<&Project> = state
+                .project_list
+                .options()
+                .iter()
+                .filter(|p| state.workspace_root(p).is_some_and(|r| r.path == root.path))
+                .collect();
+            // Paths in nested roots belong to those.
+            let local_path = |path: &Path| {
+                root.from_repo_path(&prefix, path)
+                    .filter(|p| workspace.root_of(p).is_some_and(|r| r.path == root.path))
+            };
+            diff_working_tree(
+                state,
+                &repo,
+                &projects,
+                &local_path,
+                &room_map,
+                &mut modified_room_list,
+                &mut changed_files,
+            )?;
+        }
+    } else if let Some(repo) = &state.repo {
+        let projects: Vec<&Project> = state.project_list.options().iter().collect();
+        diff_working_tree(
+            state,
+            repo,
+            &projects,
+            &|path| Some(path.to_path_buf()),
+            &room_map,
+            &mut modified_room_list,
+            &mut changed_files,
+        )?;
+    }
+    set_modified_rooms(state, modified_room_list, changed_files);
+    Ok(())
+}
+
+fn set_modified_rooms(
+    state: &mut State,
+    modified_room_list: Vec<ModifiedRoom>,
+    mut changed_files: Vec<ChangedFile>,
+) {
+    changed_files.sort_by(|a, b| (a.category, &a.path).cmp(&(b.category, &b.path)));
+    state.analysis.request(modified_room_list.len());
+    state.modified_room_list = modified_room_list;
+    state.changed_files = changed_files;
+    state.selected_rooms.clear();
+    state.modified_room_idx = None;
+    sort_modified_rooms(state);
+}
+
+// Compare the queued rooms with the remote in the background. There is no tree to diff
+// against, so the room files are compared directly.
+fn request_remote_comparison(state: &mut State) -> Task<Message> {
+    let Some(remote) = &state.remote else {
+        return Task::none();
+    };
+    if state.remote_jobs.is_empty() {
+        return Task::none();
+    }
+    let rooms = std::mem::take(&mut state.remote_jobs);
+    let url_prefix = remote.url_prefix.clone();
+    let generation = state.remote_generation;
+    in_background(move || {
+        let remote = HttpFileSystem::new(&url_prefix);
+        let mut modified_rooms: Vec<ModifiedRoom> = vec![];
+        let mut errors: Vec<String> = vec![];
+        for room in rooms {
+            let path = room.path();
+            let result = LocalFileSystem {}.load(&path).and_then(|working_bytes| {
+                match remote.load(&path) {
+                    Ok(remote_bytes) => Ok((working_bytes, Some(remote_bytes))),
+                    // A room missing from the remote is new.
+                    Err(e) if is_not_found(&e) => Ok((working_bytes, None)),
+                    Err(e) => Err(e),
+                }
+            });
+            match result {
+                Ok((working_bytes, remote_bytes)) => {
+                    if remote_bytes.as_ref() != Some(&working_bytes) {
+                        let change_size =
+                            count_changed_lines(&working_bytes, &remote_bytes.unwrap_or_default());
+                        modified_rooms.push(ModifiedRoom {
+                            change_size,
+                            ..room
+                        });
+                    }
+                }
+                Err(e) => errors.push(format!("{}: {:#}", path.display(), e)),
+            }
+        }
+        (modified_rooms, errors)
+    })
+    .map(move |result| {
+        let (modified_rooms, errors) =
+            result.unwrap_or_else(|| (vec![], vec!["Comparison was interrupted".to_string()]));
+        Message::RemoteCompared(generation, modified_rooms, errors)
+    })
+}
+
+// Add the changes between a repository's working tree and the references of the given projects
+// in it, with its paths converted to ours by `local_path` (None for paths to leave out).
+fn diff_working_tree(
+    state: &State,
+    repo: &Repository,
+    projects: &[&Project],
+    local_path: &dyn Fn(&Path) -> Option<PathBuf>,
+    room_map: &HashMap<PathBuf, ModifiedRoom>,
+    modified_room_list: &mut Vec<ModifiedRoom>,
+    changed_files: &mut Vec<ChangedFile>,
+) -> Result<()> {
+    // Projects may be compared against different references, so diff against each, and
+    // keep the changes to files belonging to projects compared against that reference.
+    let mut references: Vec<&str> = projects
+        .iter()
+        .map(|p| state.git_reference_for(p))
+        .collect();
+    references.push(&state.git_reference);
+    references.sort();
+    references.dedup();
+    for reference_name in references {
+        let reference = repo.revparse_single(reference_name)?;
+        let tree = reference.peel_to_tree()?;
+        let diff = repo.diff_tree_to_workdir_with_index(Some(&tree), None)?;
+        for (i, d) in diff.deltas().enumerate() {
+            let Some(path) = d
+                .new_file()
+                .path()
+                .or(d.old_file().path())
+                .and_then(local_path)
+            else {
+                continue;
+            };
+            if state.git_reference_for_path(&path) != reference_name {
+                continue;
+            }
+            if let Some(room) = d
+                .new_file()
+                .path()
+                .and_then(local_path)
+                .and_then(|path| room_map.get(&state.config.path_key(&path)))
+            {
+                let mut room = room.clone();
+                if let Some(patch) = git2::Patch::from_diff(&diff, i)? {
+                    let (_, additions, deletions) = patch.line_stats()?;
+                    room.change_size = additions + deletions;
+                }
+                modified_room_list.push(room);
+            }
+            changed_files.push(ChangedFile {
+                category: categorize(&path),
+                path,
+                status: d.status(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Start analyzing the modified rooms in the background, if they need it, on several threads
+// which report each room as it is done.
+fn request_room_analysis(state: &mut State) -> Task<Message> {
+    let Some((generation, cache)) = state.analysis.take_request() else {
+        return Task::none();
+    };
+    let jobs: Vec<(PathBuf, ReferenceSource)> = state
+        .modified_room_list
+        .iter()
+        .map(|room| (room.path(), state.reference_source(&room.project)))
+        .collect();
+    let (sender, receiver) = iced::futures::channel::mpsc::unbounded();
+    std::thread::spawn(move || {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let jobs = std::sync::Mutex::new(jobs.into_iter());
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    loop {
+                        let Some((path, reference)) = jobs.lock().unwrap().next() else {
+                            break;
+                        };
+                        let working = LocalFileSystem {}.load(&path);
+                        let reference = reference.with_fs(|fs| fs.load(&path)).ok();
+                        let analysis = match working {
+                            Ok(working) => {
+                                Some(analyze_room_cached(&cache, &working, reference.as_deref()))
+                            }
+                            Err(e) => {
+                                info!("Unable to analyze {}: {:#}", path.display(), e);
+                                None
+                            }
+                        };
+                        if sender.unbounded_send((path, analysis)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+    });
+    Task::run(receiver, move |(path, analysis)| {
+        Message::RoomAnalyzed(generation, path, analysis)
+    })
+}
+
+// Number of lines that differ between two versions of a file, by position (without
+// aligning insertions), for when there is no git diff.
+fn count_changed_lines(a: &[u8], b: &[u8]) -> usize {
+    let a: Vec<&[u8]> = a.split(|&c| c == b'\n').collect();
+    let b: Vec<&[u8]> = b.split(|&c| c == b'\n').collect();
+    let differing = a.iter().zip(b.iter()).filter(|(x, y)| x != y).count();
+    differing + a.len().abs_diff(b.len())
+}
+
+// Sort the modified room list in the chosen order, keeping the same rooms selected.
+fn sort_modified_rooms(state: &mut State) {
+    let current = state
+        .modified_room_idx
+        .map(|i| state.modified_room_list[i].clone());
+    let selected: Vec<ModifiedRoom> = state
+        .selected_rooms
+        .iter()
+        .map(|&i| state.modified_room_list[i].clone())
+        .collect();
+    let list = &mut state.modified_room_list;
+    match state.preferences.room_sort {
+        RoomSortOrder::ProjectName => list.sort(),
+        RoomSortOrder::Path => list.sort_by_key(|r| r.path()),
+        RoomSortOrder::ChangeSize => {
+            list.sort_by(|a, b| b.change_size.cmp(&a.change_size).then(a.cmp(b)))
+        }
+        RoomSortOrder::LastModified => {
+            list.sort_by(|a, b| b.modified_time.cmp(&a.modified_time).then(a.cmp(b)))
+        }
+    }
+    let position = |room: &ModifiedRoom| list.iter().position(|r| r == room);
+    state.modified_room_idx = current.as_ref().and_then(position);
+    state.selected_rooms = selected.iter().filter_map(position).collect();
+    refresh_modified_room_labels(state);
+}
+
+fn refresh_modified_room_labels(state: &mut State) {
+    state.modified_room_labels = state
+        .modified_room_list
+        .iter()
+        .enumerate()
+        .map(|(i, room)| {
+            let badges = state
+                .analysis
+                .get(&room.path())
+                .map_or(" [...]".to_string(), |a| a.badges());
+            if state.selected_rooms.len() > 1 && state.selected_rooms.contains(&i) {
+                format!("* {}{}", room, badges)
+            } else {
+                format!("{}{}", room, badges)
+            }
+        })
+        .collect();
+}
+
+fn refresh_room_list(state: &mut State) -> Result<()> {
+    // List rooms in current project:
+    // A project without rooms is left with no room selected, so that its other assets can
+    // still be viewed.
+    let room_list = list_rooms(&state.project.0)?;
+    if !room_list.contains(&state.room) {
+        state.room = room_list.first().cloned().unwrap_or_default();
+    }
+    if state
+        .compare_room
+        .as_ref()
+        .is_some_and(|r| !room_list.contains(r))
+    {
+        state.compare_room = None;
+    }
+    state.compare_room_list = combo_box::State::new(room_list.clone());
+    state.room_list = combo_box::State::new(room_list);
+    let project_xml = state.project.0.join("project.xml");
+    // Without a reference at all, everything is new.
+    state.project_is_new = state.single_source()
+        || match with_reference_fs(state, |fs| fs.load(&project_xml)) {
+            Ok(_) => false,
+            Err(e) if is_not_found(&e) => true,
+            Err(e) => {
+                return Err(e.context(format!(
+                    "Unable to check for {} in {}",
+                    project_xml.display(),
+                    state.other_name()
+                )));
+            }
+        };
+    Ok(())
+}
+
+// Image handles for the current room, keyed by a hash of their content. Re-rendering the room
+// (e.g. when it is saved, or when the difference baseline changes) then reuses the handles of
+// unchanged states, so their textures don't have to be uploaded again.
+#[derive(Default)]
+struct HandlePool {
+    room: Option<(Project, String)>,
+    handles: HashMap<u64, image::Handle>,
+}
+
+impl HandlePool {
+    fn set_room(&mut self, project: &Project, room: &str) {
+        if self
+            .room
+            .as_ref()
+            .is_none_or(|(p, r)| p != project || r != room)
+        {
+            self.room = Some((project.clone(), room.to_string()));
+            self.handles.clear();
+        }
+    }
+
+    fn get(&mut self, image: room::Image) -> image::Handle {
+        let mut hasher = std::hash::DefaultHasher::new();
+        image.hash(&mut hasher);
+        self.handles
+            .entry(hasher.finish())
+            .or_insert_with(|| {
+                image::Handle::from_rgba(image.width as u32, image.height as u32, image.pixels)
+            })
+            .clone()
+    }
+
+    // Drop handles which are no longer displayed.
+    fn retain(&mut self, in_use: &[&RoomData]) {
+        let ids: HashSet<_> = in_use
+            .iter()
+            .flat_map(|x| x.handles())
+            .map(|x| x.id())
+            .collect();
+        self.handles.retain(|_, h| ids.contains(&h.id()));
+    }
+}
+
+fn convert_images(pool: &mut HandlePool, images: Vec<room::Image>) -> Vec<image::Handle> {
+    images.into_iter().map(|x| pool.get(x)).collect()
+}
+
+fn prune_handle_pool(state: &mut State) {
+    let in_use: Vec<&RoomData> = [
+        &state.working_image_handles,
+        &state.other_image_handles,
+        &state.diff_image_handles,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    state.handle_pool.retain(&in_use);
+}
+
+// How unchanged pixels are dimmed in the difference view, so that changed (white) pixels stand out.
+#[derive(Debug, Clone, Copy)]
+struct DifferenceBaseline {
+    brightness: f32,
+    // Separate multipliers for red, green, and blue, applied on top of the brightness
+    channels: Option<[f32; 3]>,
+    gamma: f32,
+    // Color of the changed pixels
+    highlight: [u8; 3],
+    // Colors marking the area only in the working copy, or only in the reference, of a room
+    // that was resized
+    added: [u8; 3],
+    removed: [u8; 3],
+}
+
+impl Default for DifferenceBaseline {
+    fn default() -> Self {
+        Self {
+            brightness: 0.3,
+            channels: None,
+            gamma: 1.0,
+            highlight: [255, 255, 255],
+            added: [0, 200, 0],
+            removed: [220, 0, 0],
+        }
+    }
+}
+
+impl DifferenceBaseline {
+    fn set_colors(&mut self, colors: DiffColors) {
+        self.highlight = colors.changed;
+        self.added = colors.added;
+        self.removed = colors.removed;
+    }
+
+    fn dim(&self, pixel: [u8; 3]) -> [u8; 3] {
+        let channels = self.channels.unwrap_or([1.0; 3]);
+        std::array::from_fn(|i| {
+            let x = (pixel[i] as f32 / 255.0).powf(self.gamma);
+            (x * self.brightness * channels[i] * 255.0).clamp(0.0, 255.0) as u8
+        })
+    }
+}
+
+// Whether a pixel is within an image.
+fn contains(img: &room::Image, x: usize, y: usize) -> bool {
+    x < img.width && y < img.height
+}
+
+// Diagonal stripes marking the part of a resized room which is only in one of the versions
+fn resized_stripe(x: usize, y: usize) -> bool {
+    ((x + y) / 4).is_multiple_of(2)
+}
+
+// The difference between two images, as large as both of them together if their sizes differ
+// (e.g. the room was resized), with the area only in the first or second striped in the
+// colors for additions or removals.
+fn diff_image(img1: &room::Image, img2: &room::Image, baseline: DifferenceBaseline) -> room::Image {
+    let mut img = room::Image::new(img1.width.max(img2.width), img1.height.max(img2.height));
+    for y in 0..img.height {
+        for x in 0..img.width {
+            let (only, color) = match (contains(img1, x, y), contains(img2, x, y)) {
+                (true, true) => {
+                    let p1 = img1.get_pixel(x, y);
+                    if p1 != img2.get_pixel(x, y) {
+                        img.set_pixel(x, y, baseline.highlight);
+                    } else if !img1.get_transparent(x, y) {
+                        img.set_pixel(x, y, baseline.dim(p1));
+                    }
+                    continue;
+                }
+                (true, false) => (img1, baseline.added),
+                (false, true) => (img2, baseline.removed),
+                (false, false) => continue,
+            };
+            if resized_stripe(x, y) {
+                img.set_pixel(x, y, color);
+            } else if !only.get_transparent(x, y) {
+                img.set_pixel(x, y, baseline.dim(only.get_pixel(x, y)));
+            }
+        }
+    }
+    img
+}
+
+// Only the changed pixels, with everything else transparent, for compositing over
+// screenshots or maps in an image editor. The area of a resized room which is only in one
+// version is filled with the color for additions or removals.
+fn diff_mask_image(
+    img1: &room::Image,
+    img2: &room::Image,
+    baseline: DifferenceBaseline,
+) -> room::Image {
+    let mut img = room::Image::new(img1.width.max(img2.width), img1.height.max(img2.height));
+    for y in 0..img.height {
+        for x in 0..img.width {
+            match (contains(img1, x, y), contains(img2, x, y)) {
+                (true, true) => {
+                    if img1.get_pixel(x, y) != img2.get_pixel(x, y) {
+                        img.set_pixel(x, y, baseline.highlight);
+                    }
+                }
+                (true, false) => img.set_pixel(x, y, baseline.added),
+                (false, true) => img.set_pixel(x, y, baseline.removed),
+                (false, false) => {}
+            }
+        }
+    }
+    img
+}
+
+fn diff_image_list(
+    img1: &[room::Image],
+    img2: &[room::Image],
+    baseline: DifferenceBaseline,
+) -> Vec<room::Image> {
+    img1.iter()
+        .zip(img2.iter())
+        .map(|(x, y)| diff_image(x, y, baseline))
+        .collect()
+}
+
+fn has_layer2(images: &RoomImages, state_idx: usize) -> bool {
+    images
+        .room_xml
+        .states
+        .state
+        .get(state_idx)
+        .is_some_and(|x| x.has_layer2())
+}
+
+fn refresh_baseline(state: &mut State) -> Result<()> {
+    invalidate_room_thumbnails(state);
+    refresh_diff_images(state)?;
+    if let Some(kind) = state.comparison.as_ref().map(|x| x.kind) {
+        refresh_comparison_images(state, kind)?;
+    }
+    Ok(())
+}
+
+// Difference images of each state's layers, if there is a reference.
+fn diff_layers(state: &State) -> Option<Layers> {
+    let working_images = state.working_images.as_ref()?;
+    let other_images = state.other_images.as_ref()?;
+    let layer1 = diff_image_list(
+        &working_images.layer1,
+        &other_images.layer1,
+        state.difference_baseline,
+    );
+    let diff_layer2 = |working: &[room::Image], other: &[room::Image]| {
+        working
+            .iter()
+            .zip(other.iter())
+            .enumerate()
+            .map(|(i, (x, y))| {
+                if state.diff_common_layers
+                    && has_layer2(working_images, i) != has_layer2(other_images, i)
+                {
+                    room::Image::new(x.width.max(y.width), x.height.max(y.height))
+                } else {
+                    diff_image(x, y, state.difference_baseline)
+                }
+            })
+            .collect()
+    };
+    let layer2 = diff_layer2(&working_images.layer2, &other_images.layer2);
+    let layer2_front = diff_layer2(&working_images.layer2_front, &other_images.layer2_front);
+    let fx = diff_image_list(
+        &working_images.fx,
+        &other_images.fx,
+        state.difference_baseline,
+    );
+    Some((layer1, layer2, layer2_front, fx))
+}
+
+fn refresh_diff_images(state: &mut State) -> Result<()> {
+    let diff = diff_layers(state);
+    let overview_diff = (overview_factor(state.pixel_size) > 1)
+        .then(|| diff.clone())
+        .flatten();
+    if let (Some(working_images), Some((layer1, layer2, layer2_front, fx))) =
+        (&state.working_images, diff)
+    {
+        // The differences cover both versions of a resized room.
+        let (width, height) = layer1.first().map_or(
+            (
+                working_images.layer1[0].width,
+                working_images.layer1[0].height,
+            ),
+            |x| (x.width, x.height),
+        );
+        state.diff_image_handles = Some(RoomData {
+            width,
+            height,
+            layer1: convert_images(&mut state.handle_pool, layer1),
+            layer2: convert_images(&mut state.handle_pool, layer2),
+            layer2_front: convert_images(&mut state.handle_pool, layer2_front),
+            fx: convert_images(&mut state.handle_pool, fx),
+        });
+        prune_handle_pool(state);
+    }
+    refresh_overview_images(state, overview_diff);
+    Ok(())
+}
+
+// Where the reference comes from, in a form that can be sent to a background thread.
+#[derive(Clone)]
+enum ReferenceSource {
+    Git(String),
+    // A reference in the repository of a workspace root
+    WorkspaceGit(WorkspaceRoot, String),
+    Remote(String),
+    Rom(Arc<RomFileSystem>),
+}
+
+impl ReferenceSource {
+    fn with_fs<R>(&self, f: impl FnOnce(&dyn FileSystem) -> Result<R>) -> Result<R> {
+        match self {
+            ReferenceSource::Git(reference) => {
+                let repo = Repository::open(".")?;
+                let tree = repo.revparse_single(reference)?.peel_to_tree()?;
+                f(&GitTreeFileSystem { repo: &repo, tree })
+            }
+            ReferenceSource::WorkspaceGit(root, reference) => {
+                let repo = root.open_repo()?;
+                let tree = repo.revparse_single(reference)?.peel_to_tree()?;
+                f(&RootFileSystem {
+                    root,
+                    prefix: root.prefix(&repo)?,
+                    fs: &GitTreeFileSystem { repo: &repo, tree },
+                })
+            }
+            ReferenceSource::Remote(url) => f(&HttpFileSystem::new(url)),
+            ReferenceSource::Rom(rom) => f(rom.as_ref()),
+        }
+    }
+}
+
+// Run a function with access to the file system for the reference (git tree, remote, or
+// ROM).
+fn with_reference_fs<R>(state: &State, f: impl FnOnce(&dyn FileSystem) -> Result<R>) -> Result<R> {
+    match (&state.remote, &state.rom) {
+        (Some(remote), _) => f(remote),
+        (None, Some(rom)) => f(rom.as_ref()),
+        (None, None) if state.workspace.is_some() => {
+            state.reference_source(&state.project).with_fs(f)
+        }
+        (None, None) => {
+            let repo = state.repo()?;
+            let reference = repo.revparse_single(state.git_reference_for(&state.project))?;
+            let tree = reference.peel_to_tree()?;
+            let other_fs = GitTreeFileSystem { repo, tree };
+            f(&other_fs)
+        }
+    }
+}
+
+// Short names of all branches and tags, for searching in the reference picker.
+fn list_reference_names(repo: &Repository) -> Result<Vec<String>> {
+    let mut names: Vec<String> = repo
+        .references()?
+        .flatten()
+        .filter(|r| r.is_branch() || r.is_remote() || r.is_tag())
+        .filter_map(|r| r.shorthand().map(str::to_string))
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+// Picker for the git reference to compare against. Searching matches references by
+// substring; before a search, the most recently used references are offered.
+fn reference_picker(state: &State) -> Option<Element<'_, Message>> {
+    if state.remote.is_some() || state.rom.is_some() || state.repo.is_none() {
+        return None;
+    }
+    const MAX_MATCHES: usize = 20;
+    let search = state.reference_search.to_lowercase();
+    let candidates: Vec<&String> = if search.is_empty() {
+        state
+            .preferences
+            .recent_references
+            .iter()
+            .filter(|r| **r != state.git_reference)
+            .collect()
+    } else {
+        state
+            .reference_names
+            .iter()
+            .flatten()
+            .filter(|r| r.to_lowercase().contains(&search))
+            .take(MAX_MATCHES)
+            .collect()
+    };
+    let mut content = column![
+        text_input("Search references...", &state.reference_search)
+            .on_input(Message::SearchReferences)
+            .on_submit(Message::SelectReference(
+                state.reference_search.trim().to_string()
+            ))
+            .size(12),
+    ]
+    .spacing(2);
+    if search.is_empty() && !candidates.is_empty() {
+        content = content.push(text("Recent:").size(12));
+    }
+    for reference in candidates {
+        content = content.push(
+            button(text(reference).size(12))
+                .on_press(Message::SelectReference(reference.clone()))
+                .style(button::text)
+                .padding(0),
+        );
+    }
+    Some(content.into())
+}
+
+// What the reference is rendered from, for separating level changes from graphics changes
+// when both the room and its tilesets changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TilesetNormalization {
+    #[default]
+    Off,
+    // The reference's level data with the working copy's tilesets
+    WorkingTilesets,
+    // The working copy's level data with the reference's tilesets
+    WorkingRoom,
+}
+
+impl Display for TilesetNormalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TilesetNormalization::Off => write!(f, "Reference as is"),
+            TilesetNormalization::WorkingTilesets => write!(f, "Same tilesets (level changes)"),
+            TilesetNormalization::WorkingRoom => write!(f, "Same level data (graphics changes)"),
+        }
+    }
+}
+
+// Whether both the current room's XML and one of the tilesets it uses changed.
+fn room_and_tilesets_changed(state: &State) -> bool {
+    let Some(working) = &state.working_images else {
+        return false;
+    };
+    let changed: HashSet<PathBuf> = state
+        .changed_files
+        .iter()
+        .map(|f| state.config.path_key(&f.path))
+        .collect();
+    let is_changed = |path: &PathBuf| changed.contains(&state.config.path_key(path));
+    let (room, tilesets) = working.dependencies.split_first().unzip();
+    room.is_some_and(is_changed) && tilesets.is_some_and(|t| t.iter().any(is_changed))
+}
+
+fn tileset_normalization_controls(state: &State) -> Option<Element<'_, Message>> {
+    if state.compare_room.is_some()
+        || (state.tileset_normalization == TilesetNormalization::Off
+            && !room_and_tilesets_changed(state))
+    {
+        return None;
+    }
+    Some(
+        pick_list(
+            [
+                TilesetNormalization::Off,
+                TilesetNormalization::WorkingTilesets,
+                TilesetNormalization::WorkingRoom,
+            ],
+            Some(state.tileset_normalization),
+            Message::NormalizeTilesets,
+        )
+        .into(),
+    )
+}
+
+// Kinds of room state, by their condition, for narrowing down the room state list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum StateFilter {
+    #[default]
+    All,
+    Default,
+    Event,
+    Boss,
+    // Item and other conditions (e.g. morph ball, power bombs, door)
+    Other,
+}
+
+impl Display for StateFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateFilter::All => write!(f, "All states"),
+            StateFilter::Default => write!(f, "Default states"),
+            StateFilter::Event => write!(f, "Event states"),
+            StateFilter::Boss => write!(f, "Boss states"),
+            StateFilter::Other => write!(f, "Other states"),
+        }
+    }
+}
+
+impl StateFilter {
+    fn matches(&self, condition: &str) -> bool {
+        let kind = match condition.trim().to_ascii_uppercase().as_str() {
+            "DEFAULT" | "E5E6" => StateFilter::Default,
+            "E612" => StateFilter::Event,
+            "E629" | "E5FF" => StateFilter::Boss,
+            _ => StateFilter::Other,
+        };
+        *self == StateFilter::All || *self == kind
+    }
+}
+
+fn filter_room_states(
+    images: &RoomImages,
+    room_states: Vec<RoomState>,
+    filter: StateFilter,
+) -> Vec<RoomState> {
+    room_states
+        .into_iter()
+        .filter(|x| {
+            images
+                .room_xml
+                .states
+                .state
+                .get(x.0)
+                .is_some_and(|s| filter.matches(&s.condition))
+        })
+        .collect()
+}
+
+// Reorder the reference's states to line up with the working copy's states of the same
+// condition and argument, for when states were added, removed, or reordered. A working
+// copy state without a counterpart gets a blank reference image.
+fn align_reference_states(working: &RoomImages, mut other: RoomImages) -> RoomImages {
+    let key = |s: &crate::smart_xml::RoomState| (s.condition.trim().to_ascii_uppercase(), s.arg);
+    let mut room_state_names = vec![];
+    let mut layer1 = vec![];
+    let mut layer2 = vec![];
+    let mut layer2_front = vec![];
+    let mut fx = vec![];
+    let mut palette_lines = vec![];
+    let mut backdrop = vec![];
+    let mut states = vec![];
+    for (i, working_state) in working.room_xml.states.state.iter().enumerate() {
+        let j = other
+            .room_xml
+            .states
+            .state
+            .iter()
+            .position(|s| key(s) == key(working_state));
+        match j {
+            Some(j) => {
+                room_state_names.push(other.room_state_names[j].clone());
+                layer1.push(other.layer1[j].clone());
+                layer2.push(other.layer2[j].clone());
+                layer2_front.push(other.layer2_front[j].clone());
+                fx.push(other.fx[j].clone());
+                palette_lines.push(other.palette_lines[j].clone());
+                backdrop.push(other.backdrop[j]);
+                states.push(other.room_xml.states.state[j].clone());
+            }
+            None => {
+                let size = other.layer1.first().unwrap_or(&working.layer1[i]);
+                let (width, height) = (size.width, size.height);
+                other.warnings.push(format!(
+                    "State {} ({}) has no matching state",
+                    i, working.room_state_names[i]
+                ));
+                room_state_names.push(working.room_state_names[i].clone());
+                layer1.push(room::Image::new(width, height));
+                layer2.push(room::Image::new(width, height));
+                layer2_front.push(room::Image::new(width, height));
+                fx.push(room::Image::new(width, height));
+                palette_lines.push(vec![0; (width / 16) * (height / 16)]);
+                backdrop.push([0, 0, 0]);
+                states.push(working_state.clone());
+            }
+        }
+    }
+    other.room_state_names = room_state_names;
+    other.layer1 = layer1;
+    other.layer2 = layer2;
+    other.layer2_front = layer2_front;
+    other.fx = fx;
+    other.palette_lines = palette_lines;
+    other.backdrop = backdrop;
+    other.room_xml.states.state = states;
+    other
+}
+
+fn clear_room_images(state: &mut State) {
+    state.room_state = RoomState(0, String::new());
+    state.room_state_list = combo_box::State::new(vec![]);
+    state.working_images = None;
+    state.other_images = None;
+    state.working_image_handles = None;
+    state.other_image_handles = None;
+    state.diff_image_handles = None;
+    state.overview_images = None;
+    state.overlay_image_handles.clear();
+    state.handle_pool = HandlePool::default();
+    state.hook_output = HookOutput::default();
+    state.hook_jobs.clear();
+    state.hook_generation += 1;
+    state.region = None;
+    state.state_hint = None;
+}
+
+fn refresh_room_images(state: &mut State) -> Result<()> {
+    if state.room.is_empty() {
+        clear_room_images(state);
+        state.status = None;
+        return Ok(());
+    }
+    refresh_room_ids(state);
+    let working_fs = LocalFileSystem {};
+    let session = state.render_session.clone();
+    let working_options = RenderOptions {
+        palette_remap: state.palette_remap,
+        animation_frame: state.animation_frame,
+        color_math: state.color_math,
+    };
+    // Other versions are drawn at the same animation frame and with the same color math, but
+    // with their own palettes.
+    let other_options = RenderOptions {
+        palette_remap: IDENTITY_PALETTE_REMAP,
+        ..working_options
+    };
+    let working_images = session
+        .render_with_sources(
+            &state.project.0,
+            &state.room,
+            &working_fs,
+            &working_fs,
+            &working_options,
+        )
+        .context(WorkingCopyUnreadable)?;
+    let room_states: Vec<RoomState> = working_images
+        .room_state_names
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(i, x)| RoomState(i, x))
+        .collect();
+    // Stay on the equivalent state (same condition and argument) of the new room, if any.
+    let previous_state = state
+        .working_images
+        .as_ref()
+        .and_then(|w| w.room_xml.states.state.get(state.room_state.0))
+        .map(|s| (s.condition.clone(), s.arg));
+    let state_idx = working_images
+        .room_xml
+        .states
+        .state
+        .iter()
+        .position(|s| Some((s.condition.clone(), s.arg)) == previous_state)
+        .unwrap_or(0);
+    if room_states.is_empty() {
+        clear_room_images(state);
+        state.status = Some(format!("Room {} has no states", state.room));
+        return Ok(());
+    }
+    let opening_room = state.handle_pool.room != Some((state.project.clone(), state.room.clone()));
+    state.room_state = room_states[state_idx].clone();
+    state.room_state_list = combo_box::State::new(filter_room_states(
+        &working_images,
+        room_states.clone(),
+        state.state_filter,
+    ));
+    let width = working_images.layer1[0].width;
+    let height = working_images.layer1[0].height;
+
+    // The room may not exist (or may fail to render) in the reference, in which case
+    // only the working copy is available.
+    let other_images = if state.project_is_new && state.compare_room.is_none() {
+        None
+    } else {
+        let other = match &state.compare_room {
+            Some(room) => session.render_with_sources(
+                &state.project.0,
+                room,
+                &working_fs,
+                &working_fs,
+                &other_options,
+            ),
+            None => with_reference_fs(state, |fs| {
+                let (project, room) = (&state.project.0, &state.room);
+                match state.tileset_normalization {
+                    TilesetNormalization::Off => {
+                        session.render_with_sources(project, room, fs, fs, &other_options)
+                    }
+                    TilesetNormalization::WorkingTilesets => {
+                        session.render_with_sources(project, room, fs, &working_fs, &other_options)
+                    }
+                    TilesetNormalization::WorkingRoom => {
+                        session.render_with_sources(project, room, &working_fs, fs, &other_options)
+                    }
+                }
+            }),
+        };
+        match other {
+            Ok(images) if state.match_states_by_condition => {
+                Some(align_reference_states(&working_images, images))
+            }
+            Ok(images) => Some(images),
+            Err(e) => {
+                info!(
+                    "Room {} not available in {}: {:?}",
+                    state.room,
+                    state.other_name(),
+                    e
+                );
+                None
+            }
+        }
+    };
+
+    // When opening a room, go to a state that differs, rather than one that looks unchanged.
+    if let (true, Some(other)) = (opening_room, &other_images) {
+        let differing = session.diff(&working_images, other).differing_states;
+        if let (false, Some(&i)) = (differing.contains(&state_idx), differing.first()) {
+            state.room_state = room_states[i].clone();
+        }
+        let states = &working_images.room_xml.states.state;
+        state.state_hint = (!differing.is_empty()
+            && differing
+                .iter()
+                .all(|&i| !StateFilter::Default.matches(&states[i].condition)))
+        .then(|| {
+            let names: Vec<&str> = differing
+                .iter()
+                .map(|&i| room_states[i].1.as_str())
+                .collect();
+            format!("Only non-default states differ: {}", names.join(", "))
+        });
+    }
+
+    // The room may have changed since its thumbnails were rendered.
+    let (project, room) = (state.project.0.clone(), state.room.clone());
+    state.thumbnails.invalidate(|k| {
+        matches!(k, ThumbnailKey::RoomState { project: p, room: r, .. } if *p == project && *r == room)
+    });
+    // Keep the selected region while the same room is refreshed.
+    if opening_room {
+        state.region = None;
+    }
+    state.working_images = Some(working_images.clone());
+    state.other_images = other_images.clone();
+    state.handle_pool.set_room(&state.project, &state.room);
+    state.working_image_handles = Some(RoomData {
+        width,
+        height,
+        layer1: convert_images(&mut state.handle_pool, working_images.layer1),
+        layer2: convert_images(&mut state.handle_pool, working_images.layer2),
+        layer2_front: convert_images(&mut state.handle_pool, working_images.layer2_front),
+        fx: convert_images(&mut state.handle_pool, working_images.fx),
+    });
+    state.other_image_handles = other_images.map(|other_images| RoomData {
+        width: other_images.layer1[0].width,
+        height: other_images.layer1[0].height,
+        layer1: convert_images(&mut state.handle_pool, other_images.layer1),
+        layer2: convert_images(&mut state.handle_pool, other_images.layer2),
+        layer2_front: convert_images(&mut state.handle_pool, other_images.layer2_front),
+        fx: convert_images(&mut state.handle_pool, other_images.fx),
+    });
+    state.diff_image_handles = None;
+    refresh_diff_images(state)?;
+    prune_handle_pool(state);
+    queue_hooks(state);
+    refresh_overlay_images(state);
+    state.status = None;
+    state.session_log.visit(
+        &state.project.to_string(),
+        &state.room,
+        &state.source_selection.to_string(),
+    );
+    load_room_note(state)?;
+    Ok(())
+}
+
+// Load the note for the current room, unless it is already loaded (so that re-rendering
+// the room doesn't discard unsaved edits). Unsaved edits to the previous room's note are
+// saved first, or in a dry run, kept until its room is opened again.
+fn load_room_note(state: &mut State) -> Result<()> {
+    let room = (state.project.clone(), state.room.clone());
+    if state.room_note_room.as_ref() == Some(&room) {
+        return Ok(());
+    }
+    if let Some(previous_room) = state.room_note_room.take() {
+        let note = state.room_note.text();
+        if note != state.room_note_saved {
+            save_note(
+                &state.write_guard,
+                &state.project_key(&previous_room.0.0),
+                &previous_room.1,
+                &note,
+            )?;
+            if state.write_guard.dry_run {
+                state.unsaved_notes.insert(previous_room, note);
+            }
+        }
+    }
+    // The saved text is compared with the editor's, so it goes through an editor too.
+    let saved = text_editor::Content::with_text(&load_note(
+        &state.project_key(&state.project.0),
+        &state.room,
+    )?);
+    state.room_note_saved = saved.text();
+    state.room_note = match state.unsaved_notes.remove(&room) {
+        Some(note) => text_editor::Content::with_text(&note),
+        None => saved,
+    };
+    state.room_note_room = Some(room);
+    Ok(())
+}
+
+fn hook_input(state: &State, event: HookEvent) -> Option<HookInput> {
+    let working = state.working_images.as_ref()?;
+    let reference = match event {
+        HookEvent::RoomRendered => None,
+        HookEvent::DiffComputed => Some(state.other_images.as_ref()?),
+    };
+    let states = working
+        .room_state_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| HookRoomState {
+            index: i,
+            name: name.clone(),
+            width: working.layer1[i].width,
+            height: working.layer1[i].height,
+            changed_tiles: reference.map(|reference| {
+                changed_tiles(working, reference, i)
+                    .into_iter()
+                    .map(|(x, y)| [x, y])
+                    .collect()
+            }),
+        })
+        .collect();
+    Some(HookInput {
+        event,
+        project: state.project.to_string(),
+        room: state.room.clone(),
+        reference: state.other_name(),
+        states,
+    })
+}
+
+// Prepare the configured hook scripts to run for the newly rendered room (by
+// `request_hooks`), discarding the output for the previous rendering.
+fn queue_hooks(state: &mut State) {
+    state.hook_generation += 1;
+    state.hook_output = HookOutput::default();
+    state.hook_jobs.clear();
+    if state.config.hooks.is_empty() {
+        return;
+    }
+    if !state.allow_hooks {
+        state.hook_output.warnings.push(format!(
+            "{} hooks in .smartdiff.toml were not run; start with --allow-hooks to run them",
+            state.config.hooks.len()
+        ));
+        return;
+    }
+    for hook in &state.config.hooks {
+        let Some(input) = hook_input(state, hook.event) else {
+            continue;
+        };
+        let args: Vec<String> = hook
+            .command
+            .iter()
+            .map(|arg| fill_placeholders(state, arg))
+            .collect();
+        state.hook_jobs.push((args, input));
+    }
+}
+
+// Run the queued hook scripts in the background.
+fn request_hooks(state: &mut State) -> Task<Message> {
+    if state.hook_jobs.is_empty() {
+        return Task::none();
+    }
+    let jobs = std::mem::take(&mut state.hook_jobs);
+    let generation = state.hook_generation;
+    in_background(move || {
+        let mut output = HookOutput::default();
+        for (args, input) in &jobs {
+            match run_hook(args, input) {
+                Ok(x) => output.extend(x),
+                Err(e) => output.warnings.push(format!("{:#}", e)),
+            }
+        }
+        output
+    })
+    .map(move |output| {
+        Message::HooksRan(
+            generation,
+            output.unwrap_or_else(|| HookOutput {
+                warnings: vec!["Hooks were interrupted".to_string()],
+                marks: vec![],
+            }),
+        )
+    })
+}
+
+// Reload the per-project asset lists needed by the current view mode.
+fn refresh_project_assets(state: &mut State) -> Result<()> {
+    if state
+        .comparison
+        .as_ref()
+        .is_some_and(|x| x.project != state.project)
+    {
+        state.comparison = None;
+    }
+    state.tileset_list.clear();
+    state.area_list.clear();
+    state.background_list.clear();
+    state.door_graph = None;
+    state.asset_usage = None;
+    state.project_settings = None;
+    match state.view_mode {
+        ViewMode::Tileset => {
+            state.tileset_list = list_tilesets(&state.project.0)?;
+            // Tilesets may have changed since their thumbnails were rendered.
+            let project = state.project.0.clone();
+            state.thumbnails.invalidate(
+                |k| matches!(k, ThumbnailKey::Tileset { project: p, .. } if *p == project),
+            );
+        }
+        ViewMode::AreaMap => state.area_list = list_areas(&state.project.0)?,
+        ViewMode::Library => state.background_list = list_backgrounds(&state.project.0)?,
+        ViewMode::DoorGraph => {
+            let rooms = list_rooms(&state.project.0)?;
+            let graph = with_reference_fs(state, |fs| {
+                Ok(build_door_graph(
+                    &state.project.0,
+                    &rooms,
+                    &LocalFileSystem {},
+                    fs,
+                ))
+            })?;
+            state.door_graph = Some(graph);
+        }
+        ViewMode::AssetUsage => {
+            let rooms = list_rooms(&state.project.0)?;
+            state.asset_usage = Some(AssetUsage::scan(
+                &state.project.0,
+                &rooms,
+                &LocalFileSystem {},
+            ));
+        }
+        ViewMode::Project => {
+            let settings = with_reference_fs(state, |fs| {
+                compare_project_settings(&state.project.0, &LocalFileSystem {}, fs)
+            })?;
+            state.project_settings = Some(settings);
+        }
+        ViewMode::Room => {}
+    }
+    Ok(())
+}
+
+// What thumbnails are rendered against, which is captured for the background thread.
+struct ThumbnailSettings {
+    render_session: Arc<RenderSession>,
+    reference: ReferenceSource,
+    compare_room: Option<String>,
+    match_states_by_condition: bool,
+    baseline: DifferenceBaseline,
+}
+
+// Room thumbnails depend on what rooms are compared against, and how.
+fn invalidate_room_thumbnails(state: &mut State) {
+    state
+        .thumbnails
+        .invalidate(|k| matches!(k, ThumbnailKey::RoomState { .. }));
+}
+
+// Thumbnails needed by the current view.
+fn wanted_thumbnails(state: &State) -> Vec<ThumbnailKey> {
+    match state.view_mode {
+        ViewMode::Tileset if state.comparison.is_none() => state
+            .tileset_list
+            .iter()
+            .map(|&idx| ThumbnailKey::Tileset {
+                project: state.project.0.clone(),
+                idx,
+            })
+            .collect(),
+        ViewMode::Room if state.shows(Panel::StateThumbnails) => {
+            let states = state.room_state_list.options();
+            if states.len() < 2 {
+                return vec![];
+            }
+            states
+                .iter()
+                .map(|s| ThumbnailKey::RoomState {
+                    project: state.project.0.clone(),
+                    room: state.room.clone(),
+                    state_idx: s.0,
+                })
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+// Start rendering the thumbnails needed by the current view which aren't cached yet, on a
+// background thread which reports each one as it is done.
+fn request_thumbnails(state: &mut State) -> Task<Message> {
+    let keys = state.thumbnails.request(wanted_thumbnails(state));
+    if keys.is_empty() {
+        return Task::none();
+    }
+    let settings = ThumbnailSettings {
+        render_session: state.render_session.clone(),
+        reference: state.reference_source(&state.project),
+        compare_room: state.compare_room.clone(),
+        match_states_by_condition: state.match_states_by_condition,
+        baseline: state.difference_baseline,
+    };
+    let (sender, receiver) = iced::futures::channel::mpsc::unbounded();
+    std::thread::spawn(move || {
+        // Room states usually come together, so the last room rendered is kept.
+        let mut last_room: Option<((PathBuf, String), RoomThumbnailSources)> = None;
+        for key in keys {
+            let result =
+                render_thumbnail(&key, &settings, &mut last_room).map_err(|e| format!("{:#}", e));
+            if sender.unbounded_send((key, result)).is_err() {
+                break;
+            }
+        }
+    });
+    Task::run(receiver, |(key, result)| {
+        Message::ThumbnailRendered(key, result)
+    })
+}
+
+type RoomThumbnailSources = (RoomImages, Option<RoomImages>);
+
+fn render_thumbnail(
+    key: &ThumbnailKey,
+    settings: &ThumbnailSettings,
+    last_room: &mut Option<((PathBuf, String), RoomThumbnailSources)>,
+) -> Result<room::Image> {
+    let image = match key {
+        ThumbnailKey::Tileset { project, idx } => {
+            render_tileset(project, *idx, &LocalFileSystem {})?
+        }
+        ThumbnailKey::RoomState {
+            project,
+            room,
+            state_idx,
+        } => {
+            let room_key = (project.clone(), room.clone());
+            if last_room.as_ref().is_none_or(|(k, _)| *k != room_key) {
+                let session = &settings.render_session;
+                let working = session.render(project, room, &LocalFileSystem {})?;
+                let other = match &settings.compare_room {
+                    Some(other) => session.render(project, other, &LocalFileSystem {}),
+                    None => settings
+                        .reference
+                        .with_fs(|fs| session.render(project, room, fs)),
+                }
+                .ok()
+                .map(|other| {
+                    if settings.match_states_by_condition {
+                        align_reference_states(&working, other)
+                    } else {
+                        other
+                    }
+                });
+                *last_room = Some((room_key, (working, other)));
+            }
+            let (working, other) = &last_room.as_ref().unwrap().1;
+            let Some(working_image) = working.composite_state(*state_idx) else {
+                bail!("Room {} has no state {}", room, state_idx);
+            };
+            let other_image = other
+                .as_ref()
+                .and_then(|other| other.composite_state(*state_idx));
+            match other_image {
+                Some(other_image) => diff_image(&working_image, &other_image, settings.baseline),
+                None => working_image,
+            }
+        }
+    };
+    Ok(downscale(&image, THUMBNAIL_SIZE))
+}
+
+fn render_comparison(
+    kind: ComparisonKind,
+    project: &Project,
+    fs: &dyn FileSystem,
+) -> Result<room::Image> {
+    match kind {
+        ComparisonKind::Tileset(idx) => render_tileset(&project.0, idx, fs),
+        ComparisonKind::CreGraphics => render_cre_gfx(&project.0, fs),
+        ComparisonKind::AreaMap(area) => render_area_map(&project.0, area, fs),
+        ComparisonKind::Background(idx) => render_background(&project.0, idx, fs),
+    }
+}
+
+fn refresh_comparison_images(state: &mut State, kind: ComparisonKind) -> Result<()> {
+    let working_image = render_comparison(kind, &state.project, &LocalFileSystem {})?;
+    let other_image =
+        match with_reference_fs(state, |fs| render_comparison(kind, &state.project, fs)) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                info!("{} not available in reference: {:?}", kind, e);
+                None
+            }
+        };
+    let diff = other_image
+        .as_ref()
+        .map(|x| diff_image(&working_image, x, state.difference_baseline));
+    let to_handle = |x: room::Image| SizedHandle {
+        width: x.width,
+        height: x.height,
+        handle: image::Handle::from_rgba(x.width as u32, x.height as u32, x.pixels),
+    };
+    let palette = match kind {
+        ComparisonKind::Tileset(idx) => {
+            load_palette_entries(&state.project.0, idx, &LocalFileSystem {})
+                .ok()
+                .map(|working| {
+                    let reference = with_reference_fs(state, |fs| {
+                        load_palette_entries(&state.project.0, idx, fs)
+                    });
+                    (working, reference.ok())
+                })
+        }
+        _ => None,
+    };
+    state.comparison = Some(ComparisonImages {
+        kind,
+        project: state.project.clone(),
+        working: to_handle(working_image),
+        reference: other_image.map(to_handle),
+        diff: diff.map(to_handle),
+        palette,
+    });
+    Ok(())
+}
+
+// Images of the enabled overlays (None for the others), in the order of the registry.
+fn render_overlays(state: &State) -> Vec<Option<room::Image>> {
+    let Some(working) = &state.working_images else {
+        return vec![];
+    };
+    let ctx = RoomContext {
+        state_idx: state.room_state.0,
+        working,
+        reference: state.other_images.as_ref(),
+        hook_marks: &state.hook_output.marks,
+        colors: state.preferences.color_preset.colors(),
+        pixel_size: state.pixel_size,
+        number_format: state.preferences.number_format,
+    };
+    state
+        .overlays
+        .iter()
+        .map(|overlay| overlay.enabled().then(|| overlay.render(&ctx)))
+        .collect()
+}
+
+fn refresh_overlay_images(state: &mut State) {
+    state.overlay_image_handles = render_overlays(state)
+        .into_iter()
+        .map(|image| {
+            image.map(|image| {
+                image::Handle::from_rgba(image.width as u32, image.height as u32, image.pixels)
+            })
+        })
+        .collect();
+}
+
+// Switch to a room of a project given by name (e.g. from the control socket), leaving the
+// current room as it was if the project or room doesn't exist.
+fn open_room(state: &mut State, project: &str, room: &str) -> Result<Task<Message>> {
+    let Some(project) = find_project(state, project) else {
+        bail!("Unknown project: {}", project);
+    };
+    if !list_rooms(&project.0)?.iter().any(|r| r == room) {
+        bail!("Unknown room: {}", room);
+    }
+    state.project = project;
+    state.room = room.to_string();
+    refresh_project_assets(state)?;
+    refresh_room_list(state)?;
+    state.modified_room_idx = None;
+    refresh_room_images(state)?;
+    Ok(restore_room_scroll(state))
+}
+
+// Look up a project by its path or by its directory name.
+fn find_project(state: &State, name: &str) -> Option<Project> {
+    let projects = state.project_list.options();
+    if let Some(project) = projects.iter().find(|p| p.to_string() == name) {
+        return Some(project.clone());
+    }
+    let matches: Vec<&Project> = projects
+        .iter()
+        .filter(|p| p.0.file_name().and_then(|x| x.to_str()) == Some(name))
+        .collect();
+    if matches.len() > 1 {
+        warn!(
+            "Project name {} is ambiguous ({}); using {}",
+            name,
+            matches
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            matches[0]
+        );
+    }
+    matches.first().map(|p| (*p).clone())
+}
+
+// Substitute the placeholders {project}, {room}, {room_path}, and {ref} for the current room.
+fn fill_placeholders(state: &State, template: &str) -> String {
+    let room_path = state
+        .project
+        .0
+        .join(format!("Export/Rooms/{}.xml", state.room));
+    template
+        .replace("{project}", &state.project.to_string())
+        .replace("{room_path}", &room_path.display().to_string())
+        .replace("{room}", &state.room)
+        .replace("{ref}", &state.reference_name())
+}
+
+fn run_tool(state: &State, tool: &ExternalTool) -> Result<()> {
+    let args: Vec<String> = tool
+        .command
+        .iter()
+        .map(|arg| fill_placeholders(state, arg))
+        .collect();
+    let Some((program, program_args)) = args.split_first() else {
+        bail!("Tool '{}' has an empty command", tool.name);
+    };
+    info!("Running tool '{}': {:?}", tool.name, args);
+    std::process::Command::new(program)
+        .args(program_args)
+        .spawn()
+        .with_context(|| format!("Unable to run tool '{}'", tool.name))?;
+    Ok(())
+}
+
+fn try_update(state: &mut State, message: Message) -> Result<Task<Message>> {
+    match message {
+        Message::Event(e) => match e {
+            iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                modified_key: keyboard::Key::Character(c),
+                ..
+            }) => match c.as_str() {
+                "1" => {
+                    state.show_layer_1 = !state.show_layer_1;
+                }
+                "2" => {
+                    state.show_layer_2 = !state.show_layer_2;
+                }
+                "3" => {
+                    state.show_fx = !state.show_fx;
+                }
+                "w" => {
+                    return Ok(Task::done(Message::SelectSource(
+                        SourceSelection::WorkingCopy,
+                    )));
+                }
+                "r" => {
+                    return Ok(Task::done(Message::SelectSource(
+                        SourceSelection::GitReference(state.other_name()),
+                    )));
+                }
+                "d" => {
+                    return Ok(Task::done(Message::SelectSource(
+                        SourceSelection::Difference,
+                    )));
+                }
+                "t" => {
+                    state.highlight_transparency = !state.highlight_transparency;
+                }
+                "-" => {
+                    let pixel_size = if state.pixel_size > 1.0 {
+                        state.pixel_size - 1.0
+                    } else {
+                        state.pixel_size / 2.0
+                    };
+                    return Ok(set_pixel_size(state, pixel_size.max(MIN_PIXEL_SIZE)));
+                }
+                "=" => {
+                    let pixel_size = if state.pixel_size < 1.0 {
+                        state.pixel_size * 2.0
+                    } else {
+                        state.pixel_size + 1.0
+                    };
+                    return Ok(set_pixel_size(state, pixel_size.min(MAX_PIXEL_SIZE)));
+                }
+                _ => {}
+            },
+            // The window may have moved to a display with a different scale.
+            iced::Event::Window(
+                window::Event::Opened { .. } | window::Event::Resized(_) | window::Event::Moved(_),
+            ) => {
+                return Ok(query_scale_factor());
+            }
+            iced::Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                state.modifiers = modifiers;
+            }
+            iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                key: keyboard::Key::Named(keyboard::key::Named::Space),
+                ..
+            }) => {
+                state.flipped = true;
+            }
+            iced::Event::Keyboard(keyboard::Event::KeyReleased {
+                key: keyboard::Key::Named(keyboard::key::Named::Space),
+                ..
+            }) => {
+                state.flipped = false;
+            }
+            iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                key: keyboard::Key::Named(keyboard::key::Named::ArrowDown),
+                ..
+            }) => {
+                let new_idx = match state.modified_room_idx {
+                    Some(idx) => idx + 1,
+                    None => 0,
+                };
+                if new_idx < state.modified_room_list.len() {
+                    state.modified_room_idx = Some(new_idx);
+                    return Ok(Task::done(Message::SelectModifiedRoom(new_idx)));
+                }
+            }
+            iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                key: keyboard::Key::Named(keyboard::key::Named::ArrowUp),
+                ..
+            }) => {
+                let new_idx = match state.modified_room_idx {
+                    Some(idx) if idx > 0 => idx - 1,
+                    _ => 0,
+                };
+                if new_idx < state.modified_room_list.len() {
+                    state.modified_room_idx = Some(new_idx);
+                    return Ok(Task::done(Message::SelectModifiedRoom(new_idx)));
+                }
+            }
+            _ => {}
+        },
+        Message::SelectProject(project) => {
+            state.project = project;
+            refresh_project_assets(state)?;
+            refresh_room_list(state)?;
+            refresh_room_images(state)?;
+            return Ok(restore_room_scroll(state));
+        }
+        Message::SearchReferences(search) => {
+            if state.reference_names.is_none() {
+                state.reference_names = Some(list_reference_names(state.repo()?)?);
+            }
+            state.reference_search = search;
+        }
+        Message::SelectReference(reference) => {
+            state
+                .repo()?
+                .revparse_single(&reference)
+                .with_context(|| format!("Unknown git reference {}", reference))?;
+            state.git_reference = reference;
+            state.reference_search.clear();
+            invalidate_room_thumbnails(state);
+            state.session_log.reference = state.reference_name();
+            if let SourceSelection::GitReference(_) = state.source_selection {
+                state.source_selection = SourceSelection::GitReference(state.other_name());
+            }
+            state.preferences.add_recent_reference(&state.git_reference);
+            save_preferences(state)?;
+            refresh_modified_room_list(state)?;
+            refresh_project_assets(state)?;
+            refresh_room_list(state)?;
+            refresh_room_images(state)?;
+            return Ok(restore_room_scroll(state));
+        }
+        Message::SelectRoom(room) => {
+            state.room = room;
+            refresh_room_images(state)?;
+            return Ok(restore_room_scroll(state));
+        }
+        Message::RetryRefresh(_) => {
+            refresh_room_images(state)?;
+            return Ok(restore_room_scroll(state));
+        }
+        Message::SelectRegion(region) => {
+            state.region = region;
+        }
+        Message::SearchTile(s) => {
+            state.tile_search = state
+                .preferences
+                .number_format
+                .parse(&s)
+                .filter(|&t| t <= SearchKind::Tile.max() as usize)
+                .map(|t| t as u16);
+            state.tile_search_text = s;
+            state.tile_search_idx = 0;
+        }
+        Message::PickTile(x, y) => {
+            let Some(working) = &state.working_images else {
+                return Ok(Task::none());
+            };
+            let tiles = layer1_tile_grid(&working.room_xml, state.room_state.0);
+            // A resized room's view extends past the working copy.
+            let width = working.room_xml.width * 16;
+            let Some(tile) = (x < width)
+                .then(|| tiles.get(y * width + x))
+                .flatten()
+                .copied()
+                .flatten()
+            else {
+                return Ok(Task::none());
+            };
+            state.tile_search = Some(tile);
+            state.tile_search_text = state.preferences.number_format.format(tile as usize, 3);
+            state.tile_search_idx = tile_occurrences(state)
+                .iter()
+                .position(|o| (o.0, o.1) == (x, y))
+                .unwrap_or(0);
+        }
+        Message::SelectProjectSearchKind(kind) => {
+            state.project_search_kind = kind;
+        }
+        Message::EditProjectSearch(s) => {
+            state.project_search_text = s;
+        }
+        Message::SearchProject => {
+            let format = state.preferences.number_format;
+            let max = state.project_search_kind.max() as usize;
+            let Some(value) = format
+                .parse(&state.project_search_text)
+                .filter(|&v| v <= max)
+            else {
+                bail!(
+                    "Invalid {} (expected {} up to {}): {}",
+                    state.project_search_kind,
+                    format.name(),
+                    format.format(max, 0),
+                    state.project_search_text
+                );
+            };
+            let target = SearchTarget {
+                kind: state.project_search_kind,
+                value: value as u16,
+            };
+            return Ok(search_project(state, target));
+        }
+        Message::ProjectSearchProgress(generation, room, result) => {
+            let Some(search) = &mut state.project_search else {
+                return Ok(Task::none());
+            };
+            if search.generation != generation {
+                return Ok(Task::none());
+            }
+            search.rooms_done += 1;
+            match result {
+                Ok(hits) => search
+                    .hits
+                    .extend(hits.into_iter().map(|hit| (room.clone(), hit))),
+                Err(e) => info!("Unable to search room {}: {}", room, e),
+            }
+        }
+        Message::OpenSearchHit(idx) => {
+            let Some(search) = &state.project_search else {
+                return Ok(Task::none());
+            };
+            let Some((room, hit)) = search.hits.get(idx).cloned() else {
+                return Ok(Task::none());
+            };
+            let (project, target) = (search.project.clone(), search.target);
+            if state.project != project {
+                state.project = project;
+                state.room = room;
+                refresh_project_assets(state)?;
+                refresh_room_list(state)?;
+                refresh_room_images(state)?;
+            } else if state.room != room {
+                state.room = room;
+                refresh_room_images(state)?;
+            }
+            if let Some(room_state) = state
+                .room_state_list
+                .options()
+                .iter()
+                .find(|s| s.0 == hit.state_idx)
+            {
+                state.room_state = room_state.clone();
+                refresh_overlay_images(state);
+            }
+            if target.kind == SearchKind::Tile {
+                state.tile_search = Some(target.value);
+                state.tile_search_text = state
+                    .preferences
+                    .number_format
+                    .format(target.value as usize, 3);
+                state.tile_search_idx = tile_occurrences(state)
+                    .iter()
+                    .position(|o| (o.0, o.1) == (hit.x, hit.y))
+                    .unwrap_or(0);
+            }
+            return Ok(scroll_to_tile(state, hit.x, hit.y));
+        }
+        Message::StepTileSearch(step) => {
+            let occurrences = tile_occurrences(state);
+            if occurrences.is_empty() {
+                return Ok(Task::none());
+            }
+            let n = occurrences.len() as isize;
+            let idx = state.tile_search_idx.min(occurrences.len() - 1) as isize;
+            state.tile_search_idx = (idx + step).rem_euclid(n) as usize;
+            let (x, y, _, _) = occurrences[state.tile_search_idx];
+            return Ok(scroll_to_tile(state, x, y));
+        }
+        Message::SaveRegionImage => {
+            let path = save_region_image(state)?;
+            state.export_status = Some(format!("Saved region to {}", path.display()));
+            return Ok(iced::clipboard::write(path.display().to_string()));
+        }
+        Message::SelectRoomState(room_state) => {
+            state.room_state = room_state;
+            refresh_overlay_images(state);
+        }
+        Message::FilterRoomStates(filter) => {
+            state.state_filter = filter;
+            if let Some(images) = &state.working_images {
+                let room_states = images
+                    .room_state_names
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .map(|(i, x)| RoomState(i, x))
+                    .collect();
+                state.room_state_list =
+                    combo_box::State::new(filter_room_states(images, room_states, filter));
+            }
+        }
+        Message::CompareRooms(b) => {
+            state.compare_room = b.then(|| state.room.clone());
+            return update_compare_room(state);
+        }
+        Message::SelectCompareRoom(room) => {
+            state.compare_room = Some(room);
+            return update_compare_room(state);
+        }
+        Message::MatchStatesByCondition(b) => {
+            state.match_states_by_condition = b;
+            invalidate_room_thumbnails(state);
+            refresh_room_images(state)?;
+        }
+        Message::NormalizeTilesets(normalization) => {
+            state.tileset_normalization = normalization;
+            refresh_room_images(state)?;
+        }
+        Message::SelectSource(src) => {
+            state.source_selection = src;
+            state
+                .session_log
+                .source_viewed(&state.source_selection.to_string());
+        }
+        Message::SetReviewNotes(notes) => {
+            state.session_log.set_notes(notes);
+        }
+        Message::BookmarkScreens => {
+            let Some(region) = state.region else {
+                return Ok(Task::none());
+            };
+            for y in region.y / 16..=(region.y + region.height - 1) / 16 {
+                for x in region.x / 16..=(region.x + region.width - 1) / 16 {
+                    state.session_log.add_bookmark(state.room_state.0, x, y);
+                }
+            }
+        }
+        Message::SetBookmarkNote(idx, note) => {
+            state.session_log.set_bookmark_note(idx, note);
+        }
+        Message::RemoveBookmark(idx) => {
+            state.session_log.remove_bookmark(idx);
+        }
+        Message::EditRoomNote(action) => {
+            state.room_note.perform(action);
+        }
+        Message::SaveRoomNote => {
+            let action = save_note(
+                &state.write_guard,
+                &state.project_key(&state.project.0),
+                &state.room,
+                &state.room_note.text(),
+            )?;
+            if state.write_guard.dry_run {
+                state.export_status = Some(format!("Dry run: {}", action));
+            } else {
+                state.room_note_saved = state.room_note.text();
+            }
+        }
+        Message::SaveSessionLog => {
+            let keys: Vec<PathBuf> = (state.session_log.rooms.iter())
+                .map(|room| state.project_key(Path::new(&room.project)))
+                .collect();
+            for (room, key) in state.session_log.rooms.iter_mut().zip(keys) {
+                room.room_note = load_note(&key, &room.room)?;
+            }
+            let mut writes = bookmark_image_writes(state)?;
+            let json = state.session_log.export_json()?;
+            let markdown = state
+                .session_log
+                .export_markdown(state.preferences.number_format);
+            for (filename, contents) in [
+                (SESSION_LOG_JSON_FILENAME, json),
+                (SESSION_LOG_MARKDOWN_FILENAME, markdown),
+            ] {
+                writes.push(PlannedWrite {
+                    path: PathBuf::from(filename),
+                    contents: Some(contents.into_bytes()),
+                });
+            }
+            state
+                .write_guard
+                .apply("review log", &writes)
+                .context("Unable to save review log")?;
+            state.export_status = Some(format!(
+                "{} review log to {} and {}",
+                if state.write_guard.dry_run {
+                    "Dry run: would save"
+                } else {
+                    "Saved"
+                },
+                SESSION_LOG_JSON_FILENAME,
+                SESSION_LOG_MARKDOWN_FILENAME
+            ));
+        }
+        Message::RemapPaletteLine(line, source) => {
+            state.palette_remap[line] = source;
+            refresh_room_images(state)?;
+        }
+        Message::ResetPaletteRemap => {
+            state.palette_remap = IDENTITY_PALETTE_REMAP;
+            refresh_room_images(state)?;
+        }
+        Message::SetAnimationFrame(frame) => {
+            state.animation_frame = frame;
+            refresh_room_images(state)?;
+        }
+        Message::ToggleAnimation(b) => {
+            state.animate = b;
+            state.animation_timer += 1;
+            if b {
+                let timer = state.animation_timer;
+                return Ok(delay(ANIMATION_INTERVAL).map(move |_| Message::AnimationTick(timer)));
+            }
+        }
+        Message::SelectColorMath(mode) => {
+            state.color_math = mode;
+            refresh_room_images(state)?;
+        }
+        Message::AnimationTick(timer) => {
+            if state.animate && timer == state.animation_timer {
+                let frames = state
+                    .working_images
+                    .as_ref()
+                    .map_or(1, |w| w.animation_frames);
+                state.animation_frame = (state.animation_frame + 1) % frames;
+                refresh_room_images(state)?;
+                return Ok(delay(ANIMATION_INTERVAL).map(move |_| Message::AnimationTick(timer)));
+            }
+        }
+        Message::SelectLayerSource(layer, source) => {
+            state.layer_sources[layer - 1] = source;
+        }
+        Message::ShowLayer1(b) => {
+            state.show_layer_1 = b;
+        }
+        Message::ShowLayer2(b) => {
+            state.show_layer_2 = b;
+        }
+        Message::ShowFX(b) => {
+            state.show_fx = b;
+        }
+        Message::ShowDoors(b) => {
+            state.show_doors = b;
+            refresh_room_ids(state);
+        }
+        Message::HighlightTransparency(b) => {
+            state.highlight_transparency = b;
+        }
+        Message::AdjustDifferenceBaseline(f) => {
+            state.difference_baseline.brightness = f;
+            refresh_baseline(state)?;
+        }
+        Message::PerChannelBaseline(b) => {
+            state.difference_baseline.channels = b.then_some([1.0; 3]);
+            refresh_baseline(state)?;
+        }
+        Message::AdjustChannelBaseline(i, f) => {
+            if let Some(channels) = &mut state.difference_baseline.channels {
+                channels[i] = f;
+            }
+            refresh_baseline(state)?;
+        }
+        Message::AdjustDifferenceGamma(f) => {
+            state.difference_baseline.gamma = f;
+            refresh_baseline(state)?;
+        }
+        Message::SelectModifiedRoom(idx) => {
+            if state.modifiers.control() {
+                if !state.selected_rooms.remove(&idx) {
+                    state.selected_rooms.insert(idx);
+                }
+                state.modified_room_idx = Some(idx);
+                refresh_modified_room_labels(state);
+                return Ok(Task::none());
+            }
+            if state.modifiers.shift() {
+                let anchor = state.modified_room_idx.unwrap_or(idx);
+                state.selected_rooms = (anchor.min(idx)..=anchor.max(idx)).collect();
+                refresh_modified_room_labels(state);
+                return Ok(Task::none());
+            }
+            state.selected_rooms = BTreeSet::from([idx]);
+            refresh_modified_room_labels(state);
+            state.modified_room_idx = Some(idx);
+            let modified_room = &state.modified_room_list[idx];
+            let project_changed = state.project != modified_room.project;
+            state.project = modified_room.project.clone();
+            state.room = modified_room.room_name.clone();
+            if project_changed {
+                refresh_project_assets(state)?;
+                refresh_room_list(state)?;
+            }
+            refresh_room_images(state)?;
+            return Ok(restore_room_scroll(state));
+        }
+        Message::ToggleOverlay(idx, b) => {
+            if let Some(overlay) = state.overlays.get_mut(idx) {
+                overlay.set_enabled(b);
+            }
+            refresh_overlay_images(state);
+        }
+        Message::ControlCommand(control::Command::Open {
+            project,
+            room,
+            reply,
+        }) => {
+            let result = open_room(state, &project, &room);
+            if let Some(reply) = reply {
+                let _ = reply.send(result.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e)));
+            }
+            return result;
+        }
+        Message::RestoreSession => {
+            let Some(navigation) = state.restorable_session.take() else {
+                return Ok(Task::none());
+            };
+            return restore_navigation(state, navigation);
+        }
+        Message::DismissSession => {
+            state.restorable_session = None;
+        }
+        Message::RunTool(tool) => {
+            run_tool(state, &tool)?;
+        }
+        Message::SelectViewMode(mode) => {
+            state.view_mode = mode;
+            state.comparison = None;
+            state.asset_error = None;
+            refresh_project_assets(state)?;
+        }
+        Message::OpenRoom(room, state_idx) => {
+            state.view_mode = ViewMode::Room;
+            state.room = room;
+            state.modified_room_idx = None;
+            refresh_room_images(state)?;
+            if let Some(room_state) = state.room_state_list.options().get(state_idx) {
+                state.room_state = room_state.clone();
+                refresh_overlay_images(state);
+            }
+        }
+        Message::SelectSharedAsset(asset) => {
+            state.shared_asset = Some(asset);
+        }
+        Message::OpenComparison(kind) => {
+            state.comparison = None;
+            state.asset_error = None;
+            if let Some(kind) = kind {
+                refresh_comparison_images(state, kind)?;
+            }
+        }
+        Message::OpenChangedFile(idx) => {
+            let file = &state.changed_files[idx];
+            if file.category == ChangeCategory::Room {
+                let room_idx = state.modified_room_list.iter().position(|r| {
+                    state.config.path_key(&r.path()) == state.config.path_key(&file.path)
+                });
+                if let Some(room_idx) = room_idx {
+                    state.view_mode = ViewMode::Room;
+                    return Ok(Task::done(Message::SelectModifiedRoom(room_idx)));
+                }
+            }
+            let path = file.path.clone();
+            let is_binary_asset = matches!(
+                file.category,
+                ChangeCategory::Tileset | ChangeCategory::Palette
+            );
+            // Workspace roots have their own repositories, so those files only get a byte-level
+            // comparison.
+            let repo = state.repo.as_ref().filter(|_| state.workspace.is_none());
+            if let (false, None, Some(repo)) = (is_binary_asset, &state.remote, repo) {
+                let reference = repo.revparse_single(state.git_reference_for_path(&path))?;
+                let tree = reference.peel_to_tree()?;
+                let lines = file_patch(repo, &tree, &path)?;
+                // git reports binary files with a single 'B' line and no content.
+                if !lines.iter().any(|x| x.origin == 'B') {
+                    state.file_diff = Some((path, lines));
+                    return Ok(Task::none());
+                }
+            }
+            // Fall back to a byte-level comparison. A file missing on either side
+            // (added or deleted) is compared as empty.
+            let working = LocalFileSystem {}.load(&path).unwrap_or_default();
+            let reference = match state.project_of_path(&path) {
+                Some(project) => state.reference_source(project),
+                None => state.reference_source(&state.project),
+            }
+            .with_fs(|fs| Ok(fs.load(&path).unwrap_or_default()))?;
+            let rows = hex_diff_rows(&working, &reference, HEX_CONTEXT_ROWS);
+            state.hex_diff = Some((path, rows));
+        }
+        Message::CloseFileDiff => {
+            state.file_diff = None;
+            state.hex_diff = None;
+        }
+        Message::DiffCommonLayers(b) => {
+            state.diff_common_layers = b;
+            refresh_diff_images(state)?;
+        }
+        Message::SelectColorPreset(preset) => {
+            state.preferences.color_preset = preset;
+            state.difference_baseline.set_colors(preset.colors());
+            refresh_baseline(state)?;
+            refresh_overlay_images(state);
+            save_preferences(state)?;
+        }
+        Message::SelectNumberFormat(format) => {
+            // Keep the tile being searched for, in the new format.
+            if let Some(tile) = state.tile_search {
+                state.tile_search_text = format.format(tile as usize, 3);
+            }
+            state.preferences.number_format = format;
+            refresh_overlay_images(state);
+            save_preferences(state)?;
+        }
+        Message::SortModifiedRooms(order) => {
+            state.preferences.room_sort = order;
+            sort_modified_rooms(state);
+            save_preferences(state)?;
+        }
+        Message::ExportAlphaDiff(b) => {
+            state.export_alpha_diff = b;
+        }
+        Message::SetVideoFormat(format) => {
+            state.video_format = format;
+        }
+        Message::SetExportDir(dir) => {
+            state.export_dir = dir;
+        }
+        Message::ExportVideo => {
+            let idx = state.room_state.0;
+            let composite_state = |images: &RoomImages| images.composite_state(idx);
+            let (Some(working), Some(reference)) = (
+                state.working_images.as_ref().and_then(composite_state),
+                state.other_images.as_ref().and_then(composite_state),
+            ) else {
+                bail!("Both versions of the room state are needed for a comparison video");
+            };
+            let path = PathBuf::from(&state.export_dir)
+                .join(state.project_key(&state.project.0))
+                .join(&state.room)
+                .join(format!(
+                    "{}_comparison.{}",
+                    idx,
+                    state.video_format.extension()
+                ));
+            let ffmpeg = state
+                .config
+                .ffmpeg
+                .clone()
+                .unwrap_or_else(|| "ffmpeg".to_string());
+            state.export_status = Some(format!("Exporting video to {}...", path.display()));
+            let guard = state.write_guard;
+            return Ok(in_background(move || {
+                export_comparison_video(&working, &reference, &path, &ffmpeg, &guard)
+                    .map(|_| path)
+                    .map_err(|e| format!("{:#}", e))
+            })
+            .map(|result| {
+                Message::VideoExported(
+                    result.unwrap_or_else(|| Err("Export was interrupted".to_string())),
+                )
+            }));
+        }
+        Message::VideoExported(result) => {
+            state.export_status = Some(match result {
+                Ok(path) => format!("Exported video to {}", path.display()),
+                Err(e) => format!("Video export failed: {}", e),
+            });
+        }
+        Message::ExportSelected => {
+            state.export_queue = state
+                .selected_rooms
+                .iter()
+                .rev()
+                .map(|&i| state.modified_room_list[i].clone())
+                .collect();
+            state.exported_count = 0;
+            return Ok(export_next_room(state));
+        }
+        Message::ThumbnailRendered(key, result) => {
+            if let Err(e) = &result {
+                info!("Unable to render thumbnail {:?}: {}", key, e);
+            }
+            state.thumbnails.insert(key, result);
+        }
+        Message::RoomAnalyzed(generation, path, analysis) => {
+            state.analysis.insert(generation, path, analysis);
+            refresh_modified_room_labels(state);
+        }
+        Message::HooksRan(generation, output) => {
+            if generation == state.hook_generation {
+                state.hook_output = output;
+                refresh_overlay_images(state);
+            }
+        }
+        Message::RemoteCompared(generation, modified_rooms, errors) => {
+            if generation == state.remote_generation {
+                let changed_files = modified_rooms
+                    .iter()
+                    .map(|room| ChangedFile {
+                        category: ChangeCategory::Room,
+                        path: room.path(),
+                        status: git2::Delta::Modified,
+                    })
+                    .collect();
+                set_modified_rooms(state, modified_rooms, changed_files);
+                refresh_modified_room_labels(state);
+                // At startup, the first modified room is opened once the rooms are listed.
+                if generation == 1 && !state.modified_room_list.is_empty() {
+                    select_first_modified_room(state);
+                    refresh_room_list(state)?;
+                    refresh_room_images(state)?;
+                }
+                for e in &errors {
+                    error!("Unable to compare with the remote: {}", e);
+                }
+                if let Some(e) = errors.first() {
+                    state.status = Some(format!(
+                        "Unable to compare {} rooms with the remote: {}",
+                        errors.len(),
+                        e
+                    ));
+                }
+            }
+        }
+        Message::RoomExported(result) => {
+            if let Err(e) = result {
+                error!("Export failed: {}", e);
+            }
+            state.export_queue.pop();
+            state.exported_count += 1;
+            return Ok(export_next_room(state));
+        }
+        Message::RoomScrolled(offset, viewport) => {
+            state.room_scroll = offset;
+            state.room_viewport = viewport;
+        }
+        Message::ScaleFactorChanged(scale_factor) => {
+            state.scale_factor = scale_factor;
+        }
+        Message::CopyDiagnostics => {
+            let report: Vec<String> = malformed_assets(state)
+                .iter()
+                .map(|a| a.diagnostics())
+                .collect();
+            return Ok(iced::clipboard::write(report.join("\n\n")));
+        }
+        Message::ShowUnchangedSettings(b) => {
+            state.show_unchanged_settings = b;
+        }
+        Message::ShowPanel(panel, b) => {
+            if b {
+                state.hidden_panels.remove(&panel);
+            } else {
+                state.hidden_panels.insert(panel);
+            }
+        }
+        Message::SetLogLevel(level) => {
+            state.log_level = level;
+        }
+        Message::SetLogSearch(search) => {
+            state.log_search = search;
+        }
+        Message::CopyLog => {
+            let lines: Vec<String> = filtered_log(state).iter().map(|r| r.to_string()).collect();
+            return Ok(iced::clipboard::write(lines.join("\n")));
+        }
+    }
+    Ok(Task::none())
+}
+
+// Error context marking a failure to load or parse a room in the working copy, which
+// is usually because SMART is in the middle of saving it.
+#[derive(Debug)]
+struct WorkingCopyUnreadable;
+
+impl Display for WorkingCopyUnreadable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unable to read room from working copy")
+    }
+}
+
+fn room_scrollable_id() -> scrollable::Id {
+    scrollable::Id::new("room")
+}
+
+// Switch between comparing against the reference and against another room, keeping the
+// selected source.
+fn update_compare_room(state: &mut State) -> Result<Task<Message>> {
+    invalidate_room_thumbnails(state);
+    if let SourceSelection::GitReference(_) = state.source_selection {
+        state.source_selection = SourceSelection::GitReference(state.other_name());
+    }
+    refresh_room_images(state)?;
+    Ok(restore_room_scroll(state))
+}
+
+fn current_navigation(state: &State) -> Navigation {
+    Navigation {
+        project: state.project.0.clone(),
+        room: state.room.clone(),
+        state_idx: state.room_state.0,
+        source: match state.source_selection {
+            SourceSelection::WorkingCopy => SavedSource::WorkingCopy,
+            SourceSelection::GitReference(_) => SavedSource::Reference,
+            SourceSelection::Difference => SavedSource::Difference,
+        },
+        scroll_x: state.room_scroll.x,
+        scroll_y: state.room_scroll.y,
+        zoom: state.pixel_size,
+    }
+}
+
+// Minimum time between saves of the navigation when only the scroll position has changed
+const SCROLL_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+// Time between frames when animating tiles
+const ANIMATION_INTERVAL: Duration = Duration::from_millis(150);
+
+// Save the preferences, except in a dry run (when they are only kept until smartdiff exits).
+fn save_preferences(state: &State) -> Result<()> {
+    if state.write_guard.dry_run {
+        return Ok(());
+    }
+    state.preferences.save(Path::new(PREFERENCES_FILENAME))
+}
+
+// Save where the user is, so that it can be restored after a crash.
+fn save_navigation(state: &mut State) {
+    let navigation = current_navigation(state);
+    if let Some((saved, time)) = &state.saved_navigation {
+        let scrolled_only = Navigation {
+            scroll_x: saved.scroll_x,
+            scroll_y: saved.scroll_y,
+            ..navigation.clone()
+        } == *saved;
+        if navigation == *saved || (scrolled_only && time.elapsed() < SCROLL_SAVE_INTERVAL) {
+            return;
+        }
+    }
+    // In a dry run, nothing is written, so the session can't be restored after a crash.
+    if state.write_guard.dry_run {
+        return;
+    }
+    if let Err(e) = session::save(Path::new(SESSION_FILENAME), &navigation) {
+        warn!("{:#}", e);
+    }
+    state.saved_navigation = Some((navigation, Instant::now()));
+}
+
+fn restore_navigation(state: &mut State, navigation: Navigation) -> Result<Task<Message>> {
+    let Some(project) = state
+        .project_list
+        .options()
+        .iter()
+        .find(|p| p.0 == navigation.project)
+        .cloned()
+    else {
+        bail!("Project {} no longer exists", navigation.project.display());
+    };
+    state.view_mode = ViewMode::Room;
+    state.project = project;
+    refresh_project_assets(state)?;
+    refresh_room_list(state)?;
+    if !state.room_list.options().contains(&navigation.room) {
+        bail!("Room {} no longer exists", navigation.room);
+    }
+    state.room = navigation.room;
+    refresh_room_images(state)?;
+    if let Some(room_state) = state
+        .room_state_list
+        .options()
+        .iter()
+        .find(|s| s.0 == navigation.state_idx)
+    {
+        state.room_state = room_state.clone();
+        refresh_overlay_images(state);
+    }
+    state.source_selection = match navigation.source {
+        SavedSource::WorkingCopy => SourceSelection::WorkingCopy,
+        SavedSource::Reference => SourceSelection::GitReference(state.other_name()),
+        SavedSource::Difference => SourceSelection::Difference,
+    };
+    state.pixel_size = navigation.zoom.clamp(MIN_PIXEL_SIZE, MAX_PIXEL_SIZE);
+    let diff = overview_diff(state);
+    refresh_overview_images(state, diff);
+    refresh_overlay_images(state);
+    state.room_scroll = scrollable::AbsoluteOffset {
+        x: navigation.scroll_x,
+        y: navigation.scroll_y,
+    };
+    Ok(restore_room_scroll(state))
+}
+
+fn restore_session_banner(state: &State) -> Option<Element<'_, Message>> {
+    let navigation = state.restorable_session.as_ref()?;
+    Some(
+        row![
+            text(format!(
+                "The last session ended unexpectedly, at {}/{}.",
+                navigation.project.display(),
+                navigation.room
+            ))
+            .style(text::danger),
+            button(text("Restore")).on_press(Message::RestoreSession),
+            button(text("Dismiss"))
+                .style(button::secondary)
+                .on_press(Message::DismissSession),
+        ]
+        .spacing(10)
+        .align_y(iced::Alignment::Center)
+        .into(),
+    )
+}
+
+// Scroll the room view so that the given 16x16 tile is at its center.
+fn scroll_to_tile(state: &mut State, x: usize, y: usize) -> Task<Message> {
+    state.room_scroll = scrollable::AbsoluteOffset {
+        x: (ROOM_MATTE + state.zoomed(x * 16 + 8) - state.room_viewport.width / 2.0).max(0.0),
+        y: (ROOM_MATTE + state.zoomed(y * 16 + 8) - state.room_viewport.height / 2.0).max(0.0),
+    };
+    restore_room_scroll(state)
+}
+
+// Scroll the room view back to where it was before its content was replaced.
+fn restore_room_scroll(state: &State) -> Task<Message> {
+    scrollable::scroll_to(room_scrollable_id(), state.room_scroll)
+}
+
+// Change the zoom level, scrolling so that the point at the center of the view stays there.
+fn set_pixel_size(state: &mut State, pixel_size: f32) -> Task<Message> {
+    let ratio = pixel_size / state.pixel_size;
+    let factor = overview_factor(state.pixel_size);
+    state.pixel_size = pixel_size;
+    if overview_factor(pixel_size) != factor {
+        let diff = overview_diff(state);
+        refresh_overview_images(state, diff);
+    }
+    // Overlays may depend on the zoom (e.g. the grid's tile lines).
+    refresh_overlay_images(state);
+    let half_width = state.room_viewport.width / 2.0;
+    let half_height = state.room_viewport.height / 2.0;
+    state.room_scroll = scrollable::AbsoluteOffset {
+        x: ((state.room_scroll.x + half_width) * ratio - half_width).max(0.0),
+        y: ((state.room_scroll.y + half_height) * ratio - half_height).max(0.0),
+    };
+    restore_room_scroll(state)
+}
+
+fn query_scale_factor() -> Task<Message> {
+    window::get_latest()
+        .and_then(window::get_scale_factor)
+        .map(Message::ScaleFactorChanged)
+}
+
+const MAX_REFRESH_RETRIES: usize = 4;
+const REFRESH_RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+// A task which runs the given function on its own thread.
+fn in_background<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Task<Option<T>> {
+    let (sender, receiver) = iced::futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(f());
+    });
+    Task::future(async move { receiver.await.ok() })
+}
+
+// A task which completes after the given delay.
+fn delay(duration: Duration) -> Task<()> {
+    in_background(move || std::thread::sleep(duration)).map(|_| ())
+}
+
+// Start exporting the next room in the export queue, if any.
+fn export_next_room(state: &mut State) -> Task<Message> {
+    let Some(room) = state.export_queue.last().cloned() else {
+        if state.exported_count > 0 {
+            state.export_status = Some(format!(
+                "Exported {} rooms to {}",
+                state.exported_count, state.export_dir
+            ));
+        }
+        return Task::none();
+    };
+    state.export_status = Some(format!(
+        "Exporting {}/{}...",
+        state.exported_count + 1,
+        state.exported_count + state.export_queue.len()
+    ));
+    let session = state.render_session.clone();
+    let reference = state.reference_source(&room.project);
+    let project_dir = PathBuf::from(&state.export_dir).join(state.project_key(&room.project.0));
+    let baseline = state.difference_baseline;
+    let alpha_diff = state.export_alpha_diff;
+    let guard = state.write_guard;
+    in_background(move || {
+        export_room(
+            &session,
+            &room,
+            &reference,
+            &project_dir,
+            baseline,
+            alpha_diff,
+            &guard,
+        )
+        .map_err(|e| format!("{}: {:#}", room, e))
+    })
+    .map(|result| {
+        Message::RoomExported(result.unwrap_or_else(|| Err("Export was interrupted".to_string())))
+    })
+}
+
+// Save the working copy, reference, and difference images of each state of a room, as
+// {project_dir}/{room}/{state}_{working,reference,diff}.png (and {state}_diff_alpha.png
+// if requested), where project_dir is the project's directory in the export directory.
+fn export_room(
+    session: &RenderSession,
+    room: &ModifiedRoom,
+    reference: &ReferenceSource,
+    project_dir: &Path,
+    baseline: DifferenceBaseline,
+    alpha_diff: bool,
+    guard: &WriteGuard,
+) -> Result<()> {
+    let working = session.render(&room.project.0, &room.room_name, &LocalFileSystem {})?;
+    let other = reference
+        .with_fs(|fs| session.render(&room.project.0, &room.room_name, fs))
+        .ok();
+    let room_dir = project_dir.join(&room.room_name);
+    let mut writes: Vec<PlannedWrite> = vec![];
+    for i in 0..working.layer1.len() {
+        let Some(working_image) = working.composite_state(i) else {
+            continue;
+        };
+        writes.push(planned_png(
+            &working_image,
+            room_dir.join(format!("{}_working.png", i)),
+        )?);
+        let Some(other_image) = other.as_ref().and_then(|other| other.composite_state(i)) else {
+            continue;
+        };
+        writes.push(planned_png(
+            &other_image,
+            room_dir.join(format!("{}_reference.png", i)),
+        )?);
+        let diff = diff_image(&working_image, &other_image, baseline);
+        writes.push(planned_png(
+            &diff,
+            room_dir.join(format!("{}_diff.png", i)),
+        )?);
+        if alpha_diff {
+            let mask = diff_mask_image(&working_image, &other_image, baseline);
+            writes.push(planned_png(
+                &mask,
+                room_dir.join(format!("{}_diff_alpha.png", i)),
+            )?);
+        }
+    }
+    guard.apply("export", &writes)?;
+    Ok(())
+}
+
+fn update(state: &mut State, message: Message) -> Task<Message> {
+    let attempt = match message {
+        Message::RetryRefresh(attempt) => attempt,
+        _ => 0,
+    };
+    match try_update(state, message) {
+        Ok(t) => {
+            save_navigation(state);
+            Task::batch([
+                t,
+                request_thumbnails(state),
+                request_room_analysis(state),
+                request_hooks(state),
+                request_remote_comparison(state),
+            ])
+        }
+        Err(e) if e.downcast_ref::<WorkingCopyUnreadable>().is_some() => {
+            if attempt < MAX_REFRESH_RETRIES {
+                info!("Working copy busy, retrying: {:?}", e);
+                state.status = Some(format!(
+                    "File busy, retrying ({}/{})...",
+                    attempt + 1,
+                    MAX_REFRESH_RETRIES
+                ));
+                delay(REFRESH_RETRY_BASE_DELAY * 2u32.pow(attempt as u32))
+                    .map(move |_| Message::RetryRefresh(attempt + 1))
+            } else {
+                error!("Error: {:?}", e);
+                state.status = Some(format!("{:#}", e));
+                Task::none()
+            }
+        }
+        Err(e) => {
+            error!("Error: {:?}", e);
+            if let Some(asset) = malformed_asset(&e) {
+                state.asset_error = Some(asset.clone());
+            }
+            Task::none()
+        }
+    }
+}
+
+// Pixels per map tile in the door graph.
+const DOOR_GRAPH_SCALE: f32 = 8.0;
+
+struct DoorGraphCanvas<'a> {
+    graph: &'a DoorGraph,
+    layout: Vec<(usize, usize, usize, usize)>,
+    colors: DiffColors,
+}
+
+impl DoorGraphCanvas<'_> {
+    fn node_rect(&self, idx: usize) -> Rectangle {
+        let (x, y, w, h) = self.layout[idx];
+        Rectangle::new(
+            Point::new(x as f32 * DOOR_GRAPH_SCALE, y as f32 * DOOR_GRAPH_SCALE),
+            Size::new(w as f32 * DOOR_GRAPH_SCALE, h as f32 * DOOR_GRAPH_SCALE),
+        )
+    }
+}
+
+impl canvas::Program<Message> for DoorGraphCanvas<'_> {
+    type State = ();
+
+    fn update(
+        &self,
+        _internal_state: &mut (),
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: iced::mouse::Cursor,
+    ) -> (canvas::event::Status, Option<Message>) {
+        let pos = match (event, cursor.position_in(bounds)) {
+            (
+                canvas::Event::Mouse(iced::mouse::Event::ButtonPressed(iced::mouse::Button::Left)),
+                Some(pos),
+            ) => pos,
+            _ => return (canvas::event::Status::Ignored, None),
+        };
+        for (i, node) in self.graph.nodes.iter().enumerate() {
+            if self.node_rect(i).contains(pos) {
+                return (
+                    canvas::event::Status::Captured,
+                    Some(Message::OpenRoom(node.name.clone(), 0)),
+                );
+            }
+        }
+        (canvas::event::Status::Ignored, None)
+    }
+
+    fn draw(
+        &self,
+        _internal_state: &(),
+        renderer: &iced::Renderer,
+        theme: &iced::Theme,
+        bounds: iced::Rectangle,
+        _cursor: iced::mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+        let text_color = theme.palette().text;
+        for (i, node) in self.graph.nodes.iter().enumerate() {
+            let rect = self.node_rect(i);
+            frame.stroke(
+                &canvas::Path::rectangle(rect.position(), rect.size()),
+                canvas::Stroke::default().with_color(text_color),
+            );
+            frame.fill_text(canvas::Text {
+                content: node.name.clone(),
+                position: rect.position() + iced::Vector::new(2.0, 1.0),
+                color: text_color,
+                size: iced::Pixels(10.0),
+                ..Default::default()
+            });
+        }
+        for edge in &self.graph.edges {
+            let (color, width) = match edge.status {
+                EdgeStatus::Unchanged => (iced::Color::from_rgb8(128, 128, 128), 1.0),
+                EdgeStatus::Added => (rgb(self.colors.added), 2.0),
+                EdgeStatus::Removed => (rgb(self.colors.removed), 2.0),
+            };
+            frame.stroke(
+                &canvas::Path::line(
+                    self.node_rect(edge.from).center(),
+                    self.node_rect(edge.to).center(),
+                ),
+                canvas::Stroke::default()
+                    .with_color(color)
+                    .with_width(width),
+            );
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+// Folder and button for exporting the rooms selected in the modified room list.
+fn export_controls(state: &State) -> Element<'_, Message> {
+    let exporting = !state.export_queue.is_empty();
+    let buttons = row![
+        text_input("Export folder", &state.export_dir)
+            .on_input(Message::SetExportDir)
+            .size(12),
+        button(text(format!("Export selected ({})", state.selected_rooms.len())).size(12))
+            .on_press_maybe(
+                (!state.selected_rooms.is_empty() && !exporting).then_some(Message::ExportSelected)
+            ),
+        button(text("Export video").size(12)).on_press_maybe(
+            (state.room_images(&SourceSelection::Difference).is_some())
+                .then_some(Message::ExportVideo)
+        ),
+        pick_list(
+            [VideoFormat::Mp4, VideoFormat::WebM],
+            Some(state.video_format),
+            Message::SetVideoFormat
+        )
+        .text_size(12),
+    ]
+    .spacing(5);
+    column![
+        buttons,
+        checkbox("Transparent difference images", state.export_alpha_diff)
+            .size(12)
+            .text_size(12)
+            .on_toggle(Message::ExportAlphaDiff),
+    ]
+    .push_maybe(state.export_status.as_ref().map(|s| text(s).size(12)))
+    .spacing(5)
+    .into()
+}
+
+// Which palette line each line of the working copy is drawn with.
+fn palette_remap_controls(state: &State) -> Element<'_, Message> {
+    let lines = (0..8).map(|line| {
+        row![
+            text(format!("{}→", line)).size(12),
+            pick_list(
+                (0..8).collect::<Vec<usize>>(),
+                Some(state.palette_remap[line]),
+                move |source| Message::RemapPaletteLine(line, source)
+            )
+            .text_size(12)
+            .padding(2),
+        ]
+        .spacing(2)
+        .align_y(iced::Alignment::Center)
+        .into()
+    });
+    let mut controls = column![text("Palette remap preview (working copy)").size(12)]
+        .push(row(lines).spacing(8).wrap())
+        .spacing(5);
+    if state.palette_remap != IDENTITY_PALETTE_REMAP {
+        controls = controls.push(
+            row![
+                text("Remapped palette lines are applied")
+                    .size(12)
+                    .style(text::danger),
+                button(text("Reset").size(12)).on_press(Message::ResetPaletteRemap),
+            ]
+            .spacing(10)
+            .align_y(iced::Alignment::Center),
+        );
+    }
+    controls.into()
+}
+
+// Which frame of the tile animations is drawn, if the room's tilesets have any.
+fn animation_controls(state: &State) -> Element<'_, Message> {
+    let frames = state
+        .working_images
+        .as_ref()
+        .map_or(1, |w| w.animation_frames);
+    if frames <= 1 {
+        return column![].into();
+    }
+    let frame = state.animation_frame % frames;
+    row![
+        text(format!("Animation frame {}/{}", frame + 1, frames)).size(12),
+        slider(0..=(frames as u32 - 1), frame as u32, |f| {
+            Message::SetAnimationFrame(f as usize)
+        })
+        .width(120),
+        checkbox("Animate", state.animate)
+            .size(12)
+            .text_size(12)
+            .on_toggle(Message::ToggleAnimation),
+    ]
+    .spacing(10)
+    .align_y(iced::Alignment::Center)
+    .into()
+}
+
+fn baseline_controls(state: &State) -> Element<'_, Message> {
+    let baseline = &state.difference_baseline;
+    let mut controls = column![
+        pick_list(
+            [
+                ColorPreset::Standard,
+                ColorPreset::Deuteranopia,
+                ColorPreset::Protanopia,
+                ColorPreset::Tritanopia,
+            ],
+            Some(state.preferences.color_preset),
+            Message::SelectColorPreset,
+        ),
+        pick_list(
+            [NumberFormat::Hex, NumberFormat::Decimal],
+            Some(state.preferences.number_format),
+            Message::SelectNumberFormat,
+        ),
+        row![
+            text("Difference baseline"),
+            slider(
+                0.0..=1.0,
+                baseline.brightness,
+                Message::AdjustDifferenceBaseline
+            )
+            .step(0.01)
+        ]
+        .spacing(10),
+        row![
+            text("Baseline gamma"),
+            slider(0.2..=3.0, baseline.gamma, Message::AdjustDifferenceGamma).step(0.05)
+        ]
+        .spacing(10),
+        checkbox("Per-channel baseline", baseline.channels.is_some())
+            .on_toggle(Message::PerChannelBaseline),
+    ]
+    .spacing(5);
+    if let Some(channels) = baseline.channels {
+        for (i, name) in ["Red", "Green", "Blue"].into_iter().enumerate() {
+            controls = controls.push(
+                row![
+                    text(name),
+                    slider(0.0..=1.0, channels[i], move |f| {
+                        Message::AdjustChannelBaseline(i, f)
+                    })
+                    .step(0.01)
+                ]
+                .spacing(10),
+            );
+        }
+    }
+    controls.into()
+}
+
+fn layer_source_controls(state: &State) -> Element<'_, Message> {
+    let options = [
+        LayerSource::Selected,
+        LayerSource::WorkingCopy,
+        LayerSource::Reference,
+        LayerSource::Difference,
+    ];
+    let picker = |layer: usize| {
+        row![
+            text(format!("Layer {} from", layer)).size(12),
+            pick_list(
+                options,
+                Some(state.layer_sources[layer - 1]),
+                move |source| Message::SelectLayerSource(layer, source)
+            )
+            .text_size(12),
+        ]
+        .spacing(5)
+        .align_y(iced::Alignment::Center)
+    };
+    row![picker(1), picker(2)].spacing(10).wrap().into()
+}
+
+fn panel_toggles(state: &State) -> Element<'_, Message> {
+    let toggles = [
+        Panel::Metadata,
+        Panel::Enemies,
+        Panel::Dependencies,
+        Panel::Review,
+        Panel::Export,
+        Panel::ModifiedRooms,
+        Panel::StateThumbnails,
+        Panel::Rulers,
+        Panel::Log,
+    ]
+    .into_iter()
+    .map(|panel| {
+        checkbox(panel.to_string(), state.shows(panel))
+            .size(12)
+            .text_size(12)
+            .on_toggle(move |b| Message::ShowPanel(panel, b))
+            .into()
+    });
+    row(toggles).spacing(10).wrap().into()
+}
+
+fn room_note_editor(state: &State) -> Element<'_, Message> {
+    let unsaved = state.room_note.text() != state.room_note_saved;
+    let label = if unsaved {
+        "Room note (unsaved)"
+    } else {
+        "Room note"
+    };
+    column![
+        row![
+            text(label).size(12),
+            button(text("Save note").size(12)).on_press_maybe(
+                (unsaved && !state.room.is_empty()).then_some(Message::SaveRoomNote)
+            ),
+        ]
+        .spacing(10),
+        text_editor(&state.room_note)
+            .placeholder("Notes on this room, saved in .smartdiff/notes")
+            .on_action(Message::EditRoomNote)
+            .size(12)
+            .height(80),
+    ]
+    .spacing(5)
+    .into()
+}
+
+// Notes on the current room, and saving the log of what was reviewed this session.
+fn review_controls(state: &State) -> Element<'_, Message> {
+    let mut notes = text_input(
+        "Review notes",
+        state.session_log.current_notes().unwrap_or(""),
+    )
+    .size(12);
+    if state.session_log.current_notes().is_some() {
+        notes = notes.on_input(Message::SetReviewNotes);
+    }
+    let mut content = column![
+        row![
+            notes,
+            button(text("Save review log").size(12)).on_press(Message::SaveSessionLog),
+        ]
+        .spacing(5)
+    ]
+    .spacing(2);
+    for (i, bookmark) in state.session_log.current_bookmarks().iter().enumerate() {
+        content = content.push(
+            row![
+                text(format!(
+                    "State {}, screen ({}, {})",
+                    bookmark.state_idx,
+                    state.preferences.number_format.format(bookmark.x, 0),
+                    state.preferences.number_format.format(bookmark.y, 0)
+                ))
+                .size(12),
+                text_input("What needs attention", &bookmark.note)
+                    .on_input(move |s| Message::SetBookmarkNote(i, s))
+                    .size(12),
+                button(text("Remove").size(12))
+                    .style(button::text)
+                    .on_press(Message::RemoveBookmark(i)),
+            ]
+            .spacing(5)
+            .align_y(iced::Alignment::Center),
+        );
+    }
+    content.into()
+}
+
+// Enlarged images of the bookmarked screens of each version, for the review log.
+fn bookmark_image_writes(state: &State) -> Result<Vec<PlannedWrite>> {
+    let mut writes: Vec<PlannedWrite> = vec![];
+    let dir = Path::new(SESSION_LOG_MARKDOWN_FILENAME)
+        .parent()
+        .unwrap_or(Path::new(""));
+    let bookmarked = state
+        .session_log
+        .rooms
+        .iter()
+        .filter(|r| !r.bookmarks.is_empty());
+    for room in bookmarked {
+        let project = Project(PathBuf::from(&room.project));
+        let session = &state.render_session;
+        let working = session.render(&project.0, &room.room, &LocalFileSystem {})?;
+        let reference = state
+            .reference_source(&project)
+            .with_fs(|fs| session.render(&project.0, &room.room, fs))
+            .ok();
+        for bookmark in &room.bookmarks {
+            let idx = bookmark.state_idx;
+            for (version, images) in [
+                ("working", Some(&working)),
+                ("reference", reference.as_ref()),
+            ] {
+                let Some(image) = images.and_then(|i| i.composite_state(idx)) else {
+                    continue;
+                };
+                let screen = crop(&image, bookmark.x * 256, bookmark.y * 256, 256, 256);
+                writes.push(planned_png(
+                    &upscale(&screen, 2),
+                    dir.join(room.bookmark_image_path(bookmark, version)),
+                )?);
+            }
+        }
+    }
+    Ok(writes)
+}
+
+// Note listing the other projects with a room of the same name as the current one.
+fn duplicate_room_note(state: &State) -> Option<Element<'_, Message>> {
+    let projects = state.duplicate_rooms.get(&state.room)?;
+    let others: Vec<String> = projects
+        .iter()
+        .filter(|p| **p != state.project)
+        .map(|p| p.to_string())
+        .collect();
+    if others.is_empty() {
+        return None;
+    }
+    Some(
+        text(format!(
+            "Room {} also exists in: {}",
+            state.room,
+            others.join(", ")
+        ))
+        .size(12)
+        .into(),
+    )
+}
+
+// Log records shown in the log panel, most recent last.
+fn filtered_log(state: &State) -> Vec<LogRecord> {
+    let search = state.log_search.to_lowercase();
+    log_capture::recent()
+        .into_iter()
+        .filter(|r| r.level <= state.log_level)
+        .filter(|r| r.to_string().to_lowercase().contains(&search))
+        .collect()
+}
+
+fn log_panel(state: &State) -> Element<'_, Message> {
+    let records = filtered_log(state);
+    let mut lines = column![].spacing(2);
+    for record in records.iter().rev().take(LOG_PANEL_RECORDS) {
+        let style = match record.level {
+            log::Level::Error | log::Level::Warn => text::danger,
+            _ => text::default,
+        };
+        lines = lines.push(text(record.to_string()).size(11).style(style));
+    }
+    column![
+        row![
+            text(format!("Log ({} records, newest first)", records.len())).size(12),
+            pick_list(
+                [log::Level::Error, log::Level::Warn, log::Level::Info],
+                Some(state.log_level),
+                Message::SetLogLevel
+            )
+            .text_size(12),
+            button(text("Copy").size(12))
+                .on_press_maybe((!records.is_empty()).then_some(Message::CopyLog)),
+        ]
+        .spacing(5)
+        .align_y(iced::Alignment::Center),
+        text_input("Search log...", &state.log_search)
+            .on_input(Message::SetLogSearch)
+            .size(12),
+        Scrollable::new(lines).height(Length::Fixed(150.0)),
+    ]
+    .spacing(5)
+    .into()
+}
+
+// Files used to render the current room (in either version), marking those which changed.
+fn room_dependency_list(state: &State) -> Element<'_, Message> {
+    let mut dependencies: Vec<&PathBuf> = vec![];
+    for images in [&state.working_images, &state.other_images]
+        .into_iter()
+        .flatten()
+    {
+        for path in &images.dependencies {
+            if !dependencies.contains(&path) {
+                dependencies.push(path);
+            }
+        }
+    }
+    let mut content = column![text("Room dependencies:")];
+    for path in dependencies {
+        let modified = state.changed_files.iter().any(|f| &f.path == path);
+        let label = text(format!(
+            "{} {}",
+            if modified {
+                "[modified]"
+            } else {
+                "[unmodified]"
+            },
+            path.display()
+        ))
+        .size(12);
+        content = content.push(if modified {
+            label.style(text::danger)
+        } else {
+            label
+        });
+    }
+    content.into()
+}
+
+// Rooms states which use the selected tileset (and its palette).
+fn asset_usage_view(state: &State) -> Element<'_, Message> {
+    let Some(usage) = &state.asset_usage else {
+        return text("Asset usage not available").into();
+    };
+    let mut gfx_sets: Vec<usize> = usage.users.iter().map(|u| u.gfx_set).collect();
+    gfx_sets.sort();
+    gfx_sets.dedup();
+    let assets: Vec<SharedAsset> = gfx_sets.into_iter().map(SharedAsset::Tileset).collect();
+
+    let mut content = column![
+        pick_list(assets, state.shared_asset, Message::SelectSharedAsset)
+            .placeholder("Select a tileset...")
+    ]
+    .spacing(5);
+    if let Some(asset) = state.shared_asset {
+        let users = usage.users_of(asset);
+        content = content.push(text(format!(
+            "{} used by {} room states",
+            asset,
+            users.len()
+        )));
+        for user in users {
+            content = content.push(
+                button(text(user.to_string()))
+                    .style(button::text)
+                    .padding([0, 10])
+                    .on_press(Message::OpenRoom(user.room.clone(), user.state_idx)),
+            );
+        }
+    }
+    Scrollable::new(content).width(Length::Fill).into()
+}
+
+fn door_graph_view(state: &State) -> Element<'_, Message> {
+    let Some(graph) = &state.door_graph else {
+        return text("Door graph not available").into();
+    };
+    let layout = graph.layout();
+    let width = layout.iter().map(|(x, _, w, _)| x + w).max().unwrap_or(0);
+    let height = layout.iter().map(|(_, y, _, h)| y + h).max().unwrap_or(0);
+    let changed = graph
+        .edges
+        .iter()
+        .filter(|e| e.status != EdgeStatus::Unchanged)
+        .count();
+    column![
+        text(format!(
+            "{} rooms, {} doors changed (green: added, red: removed). Click a room to open it.",
+            graph.nodes.len(),
+            changed
+        )),
+        Scrollable::with_direction(
+            canvas(DoorGraphCanvas {
+                graph,
+                layout,
+                colors: state.preferences.color_preset.colors(),
+            })
+            .width(width as f32 * DOOR_GRAPH_SCALE + 15.0)
+            .height(height as f32 * DOOR_GRAPH_SCALE + 15.0),
+            scrollable::Direction::Both {
+                vertical: Scrollbar::default(),
+                horizontal: Scrollbar::default(),
+            },
+        )
+        .width(Length::Fill),
+    ]
+    .spacing(10)
+    .into()
+}
+
+struct RoomCanvas<'a> {
+    state: &'a State,
+}
+
+impl<'a> canvas::Program<Message> for RoomCanvas<'a> {
+    // Start and current position of a drag in progress
+    type State = Option<(Point, Point)>;
+
+    fn update(
+        &self,
+        drag: &mut Option<(Point, Point)>,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: iced::mouse::Cursor,
+    ) -> (canvas::event::Status, Option<Message>) {
+        use iced::mouse::{Button, Event};
+        // Positions are tracked outside the canvas too, so that a drag can end there.
+        let Some(pos) = cursor.position_from(bounds.position()) else {
+            return (canvas::event::Status::Ignored, None);
+        };
+        match event {
+            canvas::Event::Mouse(Event::ButtonPressed(Button::Left)) if cursor.is_over(bounds) => {
+                *drag = Some((pos, pos));
+                (canvas::event::Status::Captured, None)
+            }
+            canvas::Event::Mouse(Event::ButtonPressed(Button::Right)) if cursor.is_over(bounds) => {
+                let (x, y) = self.tile_at(pos);
+                (
+                    canvas::event::Status::Captured,
+                    Some(Message::PickTile(x, y)),
+                )
+            }
+            canvas::Event::Mouse(Event::CursorMoved { .. }) => match drag {
+                Some((_, end)) => {
+                    *end = pos;
+                    (canvas::event::Status::Captured, None)
+                }
+                None => (canvas::event::Status::Ignored, None),
+            },
+            canvas::Event::Mouse(Event::ButtonReleased(Button::Left)) => match drag.take() {
+                Some((start, _)) => (
+                    canvas::event::Status::Captured,
+                    Some(Message::SelectRegion(self.region(start, pos))),
+                ),
+                None => (canvas::event::Status::Ignored, None),
+            },
+            _ => (canvas::event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        drag: &Option<(Point, Point)>,
+        renderer: &iced::Renderer,
+        theme: &iced::Theme,
+        bounds: iced::Rectangle,
+        cursor: iced::mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let state = self.state;
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+
+        let Some((width, height)) = state.room_size() else {
+            return vec![];
+        };
+        let rect = Rectangle::new(
+            Point::new(ROOM_MATTE, ROOM_MATTE),
+            Size {
+                width: state.zoomed(width),
+                height: state.zoomed(height),
+            },
+        );
+
+        // A matte contrasting with the window background, and a border, so that the edges
+        // of the room stand out in either theme.
+        let palette = theme.extended_palette();
+        let matte_color = if palette.is_dark {
+            iced::Color::from_rgb8(150, 150, 150)
+        } else {
+            iced::Color::from_rgb8(60, 60, 60)
+        };
+        frame.fill_rectangle(
+            Point::ORIGIN,
+            Size {
+                width: rect.width + 2.0 * ROOM_MATTE,
+                height: rect.height + 2.0 * ROOM_MATTE,
+            },
+            matte_color,
+        );
+        frame.stroke(
+            &canvas::Path::rectangle(
+                rect.position() - iced::Vector::new(0.5, 0.5),
+                rect.size() + Size::new(1.0, 1.0),
+            ),
+            canvas::Stroke::default().with_color(palette.background.base.text),
+        );
+
+        let bg_color = if state.highlight_transparency {
+            iced::Color::from_rgb8(255, 105, 180)
+        } else {
+            state.backdrop_color(&state.displayed_source())
+        };
+        frame.fill_rectangle(rect.position(), rect.size(), bg_color);
+
+        let source = state.displayed_source();
+        let layer1_source = state.layer_source(1);
+        let layer2_source = state.layer_source(2);
+        let layer1_images = state.room_images(&layer1_source);
+        let layer2_images = state.room_images(&layer2_source);
+        if layer1_images.is_none() && layer2_images.is_none() {
+            frame.fill_text(canvas::Text {
+                content: format!("Not available for {}", source),
+                position: Point::new(ROOM_MATTE + 10.0, ROOM_MATTE + 10.0),
+                color: iced::Color::WHITE,
+                ..canvas::Text::default()
+            });
+            return vec![frame.into_geometry()];
+        }
+        let state_idx = state.room_state.0;
+
+        // Each source's images are drawn at their own size, which differs between the versions
+        // of a resized room.
+        let images_rect = |images: &RoomData| {
+            Rectangle::new(
+                rect.position(),
+                Size::new(state.zoomed(images.width), state.zoomed(images.height)),
+            )
+        };
+        if let Some(images) = layer2_images.filter(|_| state.show_layer_2) {
+            frame.draw_image(
+                images_rect(images),
+                canvas::Image::new(&images.layer2[state_idx])
+                    .filter_method(image::FilterMethod::Nearest),
+            );
+        }
+        if let Some(images) = layer1_images.filter(|_| state.show_layer_1) {
+            frame.draw_image(
+                images_rect(images),
+                canvas::Image::new(&images.layer1[state_idx])
+                    .filter_method(image::FilterMethod::Nearest),
+            );
+            // High-priority parts of layer 2 go over layer 1.
+            if let Some((images, handle)) = layer2_images
+                .filter(|_| state.show_layer_2)
+                .and_then(|images| Some((images, images.layer2_front.get(state_idx)?)))
+            {
+                frame.draw_image(
+                    images_rect(images),
+                    canvas::Image::new(handle).filter_method(image::FilterMethod::Nearest),
+                );
+            }
+        }
+        // Layer 3 is always from the selected source.
+        if let Some((images, handle)) = state
+            .room_images(&source)
+            .filter(|_| state.show_fx)
+            .and_then(|images| Some((images, images.fx.get(state_idx)?)))
+        {
+            frame.draw_image(
+                images_rect(images),
+                canvas::Image::new(handle).filter_method(image::FilterMethod::Nearest),
+            );
+        }
+        // Overlays are of the working copy's size.
+        let overlay_rect = state
+            .working_image_handles
+            .as_ref()
+            .map_or(rect, images_rect);
+        for handle in state.overlay_image_handles.iter().flatten() {
+            frame.draw_image(
+                overlay_rect,
+                canvas::Image::new(handle).filter_method(image::FilterMethod::Nearest),
+            );
+        }
+
+        // Occurrences of the searched tile, in the color for additions if only in the working
+        // copy, for removals if only in the reference, and the marker color if in both.
+        let colors = state.preferences.color_preset.colors();
+        let occurrences = tile_occurrences(state);
+        let current = state
+            .tile_search_idx
+            .min(occurrences.len().saturating_sub(1));
+        for (i, &(x, y, in_working, in_reference)) in occurrences.iter().enumerate() {
+            let color = match (in_working, in_reference) {
+                (true, true) => colors.marker,
+                (true, false) => colors.added,
+                _ => colors.removed,
+            };
+            let rect = self.region_rect(Region {
+                x,
+                y,
+                width: 1,
+                height: 1,
+            });
+            frame.stroke(
+                &canvas::Path::rectangle(rect.position(), rect.size()),
+                canvas::Stroke::default()
+                    .with_color(rgb(color))
+                    .with_width(if i == current { 3.0 } else { 1.0 }),
+            );
+        }
+
+        // Screens marked as needing attention
+        for bookmark in state.session_log.current_bookmarks() {
+            if bookmark.state_idx != state_idx {
+                continue;
+            }
+            let rect = self.region_rect(Region {
+                x: bookmark.x * 16,
+                y: bookmark.y * 16,
+                width: 16,
+                height: 16,
+            });
+            frame.stroke(
+                &canvas::Path::rectangle(rect.position(), rect.size()),
+                canvas::Stroke::default()
+                    .with_color(rgb(colors.removed))
+                    .with_width(3.0),
+            );
+        }
+
+        let selection = match drag {
+            Some((start, end)) => {
+                Some(Rectangle::new(*start, Size::ZERO).union(&Rectangle::new(*end, Size::ZERO)))
+            }
+            None => state.region.map(|r| self.region_rect(r)),
+        };
+        if let Some(selection) = selection {
+            frame.stroke(
+                &canvas::Path::rectangle(selection.position(), selection.size()),
+                canvas::Stroke::default()
+                    .with_color(rgb(state.preferences.color_preset.colors().marker))
+                    .with_width(2.0),
+            );
+        }
+
+        let hovered = cursor.position_in(bounds).filter(|p| rect.contains(*p));
+        if state.show_doors {
+            self.draw_doors(&mut frame, hovered.filter(|_| drag.is_none()), palette);
+        }
+        if let (SourceSelection::Difference, None, Some(pos)) = (&source, drag, hovered) {
+            self.draw_hover_previews(&mut frame, pos, palette);
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
+
+// Tile coordinates along the top (horizontal) or left (vertical) edge of the room view,
+// with longer marks and labels at screen boundaries, following its zoom and scrolling.
+struct RulerCanvas<'a> {
+    state: &'a State,
+    vertical: bool,
+}
+
+impl canvas::Program<Message> for RulerCanvas<'_> {
+    type State = ();
+
+    fn draw(
+        &self,
+        _: &(),
+        renderer: &iced::Renderer,
+        theme: &iced::Theme,
+        bounds: iced::Rectangle,
+        _cursor: iced::mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let state = self.state;
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+        let Some((width, height)) = state.room_size() else {
+            return vec![];
+        };
+        let palette = theme.extended_palette();
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), palette.background.weak.color);
+        let (tiles, scroll, length) = if self.vertical {
+            (height / 16, state.room_scroll.y, bounds.height)
+        } else {
+            (width / 16, state.room_scroll.x, bounds.width)
+        };
+        let tile_size = state.zoomed(16);
+        // Label every tile when there is room, and otherwise only the screens.
+        let label_tiles = tile_size >= 20.0;
+        let color = palette.background.weak.text;
+        for tile in 0..=tiles {
+            let pos = (ROOM_MATTE + state.zoomed(tile * 16) - scroll).round() + 0.5;
+            if pos < -tile_size || pos > length {
+                continue;
+            }
+            let screen = tile % 16 == 0;
+            let mark = if screen { RULER_SIZE } else { RULER_SIZE / 4.0 };
+            let (from, to) = if self.vertical {
+                (
+                    Point::new(RULER_SIZE - mark, pos),
+                    Point::new(RULER_SIZE, pos),
+                )
+            } else {
+                (
+                    Point::new(pos, RULER_SIZE - mark),
+                    Point::new(pos, RULER_SIZE),
+                )
+            };
+            frame.stroke(
+                &canvas::Path::line(from, to),
+                canvas::Stroke::default().with_color(color),
+            );
+            let format = self.state.preferences.number_format;
+            let label = if screen && tile < tiles {
+                format!("S{}", format.format(tile / 16, 0))
+            } else if label_tiles && tile < tiles {
+                format.format(tile, 0)
+            } else {
+                continue;
+            };
+            frame.fill_text(canvas::Text {
+                content: label,
+                position: if self.vertical {
+                    Point::new(2.0, pos + 2.0)
+                } else {
+                    Point::new(pos + 2.0, 2.0)
+                },
+                color,
+                size: iced::Pixels(10.0),
+                ..canvas::Text::default()
+            });
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+fn view(state: &State) -> Element<'_, Message> {
+    let overlay_toggles = state.overlays.iter().enumerate().map(|(i, overlay)| {
+        checkbox(overlay.name(), overlay.enabled())
+            .on_toggle(move |b| Message::ToggleOverlay(i, b))
+            .into()
+    });
+    let controls = column![
+        pick_list(
+            [
+                ViewMode::Room,
+                ViewMode::Project,
+                ViewMode::Tileset,
+                ViewMode::AreaMap,
+                ViewMode::Library,
+                ViewMode::DoorGraph,
+                ViewMode::AssetUsage
+            ],
+            Some(state.view_mode),
+            Message::SelectViewMode,
+        ),
+        combo_box(
+            &state.project_list,
+            "",
+            Some(&state.project),
+            Message::SelectProject,
+        ),
+        if state.room_list.options().is_empty() {
+            Element::from(text("No rooms in this project (Export/Rooms is empty)").size(12))
+        } else {
+            combo_box(&state.room_list, "", Some(&state.room), Message::SelectRoom).into()
+        },
+        column![
+            row![
+                combo_box(
+                    &state.room_state_list,
+                    "",
+                    Some(&state.room_state),
+                    Message::SelectRoomState
+                ),
+                pick_list(
+                    [
+                        StateFilter::All,
+                        StateFilter::Default,
+                        StateFilter::Event,
+                        StateFilter::Boss,
+                        StateFilter::Other,
+                    ],
+                    Some(state.state_filter),
+                    Message::FilterRoomStates,
+                ),
+            ]
+            .spacing(5)
+        ]
+        .push_maybe(state.state_hint.as_ref().map(|hint| {
+            text(hint)
+                .size(12)
+                .color(iced::Color::from_rgb8(200, 150, 0))
+        }))
+        .spacing(5),
+        column![
+            checkbox("Compare against another room", state.compare_room.is_some())
+                .on_toggle(Message::CompareRooms)
+        ]
+        .push_maybe(state.compare_room.as_ref().map(|room| {
+            combo_box(
+                &state.compare_room_list,
+                "",
+                Some(room),
+                Message::SelectCompareRoom,
+            )
+        }))
+        .push_maybe(tileset_normalization_controls(state))
+        .spacing(5),
+        checkbox(
+            "Compare states by condition",
+            state.match_states_by_condition
+        )
+        .on_toggle(Message::MatchStatesByCondition),
+        row![
+            checkbox("Show layer 1", state.show_layer_1).on_toggle(Message::ShowLayer1),
+            checkbox("Show layer 2", state.show_layer_2).on_toggle(Message::ShowLayer2),
+            checkbox("Show FX", state.show_fx).on_toggle(Message::ShowFX),
+        ]
+        .spacing(10),
+        layer_source_controls(state),
+        row![
+            checkbox("Highlight transparency", state.highlight_transparency)
+                .on_toggle(Message::HighlightTransparency),
+            checkbox("Show doors", state.show_doors).on_toggle(Message::ShowDoors),
+        ]
+        .spacing(10),
+        row(overlay_toggles).spacing(10),
+        baseline_controls(state),
+        palette_remap_controls(state),
+        animation_controls(state),
+        row![
+            text("Layer 2 blending").size(12),
+            pick_list(
+                [
+                    ColorMath::Off,
+                    ColorMath::HalfAdd,
+                    ColorMath::Add,
+                    ColorMath::Subtract,
+                ],
+                Some(state.color_math),
+                Message::SelectColorMath
+            )
+            .text_size(12)
+            .padding(2),
+        ]
+        .spacing(10)
+        .align_y(iced::Alignment::Center),
+        tile_search_controls(state),
+        project_search_controls(state),
+        pick_list(
+            [
+                SourceSelection::WorkingCopy,
+                SourceSelection::GitReference(state.other_name()),
+                SourceSelection::Difference
+            ]
+            .into_iter()
+            .filter(|x| state.view_mode != ViewMode::Room || state.room_images(x).is_some())
+            .collect::<Vec<_>>(),
+            Some(&state.source_selection),
+            Message::SelectSource,
+        ),
+    ]
+    .push_maybe(reference_picker(state))
+    .push_maybe((!state.config.tools.is_empty()).then(|| {
+        pick_list(
+            state.config.tools.as_slice(),
+            None::<ExternalTool>,
+            Message::RunTool,
+        )
+        .placeholder("External tools...")
+    }))
+    .push_maybe(state.single_source().then(|| {
+        text("No git repository found: showing the working copy only")
+            .size(12)
+            .style(text::success)
+    }))
+    .push_maybe((state.project_is_new && !state.single_source()).then(|| {
+        text(format!(
+            "New project (not in {}): showing the working copy only",
+            state.reference_name()
+        ))
+        .size(12)
+        .style(text::success)
+    }))
+    .push_maybe(
+        state
+            .status
+            .as_ref()
+            .map(|s| text(s).size(12).style(text::danger)),
+    )
+    .push_maybe(duplicate_room_note(state))
+    .push(panel_toggles(state))
+    .push_maybe(state.shows(Panel::Review).then(|| room_note_editor(state)))
+    .push_maybe(state.shows(Panel::Review).then(|| review_controls(state)))
+    .push_maybe(
+        state
+            .shows(Panel::Dependencies)
+            .then(|| room_dependency_list(state)),
+    )
+    .push_maybe(state.shows(Panel::Export).then(|| export_controls(state)))
+    .push_maybe(state.shows(Panel::Log).then(|| log_panel(state)))
+    .push_maybe(state.shows(Panel::ModifiedRooms).then(|| {
+        column![pick_list(
+            [
+                RoomSortOrder::ProjectName,
+                RoomSortOrder::Path,
+                RoomSortOrder::ChangeSize,
+                RoomSortOrder::LastModified,
+            ],
+            Some(state.preferences.room_sort),
+            Message::SortModifiedRooms,
+        ),]
+        .push_maybe(
+            (state.analysis.pending() > 0)
+                .then(|| text(format!("Analyzing {} rooms...", state.analysis.pending())).size(12)),
+        )
+        .push(SelectionList::new_with(
+            &state.modified_room_labels,
+            |idx, _| Message::SelectModifiedRoom(idx),
+            14.0,
+            5.0,
+            iced_aw::style::selection_list::primary,
+            state.modified_room_idx,
+            Font::default(),
+        ))
+        .spacing(10)
+    }))
+    .spacing(10);
+
+    let main_view = match state.view_mode {
+        ViewMode::Room => room_view(state),
+        ViewMode::Project => project_view(state),
+        ViewMode::Tileset => tileset_view(state),
+        ViewMode::AreaMap => area_map_view(state),
+        ViewMode::Library => library_view(state),
+        ViewMode::DoorGraph => door_graph_view(state),
+        ViewMode::AssetUsage => asset_usage_view(state),
+    };
+
+    let main_view = column![]
+        .push_maybe(restore_session_banner(state))
+        .push_maybe(malformed_asset_panel(state))
+        .push(main_view)
+        .spacing(10);
+
+    row![controls.width(state.layout.sidebar_width), main_view]
+        .spacing(10)
+        .padding(10)
+        .into()
+}
+
+// Assets that could not be decoded, in either version of the current room or the asset
+// being viewed.
+fn malformed_assets(state: &State) -> Vec<&MalformedAsset> {
+    let mut assets: Vec<&MalformedAsset> = vec![];
+    let room_assets = [&state.working_images, &state.other_images]
+        .into_iter()
+        .flatten()
+        .filter(|_| state.view_mode == ViewMode::Room)
+        .flat_map(|images| &images.malformed_assets);
+    for asset in room_assets.chain(&state.asset_error) {
+        if !assets.contains(&asset) {
+            assets.push(asset);
+        }
+    }
+    assets
+}
+
+fn malformed_asset_panel(state: &State) -> Option<Element<'_, Message>> {
+    let assets = malformed_assets(state);
+    if assets.is_empty() {
+        return None;
+    }
+    let mut panel = column![text("Malformed assets").style(text::danger)].spacing(5);
+    for asset in assets {
+        panel = panel.push(text(asset.diagnostics()).size(12).font(Font::MONOSPACE));
+    }
+    panel = panel.push(button("Copy diagnostics").on_press(Message::CopyDiagnostics));
+    Some(panel.into())
+}
+
+// Header fields of a room and of one of its states, with numbers in the chosen format.
+fn room_metadata(
+    room: &crate::smart_xml::Room,
+    state_idx: usize,
+    format: NumberFormat,
+) -> Vec<(&'static str, String)> {
+    let n = |x| format.format(x, 0);
+    let mut out = vec![
+        ("Area", n(room.area)),
+        ("Index", n(room.index)),
+        ("Map position", format!("{}, {}", n(room.x), n(room.y))),
+        ("Size", format!("{} x {}", n(room.width), n(room.height))),
+        ("Up scroller", room.upscroller.clone()),
+        ("Down scroller", room.dnscroller.clone()),
+        ("Special GFX", room.special_gfx.clone()),
+        ("Doors", n(room.doors.door.len())),
+    ];
+    if let Some(state) = room.states.state.get(state_idx) {
+        out.extend([
+            ("GFX set", format.format(state.gfx_set, 2)),
+            ("Music", state.music.clone()),
+            ("Layer 2 X scroll", state.layer2_xscroll.clone()),
+            ("Layer 2 Y scroll", state.layer2_yscroll.clone()),
+            ("Setup ASM", state.setup_asm.clone()),
+            ("Main ASM", state.main_asm.clone()),
+        ]);
+    }
+    out
+}
+
+// Header fields of the current room state, highlighting those that differ from the reference.
+fn room_metadata_view(state: &State) -> Element<'_, Message> {
+    let Some(working) = &state.working_images else {
+        return column![].into();
+    };
+    let idx = state.room_state.0;
+    let format = state.preferences.number_format;
+    let working_metadata = room_metadata(&working.room_xml, idx, format);
+    let reference_metadata = state
+        .other_images
+        .as_ref()
+        .map(|x| room_metadata(&x.room_xml, idx, format));
+    let fields = working_metadata.iter().map(|(name, value)| {
+        let reference_value = reference_metadata
+            .as_ref()
+            .and_then(|m| m.iter().find(|(n, _)| n == name))
+            .map(|(_, v)| v);
+        let label = text(match reference_value {
+            Some(v) if v != value => format!("{}: {} -> {}", name, v, value),
+            _ => format!("{}: {}", name, value),
+        })
+        .size(12);
+        if reference_value.is_some_and(|v| v != value) {
+            label.style(text::danger).into()
+        } else {
+            label.into()
+        }
+    });
+    row(fields).spacing(15).wrap().into()
+}
+
+// Problems worked around while rendering either version of the room.
+fn room_warnings_view(state: &State) -> Element<'_, Message> {
+    let mut content = column![];
+    let sources = [
+        ("Working copy".to_string(), &state.working_images),
+        (state.other_name(), &state.other_images),
+    ];
+    for (source, images) in sources {
+        for warning in images.iter().flat_map(|x| &x.warnings) {
+            content = content.push(
+                text(format!("{}: {}", source, warning))
+                    .size(12)
+                    .style(text::danger),
+            );
+        }
+    }
+    for warning in &state.hook_output.warnings {
+        content = content.push(
+            text(format!("Hook: {}", warning))
+                .size(12)
+                .style(text::danger),
+        );
+    }
+    content.into()
+}
+
+// Banner for when layer 2 was added or removed in the current room state, which would
+// otherwise show up as the whole layer being different.
+fn layer_change_banner(state: &State) -> Element<'_, Message> {
+    let (Some(working), Some(reference)) = (&state.working_images, &state.other_images) else {
+        return column![].into();
+    };
+    let idx = state.room_state.0;
+    let message = match (has_layer2(working, idx), has_layer2(reference, idx)) {
+        (true, false) => "Layer 2 was added in the working copy",
+        (false, true) => "Layer 2 was removed in the working copy",
+        _ => return column![].into(),
+    };
+    row![
+        text(message).style(text::primary),
+        checkbox("Diff only common layers", state.diff_common_layers)
+            .on_toggle(Message::DiffCommonLayers),
+    ]
+    .spacing(10)
+    .into()
+}
+
+// Table of the enemy set entries that differ from the reference, across all states of
+// the room.
+fn enemy_set_table(state: &State) -> Element<'_, Message> {
+    let (Some(working), Some(reference)) = (&state.working_images, &state.other_images) else {
+        return column![].into();
+    };
+    let changes: Vec<EnemySetChange> = compare_enemy_sets(&working.room_xml, &reference.room_xml)
+        .into_iter()
+        .filter(|x| x.is_changed())
+        .collect();
+    if changes.is_empty() {
+        return column![].into();
+    }
+    let cell = |s: String| text(s).size(12).width(110);
+    let palette = |x: Option<usize>| x.map(|p| format!("{:X}", p)).unwrap_or("-".to_string());
+    let mut table = column![
+        text(format!("Enemy set changes ({})", changes.len())).size(14),
+        row![
+            cell("State".to_string()),
+            cell("Enemy".to_string()),
+            cell("Palette (ref)".to_string()),
+            cell("Palette (working)".to_string()),
+        ],
+    ];
+    for change in changes {
+        let status = match (change.reference_palette, change.working_palette) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "palette changed",
+        };
+        table = table.push(row![
+            cell(change.state_idx.to_string()).style(text::danger),
+            cell(format!("{:04X}", change.enemy_id)).style(text::danger),
+            cell(palette(change.reference_palette)).style(text::danger),
+            cell(palette(change.working_palette)).style(text::danger),
+            text(status).size(12).style(text::danger),
+        ]);
+    }
+    table.into()
+}
+
+fn zoomable_scrollbars() -> scrollable::Direction {
+    let scrollbar = Scrollbar::new()
+        .width(SCROLLBAR_WIDTH)
+        .scroller_width(SCROLLBAR_WIDTH);
+    scrollable::Direction::Both {
+        vertical: scrollbar,
+        horizontal: scrollbar,
+    }
+}
+
+fn room_view(state: &State) -> Element<'_, Message> {
+    let (width, height) = state.room_size().unwrap_or((256, 256));
+
+    column![room_warnings_view(state), layer_change_banner(state)]
+        .push_maybe(
+            state
+                .shows(Panel::Metadata)
+                .then(|| room_metadata_view(state)),
+        )
+        .push_maybe(state.shows(Panel::Enemies).then(|| enemy_set_table(state)))
+        .push_maybe(state_thumbnails(state))
+        .push_maybe(region_panel(state))
+        .push(with_rulers(
+            state,
+            Scrollable::with_direction(
+                canvas(RoomCanvas { state })
+                    .width(state.zoomed(width) + 2.0 * ROOM_MATTE + SCROLLBAR_WIDTH)
+                    .height(state.zoomed(height) + 2.0 * ROOM_MATTE + SCROLLBAR_WIDTH),
+                zoomable_scrollbars(),
+            )
+            .id(room_scrollable_id())
+            .on_scroll(|viewport| {
+                Message::RoomScrolled(viewport.absolute_offset(), viewport.bounds().size())
+            })
+            .width(Length::Fill)
+            .into(),
+        ))
+        .spacing(10)
+        .into()
+}
+
+fn with_rulers<'a>(state: &'a State, room: Element<'a, Message>) -> Element<'a, Message> {
+    if !state.shows(Panel::Rulers) {
+        return room;
+    }
+    let ruler = |vertical| canvas(RulerCanvas { state, vertical });
+    column![
+        row![
+            iced::widget::Space::new(RULER_SIZE, RULER_SIZE),
+            ruler(false).width(Length::Fill).height(RULER_SIZE),
+        ],
+        row![ruler(true).width(RULER_SIZE).height(Length::Fill), room].height(Length::Fill),
+    ]
+    .into()
+}
+
+// The selected asset's image from the selected source.
+fn comparison_view<'a>(state: &'a State, comparison: &'a ComparisonImages) -> Element<'a, Message> {
+    let handle = match state.displayed_source() {
+        SourceSelection::WorkingCopy => Some(&comparison.working),
+        SourceSelection::GitReference(_) => comparison.reference.as_ref(),
+        SourceSelection::Difference => comparison.diff.as_ref(),
+    };
+    let content: Element<Message> = match handle {
+        Some(sized) => image(sized.handle.clone())
+            .filter_method(image::FilterMethod::Nearest)
+            .width(state.zoomed(sized.width))
+            .height(state.zoomed(sized.height))
+            .into(),
+        None => text("Not available for this source").into(),
+    };
+    column![
+        row![
+            button("Back").on_press(Message::OpenComparison(None)),
+            text(comparison.kind.to_string()).size(20),
+        ]
+        .spacing(10),
+    ]
+    .push_maybe(
+        comparison
+            .palette
+            .as_ref()
+            .map(|(working, reference)| palette_view(state, working, reference.as_deref())),
+    )
+    .push(
+        Scrollable::with_direction(
+            container(content).padding(
+                iced::Padding::ZERO
+                    .right(SCROLLBAR_WIDTH)
+                    .bottom(SCROLLBAR_WIDTH),
+            ),
+            zoomable_scrollbars(),
+        )
+        .width(Length::Fill),
+    )
+    .spacing(10)
+    .into()
+}
+
+// Swatches of a tileset's palette, a line of 16 colors per row, with the raw 15-bit value and
+// RGB of each entry. Entries which differ from the other version are outlined, with the other
+// version's value.
+fn palette_view<'a>(
+    state: &'a State,
+    working: &'a [u16],
+    reference: Option<&'a [u16]>,
+) -> Element<'a, Message> {
+    let (shown, other) = match (state.displayed_source(), reference) {
+        (SourceSelection::GitReference(_), Some(reference)) => (reference, Some(working)),
+        _ => (working, reference),
+    };
+    let marker = rgb(state.preferences.color_preset.colors().marker);
+    let swatch = |i: usize, value: u16| -> Element<'a, Message> {
+        let [r, g, b] = decode_color(value);
+        let other_value = other.map(|other| other.get(i).copied());
+        let changed = other_value.is_some_and(|v| v != Some(value));
+        let color = iced::Color::from_rgb8(r, g, b);
+        let square = container(text(""))
+            .width(24)
+            .height(24)
+            .style(move |_: &Theme| container::Style {
+                background: Some(color.into()),
+                border: iced::Border {
+                    color: marker,
+                    width: if changed { 3.0 } else { 0.0 },
+                    radius: 0.0.into(),
+                },
+                ..container::Style::default()
+            });
+        column![
+            square,
+            text(format!("{:04X}", value)).size(10),
+            text(format!("{},{},{}", r, g, b)).size(9),
+        ]
+        .push_maybe(changed.then(|| {
+            text(match other_value.flatten() {
+                Some(v) => format!("vs {:04X}", v),
+                None => "vs none".to_string(),
+            })
+            .size(9)
+            .style(text::danger)
+        }))
+        .width(56)
+        .spacing(1)
+        .into()
+    };
+    let lines = shown.chunks(16).enumerate().map(|(line, colors)| {
+        row![text(format!("{:X}", line)).size(12).width(16)]
+            .extend(
+                colors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &value)| swatch(line * 16 + i, value)),
+            )
+            .spacing(2)
+            .into()
+    });
+    let changed_count = other.map(|other| {
+        (0..shown.len().max(other.len()))
+            .filter(|&i| shown.get(i) != other.get(i))
+            .count()
+    });
+    let summary = match changed_count {
+        Some(0) => format!("Palette: {} colors, unchanged", shown.len()),
+        Some(n) => format!("Palette: {} colors, {} differ", shown.len(), n),
+        None => format!("Palette: {} colors (not in the reference)", shown.len()),
+    };
+    // Palettes with many lines scroll rather than crowding out the tileset.
+    container(Scrollable::new(
+        column![text(summary).size(14)].extend(lines).spacing(4),
+    ))
+    .max_height(400)
+    .into()
+}
+
+// A cached thumbnail, or a placeholder of the same size while it is rendered.
+fn thumbnail_view<'a>(state: &'a State, key: &ThumbnailKey) -> Element<'a, Message> {
+    let size = THUMBNAIL_SIZE as f32;
+    match state.thumbnails.get(key) {
+        Some(handle) => image(handle.clone())
+            .width(size)
+            .height(size)
+            .filter_method(image::FilterMethod::Nearest)
+            .into(),
+        None => container(
+            text(if state.thumbnails.error(key).is_some() {
+                "Unavailable"
+            } else {
+                "..."
+            })
+            .size(12),
+        )
+        .center(size)
+        .into(),
+    }
+}
+
+// Thumbnails of the states of the current room, for switching between them.
+fn state_thumbnails(state: &State) -> Option<Element<'_, Message>> {
+    let states = state.room_state_list.options();
+    if !state.shows(Panel::StateThumbnails) || states.len() < 2 {
+        return None;
+    }
+    let thumbnails = states.iter().map(|room_state| {
+        let key = ThumbnailKey::RoomState {
+            project: state.project.0.clone(),
+            room: state.room.clone(),
+            state_idx: room_state.0,
+        };
+        button(column![thumbnail_view(state, &key), text(room_state.0).size(12)].spacing(2))
+            .style(if *room_state == state.room_state {
+                button::secondary
+            } else {
+                button::text
+            })
+            .on_press(Message::SelectRoomState(room_state.clone()))
+            .into()
+    });
+    Some(row(thumbnails).spacing(5).wrap().into())
+}
+
+// Grid of tileset thumbnails, or the selected tileset's (or CRE graphics) sheet.
+fn tileset_view(state: &State) -> Element<'_, Message> {
+    if let Some(comparison) = &state.comparison {
+        return comparison_view(state, comparison);
+    }
+
+    let thumbnails = state.tileset_list.iter().map(|&idx| {
+        let key = ThumbnailKey::Tileset {
+            project: state.project.0.clone(),
+            idx,
+        };
+        button(column![thumbnail_view(state, &key), text(format!("{:02X}", idx))].spacing(5))
+            .style(button::text)
+            .on_press(Message::OpenComparison(Some(ComparisonKind::Tileset(idx))))
+            .into()
+    });
+    Scrollable::new(
+        column![
+            button("CRE graphics")
+                .on_press(Message::OpenComparison(Some(ComparisonKind::CreGraphics))),
+            row(thumbnails).spacing(10).wrap(),
+        ]
+        .spacing(10),
+    )
+    .width(Length::Fill)
+    .into()
+}
+
+// List of areas, or the selected area's map.
+fn area_map_view(state: &State) -> Element<'_, Message> {
+    if let Some(comparison) = &state.comparison {
+        return comparison_view(state, comparison);
+    }
+    if state.area_list.is_empty() {
+        return text("No area maps found in Export/Maps").into();
+    }
+
+    let areas = state.area_list.iter().map(|&area| {
+        button(text(format!("Area {:02X}", area)))
+            .on_press(Message::OpenComparison(Some(ComparisonKind::AreaMap(area))))
+            .into()
+    });
+    row(areas).spacing(10).wrap().into()
+}
+
+// List of shared backgrounds, or the selected background.
+fn library_view(state: &State) -> Element<'_, Message> {
+    if let Some(comparison) = &state.comparison {
+        return comparison_view(state, comparison);
+    }
+    if state.background_list.is_empty() {
+        return text("No backgrounds found in Export/Library/BG3").into();
+    }
+
+    let backgrounds = state.background_list.iter().map(|&idx| {
+        button(text(format!("Background {:02X}", idx)))
+            .on_press(Message::OpenComparison(Some(ComparisonKind::Background(
+                idx,
+            ))))
+            .into()
+    });
+    row(backgrounds).spacing(10).wrap().into()
+}
+
+// Maximum number of lines shown in a textual diff
+const MAX_DIFF_LINES: usize = 5000;
+
+fn file_diff_view<'a>(
+    path: &'a std::path::Path,
+    lines: &'a [DiffLine],
+    colors: DiffColors,
+) -> Element<'a, Message> {
+    let mut content = column![].spacing(0);
+    for line in lines.iter().take(MAX_DIFF_LINES) {
+        let label = match line.origin {
+            '+' | '-' | ' ' => format!("{}{}", line.origin, line.content),
+            _ => line.content.clone(),
+        };
+        let label = text(label).font(Font::MONOSPACE).size(13);
+        content = content.push(match line.origin {
+            '+' => label.color(rgb(colors.added)),
+            '-' => label.color(rgb(colors.removed)),
+            'H' | 'F' => label.style(text::primary),
+            _ => label,
+        });
+    }
+    if lines.len() > MAX_DIFF_LINES {
+        content = content.push(text(format!(
+            "... {} more lines not shown",
+            lines.len() - MAX_DIFF_LINES
+        )));
+    }
+    column![
+        row![
+            button("Back").on_press(Message::CloseFileDiff),
+            text(path.display().to_string()).size(20),
+        ]
+        .spacing(10),
+        Scrollable::with_direction(
+            content,
+            scrollable::Direction::Both {
+                vertical: Scrollbar::default(),
+                horizontal: Scrollbar::default(),
+            },
+        )
+        .width(Length::Fill),
+    ]
+    .spacing(10)
+    .into()
+}
+
+// Number of unchanged rows shown around each differing row in a hex diff
+const HEX_CONTEXT_ROWS: usize = 2;
+
+// Maximum number of rows shown in a hex diff
+const MAX_HEX_ROWS: usize = 4000;
+
+// Hex bytes of one side of a hex diff row, with bytes that differ from the other
+// side drawn in `color`.
+fn hex_spans<'a>(
+    row: &HexRow,
+    bytes: &[Option<u8>],
+    color: iced::Color,
+) -> Vec<text::Span<'a, Message, Font>> {
+    (0..HEX_ROW_WIDTH)
+        .map(|i| {
+            let s = match bytes[i] {
+                Some(b) => format!("{:02X} ", b),
+                None => "   ".to_string(),
+            };
+            let s = span(s).font(Font::MONOSPACE);
+            if row.byte_differs(i) {
+                s.color(color)
+            } else {
+                s
+            }
+        })
+        .collect()
+}
+
+// Room names by area and index
+type RoomIds = HashMap<(usize, usize), String>;
+
+// Outline of doors whose door list entry is unchanged
+const DOOR_COLOR: [u8; 3] = [255, 160, 0];
+
+fn door_region(door: DoorTiles) -> Region {
+    Region {
+        x: door.x,
+        y: door.y,
+        width: door.width,
+        height: door.height,
+    }
+}
+
+// A door list entry, e.g. "Door 2: to landingSite (area 0, room 0), direction 4, exit tile 1, 26".
+fn describe_door(state: &State, door_idx: usize, entry: Option<&crate::smart_xml::Door>) -> String {
+    let Some(entry) = entry else {
+        return format!("Door {}: not in the door list", door_idx);
+    };
+    let target = state
+        .room_ids
+        .as_ref()
+        .and_then(|(_, ids)| ids.get(&(entry.room_area, entry.room_index)))
+        .map_or("unknown room", |name| name.as_str());
+    let format = state.preferences.number_format;
+    format!(
+        "Door {}: to {} (area {:X}, room {:X}), direction {:X}, exit tile {}, {}",
+        door_idx,
+        target,
+        entry.room_area,
+        entry.room_index,
+        entry.direction,
+        format.format(entry.tile_x, 0),
+        format.format(entry.tile_y, 0)
+    )
+}
+
+// Load the names of the current project's rooms by area and index, if doors are shown and
+// they haven't been loaded for this project.
+fn refresh_room_ids(state: &mut State) {
+    if !state.show_doors
+        || state
+            .room_ids
+            .as_ref()
+            .is_some_and(|(p, _)| *p == state.project)
+    {
+        return;
+    }
+    let mut ids = HashMap::new();
+    for room in state.room_list.options() {
+        if let Ok(xml) = load_room(&state.project.0, room, &LocalFileSystem {}) {
+            ids.insert((xml.area, xml.index), room.clone());
+        }
+    }
+    state.room_ids = Some((state.project.clone(), ids));
+}
+
+fn rgb(color: [u8; 3]) -> iced::Color {
+    iced::Color::from_rgb8(color[0], color[1], color[2])
+}
+
+fn hex_diff_view<'a>(
+    path: &'a std::path::Path,
+    rows: &'a [HexRow],
+    colors: DiffColors,
+) -> Element<'a, Message> {
+    let mut content = column![
+        text(format!(
+            "{:<10}{:<width$}  {}",
+            "Offset",
+            "Working",
+            "Reference",
+            width = HEX_ROW_WIDTH * 3
+        ))
+        .font(Font::MONOSPACE)
+        .size(13)
+    ]
+    .spacing(0);
+    if rows.is_empty() {
+        content = content.push(text("Files are identical").size(13));
+    }
+    let mut prev_offset: Option<usize> = None;
+    for row in rows.iter().take(MAX_HEX_ROWS) {
+        if prev_offset.is_some_and(|x| x + HEX_ROW_WIDTH != row.offset) {
+            content = content.push(text("...").font(Font::MONOSPACE).size(13));
+        }
+        prev_offset = Some(row.offset);
+        let mut spans = vec![span(format!("{:08X}  ", row.offset)).font(Font::MONOSPACE)];
+        spans.extend(hex_spans(row, &row.working, rgb(colors.added)));
+        spans.push(span("  ").font(Font::MONOSPACE));
+        spans.extend(hex_spans(row, &row.reference, rgb(colors.removed)));
+        content = content.push(rich_text(spans).size(13));
+    }
+    if rows.len() > MAX_HEX_ROWS {
+        content = content.push(text(format!(
+            "... {} more rows not shown",
+            rows.len() - MAX_HEX_ROWS
+        )));
+    }
+    column![
+        row![
+            button("Back").on_press(Message::CloseFileDiff),
+            text(path.display().to_string()).size(20),
+        ]
+        .spacing(10),
+        Scrollable::with_direction(
+            content,
+            scrollable::Direction::Both {
+                vertical: Scrollbar::default(),
+                horizontal: Scrollbar::default(),
+            },
+        )
+        .width(Length::Fill),
+    ]
+    .spacing(10)
+    .into()
+}
+
+// Field-level comparison of the current project's project.xml.
+fn project_settings_table<'a>(
+    state: &'a State,
+    settings: &'a [SettingChange],
+) -> Element<'a, Message> {
+    let changed = settings.iter().filter(|x| x.is_changed()).count();
+    let mut content = column![
+        row![
+            text(format!(
+                "{}/project.xml ({} changed settings)",
+                state.project, changed
+            ))
+            .size(16),
+            checkbox("Show unchanged", state.show_unchanged_settings)
+                .on_toggle(Message::ShowUnchangedSettings),
+        ]
+        .spacing(10)
+    ]
+    .spacing(2);
+    for setting in settings {
+        if !setting.is_changed() && !state.show_unchanged_settings {
+            continue;
+        }
+        let value = |x: &Option<String>| x.clone().unwrap_or_else(|| "(none)".to_string());
+        let label = if setting.is_changed() {
+            format!(
+                "{}: {} -> {}",
+                setting.key,
+                value(&setting.reference),
+                value(&setting.working)
+            )
+        } else {
+            format!("{}: {}", setting.key, value(&setting.working))
+        };
+        let label = text(label).size(13);
+        content = content.push(if setting.is_changed() {
+            label.style(text::danger)
+        } else {
+            label
+        });
+    }
+    content.padding([0, 10]).into()
+}
+
+// A rectangle of 16x16 tiles in the room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Region {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+impl RoomCanvas<'_> {
+    // Tile under a point on the canvas, clamped to the room.
+    fn tile_at(&self, pos: Point) -> (usize, usize) {
+        let Some((width, height)) = self.state.room_size() else {
+            return (0, 0);
+        };
+        let tile_size = 16.0 * self.state.pixel_size;
+        let tile = |p: f32, len: usize| {
+            (((p - ROOM_MATTE) / tile_size).max(0.0) as usize).min((len / 16).saturating_sub(1))
+        };
+        (tile(pos.x, width), tile(pos.y, height))
+    }
+
+    // The tiles covered by a drag from `start` to `end`; a click without dragging clears
+    // the selection.
+    fn region(&self, start: Point, end: Point) -> Option<Region> {
+        if start.distance(end) < 3.0 {
+            return None;
+        }
+        let (x0, y0) = self.tile_at(start);
+        let (x1, y1) = self.tile_at(end);
+        Some(Region {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            width: x0.abs_diff(x1) + 1,
+            height: y0.abs_diff(y1) + 1,
+        })
+    }
+
+    // Enlarged previews of the working copy and reference versions of the tile under the
+    // cursor, next to it, if the tile changed.
+    fn draw_hover_previews(
+        &self,
+        frame: &mut canvas::Frame,
+        pos: Point,
+        palette: &iced::theme::palette::Extended,
+    ) {
+        let state = self.state;
+        let idx = state.room_state.0;
+        let (x, y) = self.tile_at(pos);
+        let (Some(working), Some(other)) = (&state.working_images, &state.other_images) else {
+            return;
+        };
+        if !tile_changed(working, other, idx, x, y) {
+            return;
+        }
+        let size = 16.0 * HOVER_PREVIEW_SCALE;
+        let label_height = 14.0;
+        let width = 2.0 * size + 3.0 * 4.0;
+        let height = size + label_height + 2.0 * 4.0;
+        // Keep the previews within the visible part of the room view.
+        let visible = Rectangle::new(
+            Point::new(state.room_scroll.x, state.room_scroll.y),
+            state.room_viewport,
+        );
+        let mut origin = pos + iced::Vector::new(16.0, 16.0);
+        if origin.x + width > visible.x + visible.width {
+            origin.x = pos.x - 16.0 - width;
+        }
+        if origin.y + height > visible.y + visible.height {
+            origin.y = pos.y - 16.0 - height;
+        }
+        frame.fill_rectangle(
+            origin,
+            Size::new(width, height),
+            palette.background.base.color,
+        );
+        let versions = [
+            ("Working".to_string(), working),
+            (state.other_name(), other),
+        ];
+        for (i, (name, images)) in versions.into_iter().enumerate() {
+            let dest = Rectangle::new(
+                origin + iced::Vector::new(4.0 + i as f32 * (size + 4.0), 4.0 + label_height),
+                Size::new(size, size),
+            );
+            frame.fill_text(canvas::Text {
+                content: name,
+                position: dest.position() - iced::Vector::new(0.0, label_height),
+                color: palette.background.base.text,
+                size: iced::Pixels(11.0),
+                ..canvas::Text::default()
+            });
+            let [r, g, b] = images.backdrop.get(idx).copied().unwrap_or([0, 0, 0]);
+            frame.fill_rectangle(
+                dest.position(),
+                dest.size(),
+                iced::Color::from_rgb8(r, g, b),
+            );
+            let layers: Vec<room::Image> = [
+                (state.show_layer_2, &images.layer2),
+                (state.show_layer_1, &images.layer1),
+                (
+                    state.show_layer_1 && state.show_layer_2,
+                    &images.layer2_front,
+                ),
+            ]
+            .into_iter()
+            .filter(|(show, _)| *show)
+            .filter_map(|(_, layer)| layer.get(idx))
+            .map(|layer| crop(layer, x * 16, y * 16, 16, 16))
+            .collect();
+            if layers.is_empty() {
+                continue;
+            }
+            let tile = composite(&layers.iter().collect::<Vec<_>>());
+            let handle =
+                image::Handle::from_rgba(tile.width as u32, tile.height as u32, tile.pixels);
+            frame.draw_image(
+                dest,
+                canvas::Image::new(handle).filter_method(image::FilterMethod::Nearest),
+            );
+        }
+    }
+
+    // Outline each door of the room state (in the "added" color if its door list entry
+    // changed), and describe the one under the cursor.
+    fn draw_doors(
+        &self,
+        frame: &mut canvas::Frame,
+        hovered: Option<Point>,
+        palette: &iced::theme::palette::Extended,
+    ) {
+        let state = self.state;
+        let idx = state.room_state.0;
+        let Some(working) = &state.working_images else {
+            return;
+        };
+        let colors = state.preferences.color_preset.colors();
+        let reference_doors = state.other_images.as_ref().map(|o| &o.room_xml.doors.door);
+        let mut description = None;
+        for door in door_tiles(&working.room_xml, idx) {
+            let entry = working.room_xml.doors.door.get(door.door_idx);
+            let reference_entry = reference_doors.map(|d| d.get(door.door_idx));
+            let changed = reference_entry.is_some_and(|r| r != entry);
+            let color = if changed { colors.added } else { DOOR_COLOR };
+            let rect = self.region_rect(door_region(door));
+            frame.fill_rectangle(
+                rect.position(),
+                rect.size(),
+                iced::Color {
+                    a: 0.25,
+                    ..rgb(color)
+                },
+            );
+            frame.stroke(
+                &canvas::Path::rectangle(rect.position(), rect.size()),
+                canvas::Stroke::default()
+                    .with_color(rgb(color))
+                    .with_width(2.0),
+            );
+            if hovered.is_some_and(|p| rect.contains(p)) {
+                let mut lines = vec![describe_door(state, door.door_idx, entry)];
+                if let Some(reference_entry) = reference_entry.filter(|_| changed) {
+                    lines.push(format!(
+                        "Was: {}",
+                        describe_door(state, door.door_idx, reference_entry)
+                    ));
+                }
+                description = Some(lines.join("\n"));
+            }
+        }
+        let (Some(pos), Some(description)) = (hovered, description) else {
+            return;
+        };
+        let lines = description.lines().count() as f32;
+        let longest = description.lines().map(|l| l.len()).max().unwrap_or(0) as f32;
+        let size = Size::new(longest * 7.0 + 8.0, lines * 16.0 + 8.0);
+        let origin = pos + iced::Vector::new(16.0, 16.0);
+        frame.fill_rectangle(origin, size, palette.background.base.color);
+        frame.fill_text(canvas::Text {
+            content: description,
+            position: origin + iced::Vector::new(4.0, 4.0),
+            color: palette.background.base.text,
+            size: iced::Pixels(12.0),
+            ..canvas::Text::default()
+        });
+    }
+
+    fn region_rect(&self, region: Region) -> Rectangle {
+        let state = self.state;
+        Rectangle::new(
+            Point::new(
+                ROOM_MATTE + state.zoomed(region.x * 16),
+                ROOM_MATTE + state.zoomed(region.y * 16),
+            ),
+            Size::new(
+                state.zoomed(region.width * 16),
+                state.zoomed(region.height * 16),
+            ),
+        )
+    }
+}
+
+// Whether a 16x16 tile of a room state differs between the working copy and the reference,
+// in either layer.
+fn tile_changed(working: &RoomImages, other: &RoomImages, idx: usize, x: usize, y: usize) -> bool {
+    let layer_changed = |a: Option<&room::Image>, b: Option<&room::Image>| {
+        let (Some(a), Some(b)) = (a, b) else {
+            return true;
+        };
+        if (a.width, a.height) != (b.width, b.height) {
+            return true;
+        }
+        (y * 16..(y * 16 + 16).min(a.height)).any(|row| {
+            let start = (row * a.width + x * 16) * 4;
+            let end = (row * a.width + (x * 16 + 16).min(a.width)) * 4;
+            a.pixels[start..end] != b.pixels[start..end]
+        })
+    };
+    layer_changed(working.layer1.get(idx), other.layer1.get(idx))
+        || layer_changed(working.layer2.get(idx), other.layer2.get(idx))
+}
+
+// Where the searched tile occurs in the current room state, in reading order, with whether
+// it occurs there in the working copy and in the reference.
+fn tile_occurrences(state: &State) -> Vec<(usize, usize, bool, bool)> {
+    let (Some(tile), Some(working)) = (state.tile_search, &state.working_images) else {
+        return vec![];
+    };
+    let idx = state.room_state.0;
+    let width = working.room_xml.width * 16;
+    let working_tiles = layer1_tile_grid(&working.room_xml, idx);
+    let reference_tiles = state
+        .other_images
+        .as_ref()
+        .filter(|r| r.room_xml.width == working.room_xml.width)
+        .map(|r| layer1_tile_grid(&r.room_xml, idx))
+        .unwrap_or_default();
+    (0..working_tiles.len().max(reference_tiles.len()))
+        .filter_map(|i| {
+            let in_working = working_tiles.get(i).copied().flatten() == Some(tile);
+            let in_reference = reference_tiles.get(i).copied().flatten() == Some(tile);
+            (in_working || in_reference).then_some((i % width, i / width, in_working, in_reference))
+        })
+        .collect()
+}
+
+fn tile_search_controls(state: &State) -> Element<'_, Message> {
+    let mut controls = row![
+        text_input(
+            &format!("Find tile ({})", state.preferences.number_format.name()),
+            &state.tile_search_text
+        )
+        .on_input(Message::SearchTile)
+        .size(12)
+        .width(110),
+    ]
+    .spacing(5)
+    .align_y(iced::Alignment::Center);
+    if state.tile_search.is_some() {
+        let occurrences = tile_occurrences(state);
+        controls = controls.extend([
+            button(text("<").size(12))
+                .on_press_maybe((!occurrences.is_empty()).then_some(Message::StepTileSearch(-1)))
+                .into(),
+            button(text(">").size(12))
+                .on_press_maybe((!occurrences.is_empty()).then_some(Message::StepTileSearch(1)))
+                .into(),
+            text(if occurrences.is_empty() {
+                "Not found".to_string()
+            } else {
+                format!(
+                    "{} of {}",
+                    state.tile_search_idx.min(occurrences.len() - 1) + 1,
+                    occurrences.len()
+                )
+            })
+            .size(12)
+            .into(),
+        ]);
+    }
+    column![
+        controls,
+        text("Right-click a tile of the room to find it").size(10)
+    ]
+    .spacing(2)
+    .into()
+}
+
+// A search of every room of the current project's working copy, which runs in the
+// background with results added as each room is done.
+struct ProjectSearch {
+    // Distinguishes the results of this search from those of earlier ones still running
+    generation: u64,
+    project: Project,
+    target: SearchTarget,
+    rooms_total: usize,
+    rooms_done: usize,
+    hits: Vec<(String, SearchHit)>,
+}
+
+fn search_project(state: &mut State, target: SearchTarget) -> Task<Message> {
+    let generation = state
+        .project_search
+        .as_ref()
+        .map_or(0, |s| s.generation + 1);
+    let rooms = state.room_list.options().to_vec();
+    state.project_search = Some(ProjectSearch {
+        generation,
+        project: state.project.clone(),
+        target,
+        rooms_total: rooms.len(),
+        rooms_done: 0,
+        hits: vec![],
+    });
+    let project = state.project.0.clone();
+    let (sender, receiver) = iced::futures::channel::mpsc::unbounded();
+    std::thread::spawn(move || {
+        for room in rooms {
+            let result = load_room(&project, &room, &LocalFileSystem {})
+                .map(|xml| search_room(&xml, target))
+                .map_err(|e| format!("{:#}", e));
+            if sender.unbounded_send((room, result)).is_err() {
+                break;
+            }
+        }
+    });
+    Task::run(receiver, move |(room, result)| {
+        Message::ProjectSearchProgress(generation, room, result)
+    })
+}
+
+fn project_search_controls(state: &State) -> Element<'_, Message> {
+    // Listing more hits than this isn't useful, and would slow down the UI.
+    const MAX_LISTED_HITS: usize = 200;
+    let mut content = column![
+        text("Search project").size(12),
+        row![
+            pick_list(
+                [SearchKind::Tile, SearchKind::Bts],
+                Some(state.project_search_kind),
+                Message::SelectProjectSearchKind
+            )
+            .text_size(12),
+            text_input(
+                &format!("Value ({})", state.preferences.number_format.name()),
+                &state.project_search_text
+            )
+            .on_input(Message::EditProjectSearch)
+            .on_submit(Message::SearchProject)
+            .size(12)
+            .width(90),
+            button(text("Search").size(12)).on_press(Message::SearchProject),
+        ]
+        .spacing(5)
+        .align_y(iced::Alignment::Center),
+    ]
+    .spacing(2);
+    let Some(search) = &state.project_search else {
+        return content.into();
+    };
+    let rooms_with_hits = search
+        .hits
+        .iter()
+        .map(|(room, _)| room)
+        .collect::<HashSet<_>>()
+        .len();
+    content = content.push(
+        text(format!(
+            "{}: {} uses in {} rooms{}",
+            search.target.describe(state.preferences.number_format),
+            search.hits.len(),
+            rooms_with_hits,
+            if search.rooms_done < search.rooms_total {
+                format!(
+                    " (searched {}/{} rooms...)",
+                    search.rooms_done, search.rooms_total
+                )
+            } else {
+                String::new()
+            }
+        ))
+        .size(12),
+    );
+    let hits = search
+        .hits
+        .iter()
+        .take(MAX_LISTED_HITS)
+        .enumerate()
+        .map(|(i, (room, hit))| {
+            button(
+                text(format!(
+                    "{} state {} ({}, {})",
+                    room,
+                    hit.state_idx,
+                    state.preferences.number_format.format(hit.x, 0),
+                    state.preferences.number_format.format(hit.y, 0)
+                ))
+                .size(12),
+            )
+            .style(button::text)
+            .padding(0)
+            .on_press(Message::OpenSearchHit(i))
+            .into()
+        });
+    content = content.push(Scrollable::new(column(hits)).height(150));
+    content.into()
+}
+
+struct RegionStats {
+    // Tiles whose pixels differ from the reference (if available)
+    changed_tiles: Option<usize>,
+    // Tiles whose BTS differs from the reference (if available)
+    changed_bts: Option<usize>,
+    palette_lines: u8,
+}
+
+fn region_stats(state: &State, region: Region) -> Option<RegionStats> {
+    let working = state.working_images.as_ref()?;
+    let idx = state.room_state.0;
+    let width = working.room_xml.width * 16;
+    let in_region = |i: usize| region.contains(i % width, i / width);
+    let palette_lines = working
+        .palette_lines
+        .get(idx)?
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| in_region(i))
+        .fold(0, |acc, (_, &mask)| acc | mask);
+    let reference = state.other_images.as_ref().filter(|r| {
+        (r.room_xml.width, r.room_xml.height) == (working.room_xml.width, working.room_xml.height)
+    });
+    let changed_tiles = reference.map(|reference| {
+        changed_tiles(working, reference, idx)
+            .into_iter()
+            .filter(|&(x, y)| region.contains(x, y))
+            .count()
+    });
+    let changed_bts = reference.map(|reference| {
+        let working_bts = bts_grid(&working.room_xml, idx);
+        let reference_bts = bts_grid(&reference.room_xml, idx);
+        working_bts
+            .iter()
+            .zip(reference_bts.iter())
+            .enumerate()
+            .filter(|&(i, (a, b))| a != b && in_region(i))
+            .count()
+    });
+    Some(RegionStats {
+        changed_tiles,
+        changed_bts,
+        palette_lines,
+    })
+}
+
+fn region_panel(state: &State) -> Option<Element<'_, Message>> {
+    let region = state.region?;
+    let stats = region_stats(state, region)?;
+    let count = |x: Option<usize>| x.map_or("-".to_string(), |x| x.to_string());
+    let palette_lines: Vec<String> = (0..8)
+        .filter(|i| stats.palette_lines & (1 << i) != 0)
+        .map(|i| i.to_string())
+        .collect();
+    Some(
+        row![
+            text(format!(
+                "Region ({}, {}) {} x {} tiles: {} changed tiles, {} changed BTS, palette lines {}",
+                state.preferences.number_format.format(region.x, 0),
+                state.preferences.number_format.format(region.y, 0),
+                region.width,
+                region.height,
+                count(stats.changed_tiles),
+                count(stats.changed_bts),
+                if palette_lines.is_empty() {
+                    "none".to_string()
+                } else {
+                    palette_lines.join(", ")
+                },
+            ))
+            .size(12),
+            button(text("Save region image").size(12)).on_press(Message::SaveRegionImage),
+            button(text("Needs attention").size(12)).on_press(Message::BookmarkScreens),
+            button(text("Clear").size(12)).on_press(Message::SelectRegion(None)),
+        ]
+        .spacing(10)
+        .align_y(iced::Alignment::Center)
+        .into(),
+    )
+}
+
+// Save the selected region of the displayed source (with the visible layers and overlays)
+// into the export folder.
+fn save_region_image(state: &State) -> Result<PathBuf> {
+    let Some(region) = state.region else {
+        bail!("No region selected");
+    };
+    let idx = state.room_state.0;
+    let Some(working) = &state.working_images else {
+        bail!("The room is not available");
+    };
+    // Each visible layer from its own source, as drawn. Layer 4 here is the part of layer 2
+    // in front of layer 1.
+    let layer_image = |layer: usize| -> Option<room::Image> {
+        let pick = |images: &RoomImages| {
+            let layers = match layer {
+                1 => &images.layer1,
+                2 => &images.layer2,
+                4 => &images.layer2_front,
+                _ => &images.fx,
+            };
+            layers.get(idx).cloned()
+        };
+        // Layer 3 (FX) is always from the selected source.
+        let source = match layer {
+            3 => state.displayed_source(),
+            4 => state.layer_source(2),
+            _ => state.layer_source(layer),
+        };
+        match source {
+            SourceSelection::WorkingCopy => pick(working),
+            SourceSelection::GitReference(_) => pick(state.other_images.as_ref()?),
+            SourceSelection::Difference => Some(diff_image(
+                &pick(working)?,
+                &pick(state.other_images.as_ref()?)?,
+                state.difference_baseline,
+            )),
+        }
+    };
+    let mut layers = vec![];
+    for (layer, shown) in [
+        (2, state.show_layer_2),
+        (1, state.show_layer_1),
+        (4, state.show_layer_1 && state.show_layer_2),
+    ] {
+        if shown {
+            layers.extend(layer_image(layer));
+        }
+    }
+    if layers.is_empty() {
+        bail!("The selected source is not available for this room");
+    }
+    if state.show_fx {
+        layers.extend(layer_image(3));
+    }
+    layers.extend(render_overlays(state).into_iter().flatten());
+    let image = composite(&layers.iter().collect::<Vec<_>>());
+    let image = crop(
+        &image,
+        region.x * 16,
+        region.y * 16,
+        region.width * 16,
+        region.height * 16,
+    );
+    let path = PathBuf::from(&state.export_dir)
+        .join(state.project_key(&state.project.0))
+        .join(&state.room)
+        .join(format!(
+            "{}_region_{:X}_{:X}_{}x{}.png",
+            idx, region.x, region.y, region.width, region.height
+        ));
+    state
+        .write_guard
+        .apply("region export", &[planned_png(&image, path.clone())?])?;
+    Ok(path)
+}
+
+// Summary of all changed files, grouped by category.
+fn project_view(state: &State) -> Element<'_, Message> {
+    if let Some((path, lines)) = &state.file_diff {
+        return file_diff_view(path, lines, state.preferences.color_preset.colors());
+    }
+    if let Some((path, rows)) = &state.hex_diff {
+        return hex_diff_view(path, rows, state.preferences.color_preset.colors());
+    }
+
+    let mut content = column![
+        text(format!(
+            "{} changed files compared to {}",
+            state.changed_files.len(),
+            state.reference_name()
+        ))
+        .size(20)
+    ]
+    .spacing(5);
+    if let Some(settings) = &state.project_settings {
+        content = content.push(project_settings_table(state, settings));
+    }
+    for category in ChangeCategory::ALL {
+        let files: Vec<(usize, &ChangedFile)> = state
+            .changed_files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.category == category)
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        content = content.push(text(format!("{} ({})", category, files.len())).size(16));
+        for (idx, file) in files {
+            content = content.push(
+                button(text(file.to_string()))
+                    .style(button::text)
+                    .padding([0, 10])
+                    .on_press(Message::OpenChangedFile(idx)),
+            );
+        }
+    }
+    Scrollable::new(content).width(Length::Fill).into()
+}
+
+fn title(state: &State) -> String {
+    fill_placeholders(
+        state,
+        state
+            .config
+            .window_title
+            .as_deref()
+            .unwrap_or(DEFAULT_WINDOW_TITLE),
+    )
+}
+
+fn theme(_state: &State) -> Theme {
+    match dark_light::detect().unwrap_or(dark_light::Mode::Unspecified) {
+        dark_light::Mode::Light => Theme::Light,
+        dark_light::Mode::Dark | dark_light::Mode::Unspecified => Theme::Dark,
+    }
+}
+
+fn subscription(state: &State) -> Subscription<Message> {
+    let mut subscriptions = vec![iced::event::listen().map(Message::Event)];
+    if let Some(port) = state.control_port {
+        subscriptions.push(control::listen(port).map(Message::ControlCommand));
+    }
+    Subscription::batch(subscriptions)
+}
+
+pub fn run(overlays: OverlayRegistry) -> Result<()> {
+    let mut args = Args::parse();
+    let mut open_args = None;
+    match args.command.take() {
+        Some(Command::Snapshot(snapshot_args)) => {
+            let config = Config::load(std::path::Path::new(CONFIG_FILENAME))?;
+            let repo = Repository::open(".").ok();
+            let projects: Vec<PathBuf> =
+                find_projects(&project_discovery(&args, &config), repo.as_ref())?
+                    .into_iter()
+                    .map(|p| p.0)
+                    .collect();
+            let guard = WriteGuard {
+                dry_run: args.dry_run,
+            };
+            return snapshot::run(&snapshot_args, &projects, &guard);
+        }
+        Some(Command::Report(report_args)) => {
+            let config = Config::load(std::path::Path::new(CONFIG_FILENAME))?;
+            let repo = Repository::open(".").ok();
+            let projects: Vec<PathBuf> =
+                find_projects(&project_discovery(&args, &config), repo.as_ref())?
+                    .into_iter()
+                    .map(|p| p.0)
+                    .collect();
+            let guard = WriteGuard {
+                dry_run: args.dry_run,
+            };
+            return report::run(&report_args, &projects, &guard);
+        }
+        Some(Command::Open(open)) => {
+            args.reference = Some(format!("{}^", open.commit));
+            open_args = Some(open);
+        }
+        None => {}
+    }
+
+    let dry_run = args.dry_run;
+    let state = get_initial_state(args, overlays)?;
+    let open_room = match &open_args {
+        Some(open_args) => {
+            let project = project_for_commit_room(&state, open_args)?;
+            Task::done(Message::ControlCommand(control::Command::Open {
+                project: project.to_string(),
+                room: open_args.room.clone(),
+                reply: None,
+            }))
+        }
+        None => Task::none(),
+    };
+
+    let window_size = Size {
+        width: state.layout.width,
+        height: state.layout.height,
+    };
+    let maximize = if state.layout.maximized {
+        window::get_latest().and_then(|id| window::maximize(id, true))
+    } else {
+        Task::none()
+    };
+    iced::application(title, update, view)
+        .theme(theme)
+        .subscription(subscription)
+        .window_size(window_size)
+        .run_with(|| {
+            (
+                state,
+                Task::batch([query_scale_factor(), maximize, open_room]),
+            )
+        })?;
+    if !dry_run {
+        session::mark_clean(Path::new(SESSION_FILENAME))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::{fmt::Display, path::Path};
+
+use log::info;
+
+use crate::{
+    file_system::FileSystem,
+    room::{load_room, room_state_name},
+};
+
+// An asset which may be shared between many rooms. A tileset's palette is loaded along with
+// it (so is used by exactly the same room states), and every room state loads the CRE, so
+// neither is listed separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedAsset {
+    Tileset(usize),
+}
+
+impl Display for SharedAsset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SharedAsset::Tileset(idx) => write!(f, "Tileset {:02X}", idx),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetUser {
+    pub room: String,
+    pub state_idx: usize,
+    pub state_name: String,
+    pub gfx_set: usize,
+}
+
+impl Display for AssetUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (state {})", self.room, self.state_name)
+    }
+}
+
+// Which rooms states use which assets, built by scanning the GFXset of every room state.
+#[derive(Debug, Clone, Default)]
+pub struct AssetUsage {
+    pub users: Vec<AssetUser>,
+}
+
+impl AssetUsage {
+    pub fn scan<F: FileSystem + ?Sized>(
+        project_dir: &Path,
+        rooms: &[String],
+        file_system: &F,
+    ) -> Self {
+        let mut users: Vec<AssetUser> = vec![];
+        for room in rooms {
+            let room_xml = match load_room(project_dir, room, file_system) {
+                Ok(x) => x,
+                Err(e) => {
+                    info!("Skipping room {} in asset usage: {:?}", room, e);
+                    continue;
+                }
+            };
+            for (i, state_xml) in room_xml.states.state.iter().enumerate() {
+                users.push(AssetUser {
+                    room: room.clone(),
+                    state_idx: i,
+                    state_name: room_state_name(state_xml),
+                    gfx_set: state_xml.gfx_set,
+                });
+            }
+        }
+        AssetUsage { users }
+    }
+
+    pub fn users_of(&self, asset: SharedAsset) -> Vec<&AssetUser> {
+        self.users
+            .iter()
+            .filter(|u| match asset {
+                SharedAsset::Tileset(idx) => u.gfx_set == idx,
+            })
+            .collect()
+    }
+}
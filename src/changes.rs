@@ -0,0 +1,177 @@
+use std::{collections::BTreeSet, fmt::Display, path::PathBuf};
+
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::project_settings::flatten_xml;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeCategory {
+    Room,
+    Tileset,
+    Palette,
+    Asm,
+    Other,
+}
+
+impl ChangeCategory {
+    pub const ALL: [ChangeCategory; 5] = [
+        ChangeCategory::Room,
+        ChangeCategory::Tileset,
+        ChangeCategory::Palette,
+        ChangeCategory::Asm,
+        ChangeCategory::Other,
+    ];
+}
+
+impl Display for ChangeCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeCategory::Room => write!(f, "Rooms"),
+            ChangeCategory::Tileset => write!(f, "Tilesets"),
+            ChangeCategory::Palette => write!(f, "Palettes"),
+            ChangeCategory::Asm => write!(f, "ASM"),
+            ChangeCategory::Other => write!(f, "Other"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedFile {
+    pub category: ChangeCategory,
+    pub path: PathBuf,
+    pub status: git2::Delta,
+}
+
+impl Display for ChangedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self.status {
+            git2::Delta::Added | git2::Delta::Untracked => "A",
+            git2::Delta::Deleted => "D",
+            git2::Delta::Renamed => "R",
+            _ => "M",
+        };
+        write!(f, "{} {}", status, self.path.display())
+    }
+}
+
+pub fn categorize(path: &std::path::Path) -> ChangeCategory {
+    let components: Vec<&str> = path
+        .components()
+        .filter_map(|c| c.as_os_str().to_str())
+        .collect();
+    let in_export_dir = |name: &str| {
+        components
+            .windows(2)
+            .any(|w| w[0] == "Export" && w[1] == name)
+    };
+    let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+    if in_export_dir("Rooms") && extension == "xml" {
+        ChangeCategory::Room
+    } else if in_export_dir("Tileset")
+        && path.file_name().and_then(|x| x.to_str()) == Some("palette.snes")
+    {
+        ChangeCategory::Palette
+    } else if in_export_dir("Tileset") {
+        ChangeCategory::Tileset
+    } else if extension.eq_ignore_ascii_case("asm") {
+        ChangeCategory::Asm
+    } else {
+        ChangeCategory::Other
+    }
+}
+
+// What part of a room changed, for triaging modified rooms without rendering them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RoomChangeKind {
+    Layer1,
+    // Layer 2 tiles, its scroll settings, or the BG data used in its place
+    Layer2,
+    Bts,
+    Fx,
+    // Everything else: room header, state conditions, PLMs, enemies, doors, ...
+    Metadata,
+}
+
+impl Display for RoomChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoomChangeKind::Layer1 => write!(f, "L1"),
+            RoomChangeKind::Layer2 => write!(f, "L2/BG"),
+            RoomChangeKind::Bts => write!(f, "BTS"),
+            RoomChangeKind::Fx => write!(f, "FX"),
+            RoomChangeKind::Metadata => write!(f, "meta"),
+        }
+    }
+}
+
+fn room_change_kind(key: &str) -> RoomChangeKind {
+    let components: Vec<String> = key
+        .split('/')
+        .map(|c| {
+            c.split(['[', '@'])
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+        })
+        .collect();
+    let has = |prefix: &str| components.iter().any(|c| c.starts_with(prefix));
+    if has("layer1") {
+        RoomChangeKind::Layer1
+    } else if has("layer2") || has("bgdata") {
+        RoomChangeKind::Layer2
+    } else if has("bts") {
+        RoomChangeKind::Bts
+    } else if has("fx") {
+        RoomChangeKind::Fx
+    } else {
+        RoomChangeKind::Metadata
+    }
+}
+
+// Classify the differences between two versions of a room XML by comparing their elements.
+pub fn classify_room_changes(working: &[u8], reference: &[u8]) -> Result<BTreeSet<RoomChangeKind>> {
+    let working: HashMap<String, String> = flatten_xml(working)?.into_iter().collect();
+    let reference: HashMap<String, String> = flatten_xml(reference)?.into_iter().collect();
+    let mut kinds = BTreeSet::new();
+    for (key, value) in &working {
+        if reference.get(key) != Some(value) {
+            kinds.insert(room_change_kind(key));
+        }
+    }
+    for key in reference.keys() {
+        if !working.contains_key(key) {
+            kinds.insert(room_change_kind(key));
+        }
+    }
+    Ok(kinds)
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffLine {
+    // '+', '-', ' ' for content lines; 'H' for hunk headers; other git2 origins as-is
+    pub origin: char,
+    pub content: String,
+}
+
+// Unified diff of a single file between the reference tree and the working copy.
+pub fn file_patch(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    path: &std::path::Path,
+) -> Result<Vec<DiffLine>, git2::Error> {
+    let mut options = git2::DiffOptions::new();
+    options.pathspec(path).disable_pathspec_match(true);
+    let diff = repo.diff_tree_to_workdir_with_index(Some(tree), Some(&mut options))?;
+    let mut lines: Vec<DiffLine> = vec![];
+    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+        lines.push(DiffLine {
+            origin: line.origin(),
+            content: String::from_utf8_lossy(line.content())
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        });
+        true
+    })?;
+    Ok(lines)
+}
@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_FILENAME: &str = ".smartdiff.toml";
+pub const PREFERENCES_FILENAME: &str = ".smartdiff/preferences.toml";
+// Where smartdiff keeps its files in the repository. Only room notes and the snapshot baseline
+// are meant to be committed; everything else there (preferences, the session, backups, the
+// audit log, review logs, and reports) is machine-local, and ignored by the .gitignore below.
+pub const STATE_DIR: &str = ".smartdiff";
+const STATE_GITIGNORE: &str = "\
+# Written by smartdiff: everything here except room notes and the snapshot baseline is
+# machine-local state, which shouldn't be committed.
+/*
+!/.gitignore
+!/notes/
+!/snapshots/
+/snapshots/actual/
+";
+pub const DEFAULT_WINDOW_TITLE: &str = "{project}/{room} @ {ref} ↔ working";
+
+// User settings, read from `.smartdiff.toml` in the repository root.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub tools: Vec<ExternalTool>,
+    // Whether paths from git and from the file system should be matched regardless of
+    // case. Defaults to true on Windows and macOS, whose file systems usually are
+    // case-insensitive.
+    pub case_insensitive_paths: Option<bool>,
+    pub projects: ProjectDiscovery,
+    // Template for the window title, with the same placeholders as tool arguments.
+    pub window_title: Option<String>,
+    pub window: WindowLayout,
+    pub hooks: Vec<Hook>,
+    // Whether to compare against the current branch's upstream (e.g. origin/main), rather
+    // than HEAD, when no reference is given.
+    pub prefer_upstream: bool,
+    // Path to ffmpeg, which encodes exported videos (by default, found on the PATH)
+    pub ffmpeg: Option<String>,
+    // Git references to compare particular projects against instead of the one given on
+    // the command line, by project path (or directory name), e.g.:
+    //
+    // [project_references]
+    // "Hack2" = "origin/hack2-dev"
+    pub project_references: BTreeMap<String, String>,
+}
+
+// Where to look for SMART projects (directories containing project.xml), e.g.:
+//
+// [projects]
+// include = ["Hack*"]
+// exclude = ["**/backup*", "vendor"]
+// max_depth = 2
+//
+// Patterns are globs matched against project paths relative to the repository root.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProjectDiscovery {
+    // If non-empty, only projects matching one of these are listed.
+    pub include: Vec<String>,
+    // Directories matching one of these are not searched.
+    pub exclude: Vec<String>,
+    // How many directory levels below the repository root to search.
+    pub max_depth: Option<usize>,
+    // Whether to skip directories ignored by git (e.g. build output).
+    pub respect_gitignore: bool,
+}
+
+impl Default for ProjectDiscovery {
+    fn default() -> Self {
+        Self {
+            include: vec![],
+            exclude: vec![],
+            max_depth: None,
+            respect_gitignore: true,
+        }
+    }
+}
+
+// Initial window geometry and layout, e.g.:
+//
+// [window]
+// width = 1920
+// height = 1080
+// maximized = false
+// sidebar_width = 400
+// zoom = 2
+// hidden_panels = ["enemies", "export"]
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct WindowLayout {
+    pub width: f32,
+    pub height: f32,
+    pub maximized: bool,
+    pub sidebar_width: f32,
+    // Pixel size of the room view
+    pub zoom: f32,
+    pub hidden_panels: Vec<Panel>,
+}
+
+impl Default for WindowLayout {
+    fn default() -> Self {
+        Self {
+            width: 1440.0,
+            height: 960.0,
+            maximized: false,
+            sidebar_width: 350.0,
+            zoom: 1.0,
+            hidden_panels: vec![Panel::Log],
+        }
+    }
+}
+
+// Parts of the UI which can be hidden.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Panel {
+    // Room header and state details above the room view
+    Metadata,
+    Enemies,
+    Dependencies,
+    Review,
+    Export,
+    ModifiedRooms,
+    // Thumbnails of the room's states above the room view
+    StateThumbnails,
+    // Tile and screen coordinates along the edges of the room view
+    Rulers,
+    // Recent log messages (hidden unless shown)
+    Log,
+}
+
+impl std::fmt::Display for Panel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Panel::Metadata => write!(f, "Metadata"),
+            Panel::Enemies => write!(f, "Enemies"),
+            Panel::Dependencies => write!(f, "Dependencies"),
+            Panel::Review => write!(f, "Review"),
+            Panel::Export => write!(f, "Export"),
+            Panel::ModifiedRooms => write!(f, "Modified rooms"),
+            Panel::StateThumbnails => write!(f, "State thumbnails"),
+            Panel::Rulers => write!(f, "Rulers"),
+            Panel::Log => write!(f, "Log"),
+        }
+    }
+}
+
+// A command that can be launched for the current room, e.g.:
+//
+// [[tools]]
+// name = "Open in SMART"
+// command = ["SMART.exe", "{project}/project.xml"]
+//
+// Each argument may contain the placeholders {project}, {room}, {room_path}, and {ref}.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExternalTool {
+    pub name: String,
+    pub command: Vec<String>,
+}
+
+// A script run after a room is rendered, for project-specific checks, e.g.:
+//
+// [[hooks]]
+// event = "diff-computed"
+// command = ["python", "checks/spikes.py", "{room_path}"]
+//
+// Arguments may contain the same placeholders as tools. See `hooks.rs` for what the
+// script receives and what it may print.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub event: HookEvent,
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookEvent {
+    // The working copy of a room was rendered.
+    RoomRendered,
+    // The working copy and reference of a room were both rendered and compared.
+    DiffComputed,
+}
+
+impl std::fmt::Display for ExternalTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tools: vec![],
+            case_insensitive_paths: None,
+            projects: ProjectDiscovery::default(),
+            window_title: None,
+            window: WindowLayout::default(),
+            hooks: vec![],
+            prefer_upstream: true,
+            ffmpeg: None,
+            project_references: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    // Key under which to compare a path with paths from other sources.
+    pub fn path_key(&self, path: &Path) -> PathBuf {
+        let case_insensitive = self
+            .case_insensitive_paths
+            .unwrap_or(cfg!(any(windows, target_os = "macos")));
+        if case_insensitive {
+            PathBuf::from(path.to_string_lossy().to_lowercase())
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read config at {}", path.display()))?;
+        toml::from_str(&s).with_context(|| format!("Unable to parse config at {}", path.display()))
+    }
+}
+
+// Choices made in the UI which are remembered between runs, in
+// `.smartdiff/preferences.toml`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Preferences {
+    pub room_sort: RoomSortOrder,
+    pub color_preset: ColorPreset,
+    pub number_format: NumberFormat,
+    // Git references most recently compared against, newest first
+    pub recent_references: Vec<String>,
+}
+
+// Colors used to mark differences, with presets that remain distinguishable with
+// common kinds of color blindness.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorPreset {
+    #[default]
+    Standard,
+    Deuteranopia,
+    Protanopia,
+    Tritanopia,
+}
+
+impl std::fmt::Display for ColorPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorPreset::Standard => write!(f, "Standard colors"),
+            ColorPreset::Deuteranopia => write!(f, "Deuteranopia-safe colors"),
+            ColorPreset::Protanopia => write!(f, "Protanopia-safe colors"),
+            ColorPreset::Tritanopia => write!(f, "Tritanopia-safe colors"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffColors {
+    // Changed pixels in difference images
+    pub changed: [u8; 3],
+    // Things only in the working copy
+    pub added: [u8; 3],
+    // Things only in the reference
+    pub removed: [u8; 3],
+    // Outlines of changed tiles
+    pub outline: [u8; 3],
+    // Regions flagged by hook scripts
+    pub marker: [u8; 3],
+}
+
+impl ColorPreset {
+    // The color-blind presets are taken from the Okabe-Ito palette.
+    pub fn colors(self) -> DiffColors {
+        match self {
+            ColorPreset::Standard => DiffColors {
+                changed: [255, 255, 255],
+                added: [0, 200, 0],
+                removed: [220, 0, 0],
+                outline: [255, 0, 0],
+                marker: [255, 160, 0],
+            },
+            // Red and green are confused, so use blue against orange.
+            ColorPreset::Deuteranopia | ColorPreset::Protanopia => DiffColors {
+                changed: [255, 255, 255],
+                added: [0, 114, 178],
+                removed: [230, 159, 0],
+                outline: [240, 228, 66],
+                marker: [204, 121, 167],
+            },
+            // Blue and green (and yellow and violet) are confused, so use green against red.
+            ColorPreset::Tritanopia => DiffColors {
+                changed: [255, 255, 255],
+                added: [0, 158, 115],
+                removed: [213, 94, 0],
+                outline: [204, 121, 167],
+                marker: [86, 180, 233],
+            },
+        }
+    }
+}
+
+// How coordinates, tile indices and BTS values are shown (and typed in searches).
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NumberFormat {
+    #[default]
+    Hex,
+    Decimal,
+}
+
+impl std::fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberFormat::Hex => write!(f, "Hex numbers"),
+            NumberFormat::Decimal => write!(f, "Decimal numbers"),
+        }
+    }
+}
+
+impl NumberFormat {
+    // A value padded to `hex_digits` digits in hex (decimal values aren't padded).
+    pub fn format(self, value: usize, hex_digits: usize) -> String {
+        match self {
+            NumberFormat::Hex => format!("{:0width$X}", value, width = hex_digits),
+            NumberFormat::Decimal => value.to_string(),
+        }
+    }
+
+    pub fn parse(self, s: &str) -> Option<usize> {
+        let s = s.trim();
+        match self {
+            NumberFormat::Hex => usize::from_str_radix(s.trim_start_matches("0x"), 16).ok(),
+            NumberFormat::Decimal => s.parse().ok(),
+        }
+    }
+
+    // Name of the format for labels, e.g. "Find tile (hex)".
+    pub fn name(self) -> &'static str {
+        match self {
+            NumberFormat::Hex => "hex",
+            NumberFormat::Decimal => "decimal",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RoomSortOrder {
+    #[default]
+    ProjectName,
+    Path,
+    // Largest changes first
+    ChangeSize,
+    // Most recently modified first
+    LastModified,
+}
+
+impl std::fmt::Display for RoomSortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoomSortOrder::ProjectName => write!(f, "Sort by project/name"),
+            RoomSortOrder::Path => write!(f, "Sort by path"),
+            RoomSortOrder::ChangeSize => write!(f, "Sort by change size"),
+            RoomSortOrder::LastModified => write!(f, "Sort by last modified"),
+        }
+    }
+}
+
+const MAX_RECENT_REFERENCES: usize = 5;
+
+impl Preferences {
+    pub fn add_recent_reference(&mut self, reference: &str) {
+        self.recent_references.retain(|r| r != reference);
+        self.recent_references.insert(0, reference.to_string());
+        self.recent_references.truncate(MAX_RECENT_REFERENCES);
+    }
+
+    // Unreadable preferences are only worth a warning, as they are easily chosen again.
+    pub fn load(path: &Path) -> Self {
+        let Ok(s) = std::fs::read_to_string(path) else {
+            return Self::default();
+        };
+        toml::from_str(&s).unwrap_or_else(|e| {
+            warn!("Ignoring preferences at {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        create_state_dir()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create directory {}", parent.display()))?;
+        }
+        std::fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Unable to save preferences to {}", path.display()))
+    }
+}
+
+// Create the state directory, with a .gitignore (unless it already has one) which keeps
+// machine-local state out of commits.
+pub fn create_state_dir() -> Result<()> {
+    std::fs::create_dir_all(STATE_DIR)
+        .with_context(|| format!("Unable to create directory {}", STATE_DIR))?;
+    let gitignore = Path::new(STATE_DIR).join(".gitignore");
+    if !gitignore.exists() {
+        std::fs::write(&gitignore, STATE_GITIGNORE)
+            .with_context(|| format!("Unable to write {}", gitignore.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc as std_mpsc,
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use iced::{
+    Subscription,
+    futures::{channel::mpsc, future},
+};
+use log::{error, info};
+
+// Commands accepted on the local control socket, one per line, e.g.:
+//   open MyProject landingSite
+// Each is answered with "ok" once it has been applied, or "error: ..." if it couldn't be.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Open {
+        project: String,
+        room: String,
+        reply: Option<Reply>,
+    },
+}
+
+// Where the result of applying a command is sent, for commands from the control socket.
+pub type Reply = std_mpsc::Sender<Result<(), String>>;
+
+// How long to wait for a command to be applied before answering the client.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn parse_command(line: &str, reply: Reply) -> Result<Command> {
+    let mut parts = line.trim().splitn(3, ' ');
+    match parts.next() {
+        Some("open") => {
+            let (Some(project), Some(room)) = (parts.next(), parts.next()) else {
+                bail!("Usage: open PROJECT ROOM");
+            };
+            Ok(Command::Open {
+                project: project.to_string(),
+                room: room.trim().to_string(),
+                reply: Some(reply),
+            })
+        }
+        Some(cmd) if !cmd.is_empty() => bail!("Unknown command: {}", cmd),
+        _ => bail!("Empty command"),
+    }
+}
+
+fn handle_connection(stream: TcpStream, mut sender: mpsc::Sender<Command>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let (reply, result) = std_mpsc::channel();
+        match parse_command(&line, reply) {
+            Ok(cmd) => {
+                sender.try_send(cmd)?;
+                match result.recv_timeout(REPLY_TIMEOUT) {
+                    Ok(Ok(())) => writeln!(writer, "ok")?,
+                    Ok(Err(e)) => writeln!(writer, "error: {}", e)?,
+                    Err(_) => writeln!(writer, "error: the command was not applied")?,
+                }
+            }
+            Err(e) => {
+                writeln!(writer, "error: {}", e)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_listener(port: u16, sender: mpsc::Sender<Command>) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Unable to listen for commands on port {}: {}", port, e);
+            return;
+        }
+    };
+    info!("Listening for commands on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, sender) {
+                        error!("Error handling control connection: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Error accepting control connection: {}", e);
+            }
+        }
+    }
+}
+
+pub fn listen(port: u16) -> Subscription<Command> {
+    Subscription::run_with_id(
+        port,
+        iced::stream::channel(100, move |sender| async move {
+            std::thread::spawn(move || run_listener(port, sender));
+            future::pending::<()>().await;
+        }),
+    )
+}
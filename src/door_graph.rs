@@ -0,0 +1,145 @@
+use std::path::Path;
+
+use hashbrown::{HashMap, HashSet};
+use log::info;
+
+use crate::{file_system::FileSystem, room::load_room, smart_xml};
+
+#[derive(Debug, Clone)]
+pub struct DoorGraphNode {
+    pub name: String,
+    pub area: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeStatus {
+    Unchanged,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone)]
+pub struct DoorGraphEdge {
+    pub from: usize,
+    pub to: usize,
+    pub door_idx: usize,
+    pub status: EdgeStatus,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DoorGraph {
+    pub nodes: Vec<DoorGraphNode>,
+    pub edges: Vec<DoorGraphEdge>,
+}
+
+// Doors of one version of the project, as (room, door index, target room).
+type DoorSet = HashSet<(String, usize, String)>;
+
+fn collect_doors<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    rooms: &[String],
+    file_system: &F,
+) -> (HashMap<String, smart_xml::Room>, DoorSet) {
+    let mut room_xmls: HashMap<String, smart_xml::Room> = HashMap::new();
+    for room in rooms {
+        match load_room(project_dir, room, file_system) {
+            Ok(xml) => {
+                room_xmls.insert(room.clone(), xml);
+            }
+            Err(e) => info!("Skipping room {} in door graph: {:?}", room, e),
+        }
+    }
+    let room_by_id: HashMap<(usize, usize), String> = room_xmls
+        .iter()
+        .map(|(name, xml)| ((xml.area, xml.index), name.clone()))
+        .collect();
+    let mut doors: DoorSet = HashSet::new();
+    for (name, xml) in &room_xmls {
+        for (i, door) in xml.doors.door.iter().enumerate() {
+            if let Some(target) = room_by_id.get(&(door.room_area, door.room_index)) {
+                doors.insert((name.clone(), i, target.clone()));
+            }
+        }
+    }
+    (room_xmls, doors)
+}
+
+pub fn build_door_graph<W: FileSystem + ?Sized, R: FileSystem + ?Sized>(
+    project_dir: &Path,
+    rooms: &[String],
+    working_fs: &W,
+    reference_fs: &R,
+) -> DoorGraph {
+    let (working_rooms, working_doors) = collect_doors(project_dir, rooms, working_fs);
+    let (reference_rooms, reference_doors) = collect_doors(project_dir, rooms, reference_fs);
+
+    let mut graph = DoorGraph::default();
+    let mut node_idx: HashMap<String, usize> = HashMap::new();
+    for room in rooms {
+        let Some(xml) = working_rooms.get(room).or(reference_rooms.get(room)) else {
+            continue;
+        };
+        node_idx.insert(room.clone(), graph.nodes.len());
+        graph.nodes.push(DoorGraphNode {
+            name: room.clone(),
+            area: xml.area,
+            x: xml.x,
+            y: xml.y,
+            width: xml.width,
+            height: xml.height,
+        });
+    }
+
+    let mut add_edges = |doors: &DoorSet, other: &DoorSet, status: EdgeStatus| {
+        for door in doors {
+            if status == EdgeStatus::Unchanged || !other.contains(door) {
+                graph.edges.push(DoorGraphEdge {
+                    from: node_idx[&door.0],
+                    to: node_idx[&door.2],
+                    door_idx: door.1,
+                    status,
+                });
+            }
+        }
+    };
+    let unchanged: DoorSet = working_doors
+        .intersection(&reference_doors)
+        .cloned()
+        .collect();
+    add_edges(&unchanged, &unchanged, EdgeStatus::Unchanged);
+    add_edges(&working_doors, &reference_doors, EdgeStatus::Added);
+    add_edges(&reference_doors, &working_doors, EdgeStatus::Removed);
+    graph
+}
+
+// Size of the block reserved for each area in the layout, in map tiles.
+const AREA_BLOCK_WIDTH: usize = 68;
+const AREA_BLOCK_HEIGHT: usize = 36;
+const AREA_COLUMNS: usize = 2;
+
+impl DoorGraph {
+    // Position and size of each node as (x, y, width, height) in map tiles. Rooms are
+    // placed at their map coordinates within their area; if no room has map coordinates,
+    // they are arranged in a grid instead.
+    pub fn layout(&self) -> Vec<(usize, usize, usize, usize)> {
+        if self.nodes.iter().any(|n| n.x != 0 || n.y != 0) {
+            self.nodes
+                .iter()
+                .map(|n| {
+                    let x0 = (n.area % AREA_COLUMNS) * AREA_BLOCK_WIDTH;
+                    let y0 = (n.area / AREA_COLUMNS) * AREA_BLOCK_HEIGHT;
+                    (x0 + n.x, y0 + n.y, n.width.max(1), n.height.max(1))
+                })
+                .collect()
+        } else {
+            let columns = (self.nodes.len() as f32).sqrt().ceil().max(1.0) as usize;
+            (0..self.nodes.len())
+                .map(|i| ((i % columns) * 6, (i / columns) * 4, 4, 2))
+                .collect()
+        }
+    }
+}
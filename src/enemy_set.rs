@@ -0,0 +1,66 @@
+use crate::smart_xml;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnemySetChange {
+    pub state_idx: usize,
+    pub enemy_id: usize,
+    // Palette line the enemy's graphics are loaded with, if it is in the enemy set
+    pub working_palette: Option<usize>,
+    pub reference_palette: Option<usize>,
+}
+
+impl EnemySetChange {
+    pub fn is_changed(&self) -> bool {
+        self.working_palette != self.reference_palette
+    }
+}
+
+fn enemy_set(room: &smart_xml::Room, state_idx: usize) -> Vec<(usize, usize)> {
+    room.states
+        .state
+        .get(state_idx)
+        .map(|state| {
+            state
+                .enemy_gfx
+                .enemy
+                .iter()
+                .map(|x| (x.id, x.palette))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Compare the enemy sets (enemy graphics to load) of each state of a room. States are
+// matched by index; a state missing on one side has an empty enemy set.
+pub fn compare_enemy_sets(
+    working: &smart_xml::Room,
+    reference: &smart_xml::Room,
+) -> Vec<EnemySetChange> {
+    let num_states = working.states.state.len().max(reference.states.state.len());
+    let mut out: Vec<EnemySetChange> = vec![];
+    for state_idx in 0..num_states {
+        let working_set = enemy_set(working, state_idx);
+        let reference_set = enemy_set(reference, state_idx);
+        let palette_of =
+            |set: &[(usize, usize)], id| set.iter().find(|(x, _)| *x == id).map(|(_, p)| *p);
+        for &(enemy_id, palette) in &working_set {
+            out.push(EnemySetChange {
+                state_idx,
+                enemy_id,
+                working_palette: Some(palette),
+                reference_palette: palette_of(&reference_set, enemy_id),
+            });
+        }
+        for &(enemy_id, palette) in &reference_set {
+            if palette_of(&working_set, enemy_id).is_none() {
+                out.push(EnemySetChange {
+                    state_idx,
+                    enemy_id,
+                    working_palette: None,
+                    reference_palette: Some(palette),
+                });
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{guarded_write::PlannedWrite, room::Image};
+
+// Combine layers into a single image, with later layers drawn over earlier ones. Layers of
+// different sizes (e.g. from versions of a resized room) are aligned at the top left, in an
+// image as large as the largest.
+pub fn composite(layers: &[&Image]) -> Image {
+    let width = layers.iter().map(|l| l.width).max().unwrap_or(0);
+    let height = layers.iter().map(|l| l.height).max().unwrap_or(0);
+    let mut out = Image::new(width, height);
+    for layer in layers {
+        for y in 0..layer.height {
+            for x in 0..layer.width {
+                // Translucent pixels (e.g. of the FX layer) are blended over what's below.
+                let alpha = layer.pixels[(y * layer.width + x) * 4 + 3] as u16;
+                if alpha == 0 {
+                    continue;
+                }
+                let (top, bottom) = (layer.get_pixel(x, y), out.get_pixel(x, y));
+                let blend = |i: usize| {
+                    ((top[i] as u16 * alpha + bottom[i] as u16 * (255 - alpha)) / 255) as u8
+                };
+                out.set_pixel(x, y, [blend(0), blend(1), blend(2)]);
+            }
+        }
+    }
+    out
+}
+
+// The part of an image within a rectangle, clipped to the image.
+pub fn crop(image: &Image, x0: usize, y0: usize, width: usize, height: usize) -> Image {
+    let width = width.min(image.width.saturating_sub(x0));
+    let height = height.min(image.height.saturating_sub(y0));
+    let mut out = Image::new(width, height);
+    for y in 0..height {
+        let start = ((y0 + y) * image.width + x0) * 4;
+        out.pixels[y * width * 4..(y + 1) * width * 4]
+            .copy_from_slice(&image.pixels[start..start + width * 4]);
+    }
+    out
+}
+
+// Enlarge an image by a whole factor, repeating each pixel.
+pub fn upscale(image: &Image, factor: usize) -> Image {
+    let mut out = Image::new(image.width * factor, image.height * factor);
+    for y in 0..out.height {
+        for x in 0..out.width {
+            let i = (y * out.width + x) * 4;
+            let j = ((y / factor) * image.width + x / factor) * 4;
+            out.pixels[i..i + 4].copy_from_slice(&image.pixels[j..j + 4]);
+        }
+    }
+    out
+}
+
+pub fn encode_png(image: &Image) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = vec![];
+    let mut encoder = png::Encoder::new(&mut out, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.pixels)?;
+    writer.finish()?;
+    Ok(out)
+}
+
+pub fn save_png(image: &Image, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create directory {}", parent.display()))?;
+    }
+    std::fs::write(path, encode_png(image)?)
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+// A PNG file to be written through the `WriteGuard`.
+pub fn planned_png(image: &Image, path: PathBuf) -> Result<PlannedWrite> {
+    Ok(PlannedWrite {
+        contents: Some(
+            encode_png(image).with_context(|| format!("Unable to encode {}", path.display()))?,
+        ),
+        path,
+    })
+}
+
+// FNV-1a hash of the image contents, which (unlike std's hashers) is stable
+// across builds and platforms, so it can be stored in baselines.
+pub fn image_hash(image: &Image) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in (image.width as u32)
+        .to_le_bytes()
+        .iter()
+        .chain((image.height as u32).to_le_bytes().iter())
+        .chain(image.pixels.iter())
+    {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Check that an output directory given on the command line can be replaced: it doesn't
+// exist, is empty, or holds `marker` (a file smartdiff wrote there on an earlier run), so
+// that a mistaken `--dir` (e.g. `.`) can't delete unrelated files.
+pub fn check_output_dir(dir: &Path, marker: &str) -> Result<()> {
+    let Ok(mut entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    if entries.next().is_none() || dir.join(marker).is_file() {
+        return Ok(());
+    }
+    bail!(
+        "{} is not empty and has no {} from an earlier run, so it won't be replaced",
+        dir.display(),
+        marker
+    );
+}
+
+// Remove the empty directories under a directory (and it, if it ends up empty).
+pub fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    // Fails (harmlessly) if the directory isn't empty.
+    let _ = std::fs::remove_dir(dir);
+}
+
+// All files under a directory (none if it doesn't exist).
+pub fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut out: Vec<PathBuf> = vec![];
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                out.extend(list_files(&path));
+            } else {
+                out.push(path);
+            }
+        }
+    }
+    out.sort();
+    out
+}
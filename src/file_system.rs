@@ -1,6 +1,12 @@
-use std::path::{Path, PathBuf};
+use std::{
+    cell::RefCell,
+    ffi::OsString,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
 
 use anyhow::{Context, Result, bail};
+use hashbrown::HashMap;
 
 // Trait to abstract over whether we are using the local file system (for working copy)
 // or git tree (for comparison branch)
@@ -8,21 +14,52 @@ pub trait FileSystem {
     fn load(&self, path: &Path) -> Result<Vec<u8>>;
 }
 
+// Error for a file that doesn't exist, as opposed to one that couldn't be read.
+#[derive(Debug)]
+pub struct FileNotFound(pub String);
+
+impl std::fmt::Display for FileNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "File not found at {}", self.0)
+    }
+}
+
+impl std::error::Error for FileNotFound {}
+
+// Whether loading a file failed because it doesn't exist.
+pub fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.is::<FileNotFound>()
+            || cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
 pub struct GitTreeFileSystem<'a> {
     pub repo: &'a git2::Repository,
     pub tree: git2::Tree<'a>,
 }
 
-fn get_components(path: &Path) -> Result<Vec<String>> {
-    let mut out: Vec<String> = vec![];
-    for c in path.components().rev() {
-        let name = c.as_os_str().to_str().context(format!(
-            "Error converting path component to string: {:?}",
-            c
-        ))?;
-        out.push(name.to_string());
-    }
-    Ok(out)
+// Path components in reverse order, as bytes (which is how git stores names).
+fn get_components(path: &Path) -> Vec<Vec<u8>> {
+    path.components()
+        .rev()
+        .map(|c| c.as_os_str().as_encoded_bytes().to_vec())
+        .collect()
+}
+
+// Convert the bytes of a git path to a path. On Unix any bytes are valid; elsewhere
+// they must be UTF-8.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    Ok(PathBuf::from(std::str::from_utf8(bytes)?))
 }
 
 impl<'a> FileSystem for GitTreeFileSystem<'a> {
@@ -30,11 +67,15 @@ impl<'a> FileSystem for GitTreeFileSystem<'a> {
         // We have to manually walk the git tree in order to resolve
         // symbolic links along the way, because git2 doesn't do it.
         let mut obj = self.tree.as_object().clone();
-        let mut components: Vec<String> = get_components(path)?;
+        let mut components: Vec<Vec<u8>> = get_components(path);
         let mut parents: Vec<git2::Tree<'a>> = vec![];
-        let mut symlink_limit = 40;
+        let mut symlink_limit = SYMLINK_LIMIT;
         while let Some(name) = components.pop() {
-            if name == ".." {
+            let display_name = String::from_utf8_lossy(&name);
+            if name == b"." {
+                continue;
+            }
+            if name == b".." {
                 match parents.pop() {
                     None => {
                         bail!("Invalid reference to parent directory outside of repo");
@@ -44,14 +85,16 @@ impl<'a> FileSystem for GitTreeFileSystem<'a> {
                     }
                 }
             } else {
-                let tree = obj
-                    .as_tree()
-                    .context(format!("Parent of component '{}' is not a tree", name,))?;
+                let tree = obj.as_tree().context(format!(
+                    "Parent of component '{}' is not a tree",
+                    display_name
+                ))?;
                 let entry = tree
-                    .get_name(&name)
-                    .context(format!("Error getting component '{}'", name,))?;
+                    .iter()
+                    .find(|e| e.name_bytes() == name.as_slice())
+                    .ok_or_else(|| FileNotFound(path.display().to_string()))?;
                 let new_obj = entry
-                    .to_object(&self.repo)
+                    .to_object(self.repo)
                     .context("Error calling to_object")?;
                 if entry.filemode() == 0xA000 {
                     // Symbolic link
@@ -62,9 +105,8 @@ impl<'a> FileSystem for GitTreeFileSystem<'a> {
                     let blob = new_obj
                         .as_blob()
                         .context("Symbolic link content is not a blob")?;
-                    let content = String::from_utf8(blob.content().to_vec())?;
-                    let new_path = PathBuf::from(content);
-                    components.extend(get_components(&new_path)?);
+                    let new_path = path_from_bytes(blob.content())?;
+                    components.extend(get_components(&new_path));
                 } else {
                     parents.push(tree.clone());
                     drop(entry);
@@ -72,17 +114,174 @@ impl<'a> FileSystem for GitTreeFileSystem<'a> {
                 }
             }
         }
-        let blob = obj
-            .as_blob()
-            .context(format!("Object exists but is not a blob"))?;
+        let blob = obj.as_blob().context("Object exists but is not a blob")?;
         Ok(blob.content().to_vec())
     }
 }
 
+// Maximum number of symbolic links followed while resolving a single path
+const SYMLINK_LIMIT: usize = 40;
+
 pub struct LocalFileSystem {}
 
+// Resolve symbolic links one component at a time, the same way as GitTreeFileSystem
+// does: ".." refers to the parent of the directory reached so far (i.e. of a link's
+// target, not of the link itself), and it may not go above the starting directory.
+fn resolve_local_path(path: &Path) -> Result<PathBuf> {
+    let mut components: Vec<OsString> = path
+        .components()
+        .rev()
+        .map(|c| c.as_os_str().to_owned())
+        .collect();
+    let mut resolved = PathBuf::new();
+    let mut symlink_limit = SYMLINK_LIMIT;
+    while let Some(name) = components.pop() {
+        if name == "." {
+            continue;
+        }
+        if name == ".." {
+            if !resolved.pop() {
+                bail!("Invalid reference to parent directory outside of repo");
+            }
+            continue;
+        }
+        let next = resolved.join(&name);
+        if std::fs::symlink_metadata(&next)?.file_type().is_symlink() {
+            if symlink_limit == 0 {
+                bail!("Symlink limit reached (possibly a cyclic reference)");
+            }
+            symlink_limit -= 1;
+            // The target is relative to the directory containing the link.
+            let target = std::fs::read_link(&next)?;
+            components.extend(target.components().rev().map(|c| c.as_os_str().to_owned()));
+        } else {
+            resolved = next;
+        }
+    }
+    Ok(resolved)
+}
+
+// Files in `dir` with the given extension, sorted. A missing directory has no files.
+// Unlike glob patterns, this works with paths which are not valid UTF-8.
+pub fn list_files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("Unable to list {}", dir.display())),
+    };
+    let mut out: Vec<PathBuf> = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == extension) {
+            out.push(path);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+// Directories under `dir` (including itself) which contain a file named `name`,
+// without following symbolic links or descending into .git. Subdirectories are only
+// searched down to `max_depth` levels (if given), and those for which `skip_dir` returns
+// true are pruned along with their contents.
+pub fn find_dirs_containing(
+    dir: &Path,
+    name: &str,
+    max_depth: Option<usize>,
+    skip_dir: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut out: Vec<PathBuf> = vec![];
+    if dir.join(name).is_file() {
+        out.push(dir.to_path_buf());
+    }
+    if max_depth == Some(0) {
+        return Ok(out);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.file_name() != ".git" && !skip_dir(&entry.path()) {
+            out.extend(find_dirs_containing(
+                &entry.path(),
+                name,
+                max_depth.map(|d| d - 1),
+                skip_dir,
+            )?);
+        }
+    }
+    Ok(out)
+}
+
 impl FileSystem for LocalFileSystem {
     fn load(&self, path: &Path) -> Result<Vec<u8>> {
-        Ok(std::fs::read(path)?)
+        Ok(std::fs::read(resolve_local_path(path)?)?)
+    }
+}
+
+// Fetches files over HTTP from a raw-file URL prefix (e.g. a forge's endpoint for
+// serving raw files from a branch), with paths interpreted relative to the repo root.
+pub struct HttpFileSystem {
+    pub url_prefix: String,
+    cache: RefCell<HashMap<String, Option<Vec<u8>>>>,
+}
+
+impl HttpFileSystem {
+    pub fn new(url_prefix: &str) -> Self {
+        Self {
+            url_prefix: url_prefix.trim_end_matches('/').to_string(),
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn get_url(&self, path: &Path) -> Result<String> {
+        let mut components: Vec<String> = vec![];
+        for c in path.components() {
+            match c {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if components.pop().is_none() {
+                        bail!("Invalid reference to parent directory outside of repo");
+                    }
+                }
+                Component::Normal(name) => {
+                    let name = name.to_str().context(format!(
+                        "Error converting path component to string: {:?}",
+                        name
+                    ))?;
+                    components.push(name.to_string());
+                }
+                Component::RootDir | Component::Prefix(_) => {
+                    bail!("Absolute paths are not supported: {}", path.display());
+                }
+            }
+        }
+        Ok(format!("{}/{}", self.url_prefix, components.join("/")))
+    }
+}
+
+impl FileSystem for HttpFileSystem {
+    fn load(&self, path: &Path) -> Result<Vec<u8>> {
+        let url = self.get_url(path)?;
+        if let Some(cached) = self.cache.borrow().get(&url) {
+            return match cached {
+                Some(data) => Ok(data.clone()),
+                None => return Err(FileNotFound(url).into()),
+            };
+        }
+        let data = match ureq::get(&url).call() {
+            Ok(response) => {
+                let mut data: Vec<u8> = vec![];
+                response
+                    .into_reader()
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("Error reading response from {}", url))?;
+                Some(data)
+            }
+            Err(ureq::Error::Status(404, _)) => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Error fetching {}", url));
+            }
+        };
+        self.cache.borrow_mut().insert(url.clone(), data.clone());
+        data.ok_or_else(|| FileNotFound(url).into())
     }
 }
@@ -0,0 +1,166 @@
+// Changes that smartdiff makes to files in the working tree go through here, so that they
+// can be previewed without being made (a dry run), every file that is overwritten or
+// removed is backed up first, and there is a record of everything that was written.
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::Serialize;
+
+use crate::config::create_state_dir;
+
+pub const BACKUP_DIR: &str = ".smartdiff/backup";
+pub const AUDIT_LOG_FILENAME: &str = ".smartdiff/audit.log";
+
+#[derive(Debug, Clone)]
+pub struct PlannedWrite {
+    pub path: PathBuf,
+    // New contents, or None to remove the file
+    pub contents: Option<Vec<u8>>,
+}
+
+impl PlannedWrite {
+    // What the write would do, e.g. "Overwrite Hack/Export/Rooms/parlor.xml (12 -> 15 bytes)".
+    pub fn describe(&self) -> String {
+        let path = self.path.display();
+        let existing = std::fs::metadata(&self.path).ok().map(|m| m.len());
+        match (&self.contents, existing) {
+            (Some(c), Some(n)) => format!("Overwrite {} ({} -> {} bytes)", path, n, c.len()),
+            (Some(c), None) => format!("Create {} ({} bytes)", path, c.len()),
+            (None, Some(n)) => format!("Remove {} ({} bytes)", path, n),
+            (None, None) => format!("Leave {} absent", path),
+        }
+    }
+}
+
+// One line of the audit log (as JSON)
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    // Unix time in milliseconds
+    time: u128,
+    reason: &'a str,
+    description: &'a str,
+    path: &'a Path,
+    backup: Option<&'a Path>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteGuard {
+    // Only log what would be written
+    pub dry_run: bool,
+}
+
+impl WriteGuard {
+    // Make the writes (or in a dry run, just log them), returning a description of each.
+    pub fn apply(&self, reason: &str, writes: &[PlannedWrite]) -> Result<Vec<String>> {
+        let descriptions: Vec<String> = writes.iter().map(|w| w.describe()).collect();
+        if self.dry_run {
+            for description in &descriptions {
+                info!("Dry run ({}): {}", reason, description);
+            }
+            return Ok(descriptions);
+        }
+        create_state_dir()?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        for (write, description) in writes.iter().zip(&descriptions) {
+            let backup = back_up(&write.path, time)?;
+            match &write.contents {
+                Some(contents) => {
+                    if let Some(parent) = write.path.parent() {
+                        std::fs::create_dir_all(parent).with_context(|| {
+                            format!("Unable to create directory {}", parent.display())
+                        })?;
+                    }
+                    std::fs::write(&write.path, contents)
+                        .with_context(|| format!("Unable to write {}", write.path.display()))?;
+                }
+                None if write.path.exists() => std::fs::remove_file(&write.path)
+                    .with_context(|| format!("Unable to remove {}", write.path.display()))?,
+                None => continue,
+            }
+            log_write(&AuditEntry {
+                time,
+                reason,
+                description,
+                path: &write.path,
+                backup: backup.as_deref(),
+            })?;
+        }
+        Ok(descriptions)
+    }
+
+    // Make the writes, first removing `old_files` (e.g. the previous version of an output
+    // directory) unless they are overwritten anyway.
+    pub fn replace(
+        &self,
+        reason: &str,
+        old_files: Vec<PathBuf>,
+        mut writes: Vec<PlannedWrite>,
+    ) -> Result<Vec<String>> {
+        let written: HashSet<PathBuf> = writes.iter().map(|w| w.path.clone()).collect();
+        let removals = old_files
+            .into_iter()
+            .filter(|path| path.exists() && !written.contains(path))
+            .map(|path| PlannedWrite {
+                path,
+                contents: None,
+            });
+        writes.splice(0..0, removals);
+        self.apply(reason, &writes)
+    }
+}
+
+// Copy an existing file to {BACKUP_DIR}/{time}/{path}, returning where it went.
+fn back_up(path: &Path, time: u128) -> Result<Option<PathBuf>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let relative: PathBuf = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    // Another backup of the same file may have been made in the same millisecond.
+    let backup = (0..)
+        .map(|i| match i {
+            0 => time.to_string(),
+            i => format!("{}-{}", time, i),
+        })
+        .map(|dir| Path::new(BACKUP_DIR).join(dir).join(&relative))
+        .find(|backup| !backup.exists())
+        .unwrap();
+    if let Some(parent) = backup.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create directory {}", parent.display()))?;
+    }
+    std::fs::copy(path, &backup).with_context(|| {
+        format!(
+            "Unable to back up {} to {}",
+            path.display(),
+            backup.display()
+        )
+    })?;
+    Ok(Some(backup))
+}
+
+fn log_write(entry: &AuditEntry) -> Result<()> {
+    if let Some(parent) = Path::new(AUDIT_LOG_FILENAME).parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create directory {}", parent.display()))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_LOG_FILENAME)
+        .with_context(|| format!("Unable to open {}", AUDIT_LOG_FILENAME))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+        .with_context(|| format!("Unable to write to {}", AUDIT_LOG_FILENAME))
+}
@@ -0,0 +1,54 @@
+pub const HEX_ROW_WIDTH: usize = 16;
+
+// One row of a hex dump, with the bytes of each side at this offset (None past the
+// end of a file).
+#[derive(Debug, Clone)]
+pub struct HexRow {
+    pub offset: usize,
+    pub working: Vec<Option<u8>>,
+    pub reference: Vec<Option<u8>>,
+}
+
+impl HexRow {
+    pub fn differs(&self) -> bool {
+        self.working != self.reference
+    }
+
+    pub fn byte_differs(&self, i: usize) -> bool {
+        self.working.get(i) != self.reference.get(i)
+    }
+}
+
+// Rows of a byte-level comparison of two blobs, keeping only rows that differ along
+// with `context` unchanged rows around each of them. A missing blob is treated as empty.
+pub fn hex_diff_rows(working: &[u8], reference: &[u8], context: usize) -> Vec<HexRow> {
+    let len = working.len().max(reference.len());
+    let num_rows = len.div_ceil(HEX_ROW_WIDTH);
+    let rows: Vec<HexRow> = (0..num_rows)
+        .map(|r| {
+            let offset = r * HEX_ROW_WIDTH;
+            let bytes = |data: &[u8]| {
+                (offset..offset + HEX_ROW_WIDTH)
+                    .map(|i| data.get(i).copied())
+                    .collect::<Vec<Option<u8>>>()
+            };
+            HexRow {
+                offset,
+                working: bytes(working),
+                reference: bytes(reference),
+            }
+        })
+        .collect();
+    let mut keep = vec![false; num_rows];
+    for (r, row) in rows.iter().enumerate() {
+        if row.differs() {
+            let start = r.saturating_sub(context);
+            let end = (r + context + 1).min(num_rows);
+            keep[start..end].fill(true);
+        }
+    }
+    rows.into_iter()
+        .zip(keep)
+        .filter_map(|(row, k)| if k { Some(row) } else { None })
+        .collect()
+}
@@ -0,0 +1,116 @@
+// Hooks are external scripts which run after a room is rendered, so that project-specific
+// checks (e.g. "warn if any new spike tiles appear") can be added without modifying smartdiff.
+//
+// The script receives a JSON description of the room (`HookInput`) on stdin. Each line it
+// prints to stdout is shown as a warning, except for lines of the form
+//
+//   box STATE X Y WIDTH HEIGHT
+//
+// which mark a region (in pixels) of the given room state, drawn by the "Hook marks" overlay.
+//
+// These stand in for an embedded scripting engine: the two events take the place of its
+// on_room_rendered and on_diff_computed callbacks, and marks of overlay painting.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+use crate::config::HookEvent;
+
+#[derive(Debug, Serialize)]
+pub struct HookInput {
+    pub event: HookEvent,
+    pub project: String,
+    pub room: String,
+    pub reference: String,
+    pub states: Vec<HookRoomState>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HookRoomState {
+    pub index: usize,
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    // 16x16 tiles which differ from the reference, as [x, y]; only for "diff-computed"
+    pub changed_tiles: Option<Vec<[usize; 2]>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookMark {
+    pub state_idx: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HookOutput {
+    pub warnings: Vec<String>,
+    pub marks: Vec<HookMark>,
+}
+
+impl HookOutput {
+    pub fn extend(&mut self, other: HookOutput) {
+        self.warnings.extend(other.warnings);
+        self.marks.extend(other.marks);
+    }
+}
+
+fn parse_mark(line: &str) -> Option<HookMark> {
+    let fields: Vec<usize> = line
+        .strip_prefix("box ")?
+        .split_whitespace()
+        .map(|x| x.parse().ok())
+        .collect::<Option<_>>()?;
+    let &[state_idx, x, y, width, height] = fields.as_slice() else {
+        return None;
+    };
+    Some(HookMark {
+        state_idx,
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+fn parse_output(stdout: &str) -> HookOutput {
+    let mut output = HookOutput::default();
+    for line in stdout.lines().map(str::trim).filter(|x| !x.is_empty()) {
+        match parse_mark(line) {
+            Some(mark) => output.marks.push(mark),
+            None => output.warnings.push(line.to_string()),
+        }
+    }
+    output
+}
+
+pub fn run_hook(args: &[String], input: &HookInput) -> Result<HookOutput> {
+    let Some((program, program_args)) = args.split_first() else {
+        bail!("Hook has an empty command");
+    };
+    let input = serde_json::to_vec(input)?;
+    let mut child = Command::new(program)
+        .args(program_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Unable to run hook {:?}", args))?;
+    // Write the input from another thread, so that a script which prints before it has
+    // read all of its input can't deadlock with us.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let result = child
+        .wait_with_output()
+        .with_context(|| format!("Unable to run hook {:?}", args))?;
+    // A script is free to ignore its input.
+    let _ = writer.join();
+    if !result.status.success() {
+        bail!("Hook {:?} failed ({})", args, result.status);
+    }
+    Ok(parse_output(&String::from_utf8_lossy(&result.stdout)))
+}
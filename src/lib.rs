@@ -0,0 +1,33 @@
+pub mod analysis;
+mod app;
+pub mod asset_usage;
+pub mod changes;
+pub mod config;
+mod control;
+pub mod door_graph;
+pub mod enemy_set;
+pub mod export;
+pub mod file_system;
+pub mod guarded_write;
+pub mod hex_diff;
+pub mod hooks;
+pub mod library;
+pub mod log_capture;
+pub mod map;
+pub mod overlay;
+pub mod project_settings;
+pub mod render_session;
+mod report;
+pub mod rom;
+pub mod room;
+mod room_notes;
+mod session;
+mod session_log;
+pub mod smart_xml;
+mod snapshot;
+pub mod thumbnails;
+pub mod tile_search;
+pub mod video;
+pub mod workspace;
+
+pub use app::run;
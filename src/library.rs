@@ -0,0 +1,44 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    file_system::FileSystem,
+    map::{list_hex_indexed_files, render_2bpp_tilemap},
+    room::{Image, load_palette},
+};
+
+// Shared layer-3 backgrounds are exported to `Export/Library/BG3/` as raw tilemaps
+// named `{idx:02X}.ttb`, each one or two 32x32 pages of little-endian tilemap
+// words. They share the 2bpp graphics in `bg3tiles.gfx` and the colors in
+// `palette.snes` from the same directory.
+const LIBRARY_DIR: &str = "Export/Library/BG3";
+
+pub fn list_backgrounds(project_dir: &Path) -> Result<Vec<usize>> {
+    list_hex_indexed_files(&project_dir.join(LIBRARY_DIR), "ttb")
+}
+
+pub fn render_background<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    idx: usize,
+    file_system: &F,
+) -> Result<Image> {
+    let library_dir = project_dir.join(LIBRARY_DIR);
+    let map_path = library_dir.join(format!("{:02X}.ttb", idx));
+    let map_bytes = file_system
+        .load(&map_path)
+        .with_context(|| format!("Unable to load background at {}", map_path.display()))?;
+    if map_bytes.len() != 2048 && map_bytes.len() != 4096 {
+        bail!(
+            "Background at {} has size {} bytes (expected 2048 or 4096)",
+            map_path.display(),
+            map_bytes.len()
+        );
+    }
+    let gfx_path = library_dir.join("bg3tiles.gfx");
+    let gfx_bytes = file_system
+        .load(&gfx_path)
+        .with_context(|| format!("Unable to load background tiles at {}", gfx_path.display()))?;
+    let palette = load_palette(&library_dir.join("palette.snes"), file_system)?;
+    Ok(render_2bpp_tilemap(&map_bytes, &gfx_bytes, &palette))
+}
@@ -0,0 +1,94 @@
+// Keeps the most recent log records in memory (as well as passing them on to the usual
+// logger), so that the log panel can show them to users who didn't start smartdiff from a
+// terminal.
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Number of records kept
+const CAPACITY: usize = 500;
+
+static RECORDS: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    // Unix time in milliseconds
+    pub time: u128,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = (self.time / 1000) % 86400;
+        write!(
+            f,
+            "{:02}:{:02}:{:02}.{:03} {:5} {}: {}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.time % 1000,
+            self.level,
+            self.target,
+            self.message
+        )
+    }
+}
+
+struct CapturingLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> Log for CapturingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+        if record.level() > Level::Info {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut records = RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+        if records.len() == CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(LogRecord {
+            time,
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Install a logger which passes records on to `inner` (e.g. env_logger's) and keeps the
+// recent ones at info level or above.
+pub fn init<L: Log + 'static>(inner: L, max_level: LevelFilter) -> Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(CapturingLogger { inner }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+// The records kept so far, oldest first.
+pub fn recent() -> Vec<LogRecord> {
+    let records = RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+    records.iter().cloned().collect()
+}
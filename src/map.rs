@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use log::info;
+
+use crate::{
+    file_system::{FileSystem, list_files_with_extension},
+    room::{Color, Image, OutOfRange, decode_8x8_tile, load_palette, missing_color},
+};
+
+// Area maps are exported as raw SNES tilemaps, one per area, under
+// `Export/Maps/{area:02X}.map`: 64x32 little-endian tilemap words, stored as
+// two 32x32 pages side by side. The map tiles themselves are 2bpp graphics in
+// `Export/Maps/maptiles.gfx`, with colors from `Export/Maps/palette.snes`.
+pub const MAP_WIDTH: usize = 64;
+pub const MAP_HEIGHT: usize = 32;
+
+pub fn list_areas(project_dir: &Path) -> Result<Vec<usize>> {
+    list_hex_indexed_files(&project_dir.join("Export/Maps"), "map")
+}
+
+// Indices of the files in `dir` named `{idx:02X}.{extension}`.
+pub(crate) fn list_hex_indexed_files(dir: &Path, extension: &str) -> Result<Vec<usize>> {
+    let mut indices: Vec<usize> = vec![];
+    for path in list_files_with_extension(dir, extension)? {
+        if let Some(idx) = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+        {
+            indices.push(idx);
+        }
+    }
+    indices.sort();
+    Ok(indices)
+}
+
+fn decode_8x8_tile_data_2bpp(data: &[u8]) -> [[u8; 8]; 8] {
+    let mut out: [[u8; 8]; 8] = [[0; 8]; 8];
+    for (y, row) in out.iter_mut().enumerate() {
+        let data_0 = data[y * 2];
+        let data_1 = data[y * 2 + 1];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let bit_0 = (data_0 >> (7 - x)) & 1;
+            let bit_1 = (data_1 >> (7 - x)) & 1;
+            *pixel = bit_0 | (bit_1 << 1);
+        }
+    }
+    out
+}
+
+pub fn render_area_map<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    area: usize,
+    file_system: &F,
+) -> Result<Image> {
+    let maps_dir = project_dir.join("Export/Maps");
+    let map_path = maps_dir.join(format!("{:02X}.map", area));
+    let map_bytes = file_system
+        .load(&map_path)
+        .with_context(|| format!("Unable to load map at {}", map_path.display()))?;
+    if map_bytes.len() != MAP_WIDTH * MAP_HEIGHT * 2 {
+        bail!(
+            "Map at {} has size {} bytes (expected {})",
+            map_path.display(),
+            map_bytes.len(),
+            MAP_WIDTH * MAP_HEIGHT * 2
+        );
+    }
+    let gfx_path = maps_dir.join("maptiles.gfx");
+    let gfx_bytes = file_system
+        .load(&gfx_path)
+        .with_context(|| format!("Unable to load map tiles at {}", gfx_path.display()))?;
+    let palette = load_palette(&maps_dir.join("palette.snes"), file_system)?;
+    Ok(render_2bpp_tilemap(&map_bytes, &gfx_bytes, &palette))
+}
+
+// Render a tilemap made of 32x32 pages placed side by side, using 2bpp graphics.
+// Color 0 is left transparent, and graphics or colors out of range are drawn with the
+// placeholder pattern (and logged).
+pub(crate) fn render_2bpp_tilemap(map_bytes: &[u8], gfx_bytes: &[u8], palette: &[Color]) -> Image {
+    let gfx: Vec<[[u8; 8]; 8]> = gfx_bytes
+        .chunks_exact(16)
+        .map(decode_8x8_tile_data_2bpp)
+        .collect();
+    let num_pages = map_bytes.len().div_ceil(2048);
+    let mut image = Image::new(num_pages * 256, 256);
+    let mut out_of_range = OutOfRange::default();
+    for (i, word) in map_bytes.chunks_exact(2).enumerate() {
+        let tile = decode_8x8_tile(u16::from_le_bytes([word[0], word[1]]));
+        // The 32x32 pages are stored one after the other.
+        let page = i / 1024;
+        let x0 = (page * 32 + i % 32) * 8;
+        let y0 = ((i % 1024) / 32) * 8;
+        let Some(tile_gfx) = gfx.get(tile.idx) else {
+            out_of_range.gfx.insert(tile.idx);
+            for y in 0..8 {
+                for x in 0..8 {
+                    image.set_pixel(x0 + x, y0 + y, missing_color(x, y));
+                }
+            }
+            continue;
+        };
+        for y in 0..8 {
+            for x in 0..8 {
+                let x1 = if tile.flip_x { 7 - x } else { x };
+                let y1 = if tile.flip_y { 7 - y } else { y };
+                let c = tile_gfx[y1][x1] as usize;
+                if c == 0 {
+                    continue;
+                }
+                let color = match palette.get(tile.palette * 4 + c) {
+                    Some(&color) => color,
+                    None => {
+                        out_of_range.colors.insert(tile.palette * 4 + c);
+                        missing_color(x, y)
+                    }
+                };
+                image.set_pixel(x0 + x, y0 + y, color);
+            }
+        }
+    }
+    for problem in out_of_range.describe() {
+        info!("Tilemap: {}", problem);
+    }
+    image
+}
@@ -0,0 +1,689 @@
+use crate::{
+    config::{DiffColors, NumberFormat},
+    hooks::HookMark,
+    room::{Image, RoomImages},
+    smart_xml,
+    tile_search::{bts_grid, layer1_grid},
+};
+
+// Everything an overlay may need in order to draw itself over the currently
+// selected room state.
+pub struct RoomContext<'a> {
+    pub state_idx: usize,
+    pub working: &'a RoomImages,
+    // None when there is nothing to compare against (e.g. a new room, or no repository)
+    pub reference: Option<&'a RoomImages>,
+    // Regions flagged by hook scripts
+    pub hook_marks: &'a [HookMark],
+    pub colors: DiffColors,
+    // Zoom of the room view, in screen pixels per room pixel
+    pub pixel_size: f32,
+    pub number_format: NumberFormat,
+}
+
+impl RoomContext<'_> {
+    pub fn width(&self) -> usize {
+        self.size().0
+    }
+
+    pub fn height(&self) -> usize {
+        self.size().1
+    }
+
+    // Size of the rendered state, falling back to the room's size in screens if the
+    // state has no image.
+    fn size(&self) -> (usize, usize) {
+        match self.working.layer1.get(self.state_idx) {
+            Some(image) => (image.width, image.height),
+            None => {
+                let room = &self.working.room_xml;
+                (room.width * 256, room.height * 256)
+            }
+        }
+    }
+}
+
+// An overlay renders a (mostly transparent) image with the same dimensions as
+// the room, which is drawn on top of the selected layers.
+pub trait Overlay {
+    fn name(&self) -> &str;
+    fn render(&self, ctx: &RoomContext) -> Image;
+    fn enabled(&self) -> bool;
+    fn set_enabled(&mut self, enabled: bool);
+}
+
+#[derive(Default)]
+pub struct OverlayRegistry {
+    overlays: Vec<Box<dyn Overlay>>,
+}
+
+impl OverlayRegistry {
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry.register(GridOverlay::default());
+        registry.register(ScreenOverlay::default());
+        registry.register(DiffBoxOverlay::default());
+        registry.register(StationOverlay::default());
+        registry.register(PlmOverlay::default());
+        registry.register(EnemyOverlay::default());
+        registry.register(BtsOverlay::default());
+        registry.register(HookMarkOverlay::default());
+        registry
+    }
+
+    pub fn register<O: Overlay + 'static>(&mut self, overlay: O) {
+        self.overlays.push(Box::new(overlay));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Overlay> {
+        self.overlays.iter().map(|x| x.as_ref())
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut (dyn Overlay + 'static)> {
+        self.overlays.get_mut(idx).map(|x| x.as_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.overlays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.overlays.is_empty()
+    }
+}
+
+// Zoom below which the lines between 16x16 tiles would be less than 4 screen pixels apart
+const MIN_TILE_GRID_PIXEL_SIZE: f32 = 0.25;
+
+// Lines between the 16x16 tiles of the room, and stronger ones between screens. The tile
+// lines are left out when zoomed out too far for them to be told apart.
+#[derive(Default)]
+pub struct GridOverlay {
+    enabled: bool,
+}
+
+impl Overlay for GridOverlay {
+    fn name(&self) -> &str {
+        "Grid"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let tile_lines = ctx.pixel_size >= MIN_TILE_GRID_PIXEL_SIZE;
+        for y in 0..image.height {
+            for x in 0..image.width {
+                if x % 256 == 0 || y % 256 == 0 {
+                    image.set_pixel_alpha(x, y, [255, 255, 0], 160);
+                } else if tile_lines && (x % 16 == 0 || y % 16 == 0) {
+                    image.set_pixel_alpha(x, y, [128, 128, 128], 96);
+                }
+            }
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+// Outlines each 256x256 screen of the room, labeled with its coordinates (as in the room XML)
+// in its top-left corner.
+#[derive(Default)]
+pub struct ScreenOverlay {
+    enabled: bool,
+}
+
+impl Overlay for ScreenOverlay {
+    fn name(&self) -> &str {
+        "Screens"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let format = ctx.number_format;
+        for screen_y in 0..ctx.height() / 256 {
+            for screen_x in 0..ctx.width() / 256 {
+                let (x, y) = (screen_x * 256, screen_y * 256);
+                draw_rect(&mut image, x, y, 256, 256, ctx.colors.marker);
+                draw_rect(&mut image, x + 1, y + 1, 254, 254, ctx.colors.marker);
+                let label = format!(
+                    "({},{})",
+                    format.format(screen_x, 0),
+                    format.format(screen_y, 0)
+                );
+                draw_text(&mut image, x + 3, y + 3, &label);
+            }
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+// Outlines each 16x16 tile in which any pixel (on either layer) differs
+// between the working copy and the reference.
+#[derive(Default)]
+pub struct DiffBoxOverlay {
+    enabled: bool,
+}
+
+fn tile_differs(img1: &Image, img2: &Image, tx: usize, ty: usize) -> bool {
+    for y in ty * 16..(ty + 1) * 16 {
+        for x in tx * 16..(tx + 1) * 16 {
+            if img1.get_pixel(x, y) != img2.get_pixel(x, y)
+                || img1.get_transparent(x, y) != img2.get_transparent(x, y)
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Coordinates of the 16x16 tiles in which the given state differs between the two
+// versions, if they are comparable.
+pub fn changed_tiles(
+    working: &RoomImages,
+    reference: &RoomImages,
+    idx: usize,
+) -> Vec<(usize, usize)> {
+    let (Some(ref_layer1), Some(ref_layer2)) =
+        (reference.layer1.get(idx), reference.layer2.get(idx))
+    else {
+        return vec![];
+    };
+    let (Some(layer1), Some(layer2)) = (working.layer1.get(idx), working.layer2.get(idx)) else {
+        return vec![];
+    };
+    if (ref_layer1.width, ref_layer1.height) != (layer1.width, layer1.height) {
+        return vec![];
+    }
+    let mut out = vec![];
+    for ty in 0..layer1.height / 16 {
+        for tx in 0..layer1.width / 16 {
+            if tile_differs(layer1, ref_layer1, tx, ty) || tile_differs(layer2, ref_layer2, tx, ty)
+            {
+                out.push((tx, ty));
+            }
+        }
+    }
+    out
+}
+
+impl Overlay for DiffBoxOverlay {
+    fn name(&self) -> &str {
+        "Difference boxes"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let Some(reference) = ctx.reference else {
+            return image;
+        };
+        for (tx, ty) in changed_tiles(ctx.working, reference, ctx.state_idx) {
+            draw_rect(&mut image, tx * 16, ty * 16, 16, 16, ctx.colors.outline);
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+// Draw the outline of a rectangle, clipped to the image.
+pub fn draw_rect(
+    image: &mut Image,
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    color: [u8; 3],
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (x1, y1) = (x0.saturating_add(width - 1), y0.saturating_add(height - 1));
+    for x in x0..x0.saturating_add(width).min(image.width) {
+        for y in [y0, y1] {
+            if y < image.height {
+                image.set_pixel_alpha(x, y, color, 255);
+            }
+        }
+    }
+    for y in y0..y0.saturating_add(height).min(image.height) {
+        for x in [x0, x1] {
+            if x < image.width {
+                image.set_pixel_alpha(x, y, color, 255);
+            }
+        }
+    }
+}
+
+// Fill a rectangle with a translucent color, clipped to the image.
+pub fn fill_rect(
+    image: &mut Image,
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    color: [u8; 3],
+    alpha: u8,
+) {
+    for y in y0..y0.saturating_add(height).min(image.height) {
+        for x in x0..x0.saturating_add(width).min(image.width) {
+            image.set_pixel_alpha(x, y, color, alpha);
+        }
+    }
+}
+
+const SAVE_STATION_PLM: usize = 0xB76F;
+const ELEVATOR_ENEMY: usize = 0xD73F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StationKind {
+    Save,
+    Elevator,
+}
+
+// Save stations and elevators, with their positions in pixels.
+fn station_markers(room: &smart_xml::Room, state_idx: usize) -> Vec<(StationKind, usize, usize)> {
+    let Some(state) = room.states.state.get(state_idx) else {
+        return vec![];
+    };
+    let mut out = vec![];
+    for plm in &state.plms.plm {
+        if plm.type_ == SAVE_STATION_PLM {
+            out.push((StationKind::Save, plm.x * 16, plm.y * 16));
+        }
+    }
+    for enemy in &state.enemies.enemy {
+        if enemy.id == ELEVATOR_ENEMY {
+            // Enemy positions are of their center.
+            out.push((
+                StationKind::Elevator,
+                enemy.x.saturating_sub(8),
+                enemy.y.saturating_sub(8),
+            ));
+        }
+    }
+    out
+}
+
+// Marks save stations (cyan) and elevators (magenta). Markers added in the working copy
+// are outlined in the "added" color (green by default); markers only present in the
+// reference are outlined in the "removed" color (red by default).
+#[derive(Default)]
+pub struct StationOverlay {
+    enabled: bool,
+}
+
+impl Overlay for StationOverlay {
+    fn name(&self) -> &str {
+        "Stations"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let working = station_markers(&ctx.working.room_xml, ctx.state_idx);
+        // Without a reference, nothing is marked as added or removed.
+        let reference = ctx.reference.map_or_else(
+            || working.clone(),
+            |r| station_markers(&r.room_xml, ctx.state_idx),
+        );
+        let fill_color = |kind| match kind {
+            StationKind::Save => [0, 255, 255],
+            StationKind::Elevator => [255, 0, 255],
+        };
+        for &(kind, x, y) in &working {
+            fill_rect(&mut image, x, y, 16, 16, fill_color(kind), 128);
+            let outline = if reference.contains(&(kind, x, y)) {
+                [255, 255, 255]
+            } else {
+                ctx.colors.added
+            };
+            draw_rect(&mut image, x, y, 16, 16, outline);
+        }
+        for &(kind, x, y) in &reference {
+            if !working.contains(&(kind, x, y)) {
+                draw_rect(&mut image, x, y, 16, 16, ctx.colors.removed);
+            }
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+// Hex digits in a 3x5 pixel font, one row per byte (high bit on the left)
+const HEX_FONT: [[u8; 5]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+];
+
+// Draw a 16-bit value as 4 hex digits (15x5 pixels) on a dark band, clipped to the image.
+fn draw_hex_label(image: &mut Image, x0: usize, y0: usize, value: usize) {
+    draw_hex_digits(image, x0, y0, value, 4);
+}
+
+// Draw the low `digits` hex digits of a value on a dark band, clipped to the image.
+fn draw_hex_digits(image: &mut Image, x0: usize, y0: usize, value: usize, digits: usize) {
+    let text: String = (0..digits)
+        .rev()
+        .filter_map(|i| char::from_digit(((value >> (i * 4)) & 0xF) as u32, 16))
+        .collect();
+    draw_text(image, x0, y0, &text);
+}
+
+// The 3x5 glyph of a character, for the hex digits and the punctuation of coordinates.
+fn glyph(c: char) -> Option<[u8; 5]> {
+    match c {
+        '(' => Some([0b001, 0b010, 0b010, 0b010, 0b001]),
+        ')' => Some([0b100, 0b010, 0b010, 0b010, 0b100]),
+        ',' => Some([0b000, 0b000, 0b000, 0b010, 0b100]),
+        _ => c.to_digit(16).map(|d| HEX_FONT[d as usize]),
+    }
+}
+
+// Draw text (of the characters in `glyph`) on a dark band, clipped to the image.
+fn draw_text(image: &mut Image, x0: usize, y0: usize, text: &str) {
+    let glyphs: Vec<[u8; 5]> = text.chars().filter_map(glyph).collect();
+    fill_rect(image, x0, y0, glyphs.len() * 4, 7, [0, 0, 0], 192);
+    for (i, glyph) in glyphs.iter().enumerate() {
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3 {
+                let (x, y) = (x0 + i * 4 + col, y0 + 1 + row);
+                if bits & (4 >> col) != 0 && x < image.width && y < image.height {
+                    image.set_pixel(x, y, [255, 255, 255]);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlmKind {
+    Item,
+    DoorCap,
+    Scroll,
+    Other,
+}
+
+impl PlmKind {
+    fn of(type_: usize) -> Self {
+        match type_ {
+            0xEED7..=0xEFFF => PlmKind::Item,
+            0xC842..=0xC8CA => PlmKind::DoorCap,
+            0xB703 | 0xB63B | 0xB647 => PlmKind::Scroll,
+            _ => PlmKind::Other,
+        }
+    }
+
+    fn color(self) -> [u8; 3] {
+        match self {
+            PlmKind::Item => [255, 208, 0],
+            PlmKind::DoorCap => [255, 128, 0],
+            PlmKind::Scroll => [64, 128, 255],
+            PlmKind::Other => [160, 160, 160],
+        }
+    }
+}
+
+fn plms(room: &smart_xml::Room, state_idx: usize) -> &[smart_xml::PLM] {
+    room.states
+        .state
+        .get(state_idx)
+        .map_or(&[], |s| s.plms.plm.as_slice())
+}
+
+// Marks each PLM's tile, colored by kind (items yellow, door caps orange, scroll PLMs blue,
+// others gray) and labeled with its type. As with stations, PLMs added or changed in the
+// working copy are outlined in the "added" color, and those only in the reference are
+// outlined (and labeled) in the "removed" color.
+#[derive(Default)]
+pub struct PlmOverlay {
+    enabled: bool,
+}
+
+impl Overlay for PlmOverlay {
+    fn name(&self) -> &str {
+        "PLMs"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let working = plms(&ctx.working.room_xml, ctx.state_idx);
+        let reference = ctx
+            .reference
+            .map_or(working, |r| plms(&r.room_xml, ctx.state_idx));
+        for plm in reference.iter().filter(|p| !working.contains(p)) {
+            let (x, y) = (plm.x * 16, plm.y * 16);
+            draw_hex_label(&mut image, x, y + 9, plm.type_);
+            draw_rect(&mut image, x, y, 16, 16, ctx.colors.removed);
+        }
+        for plm in working {
+            let (x, y) = (plm.x * 16, plm.y * 16);
+            fill_rect(&mut image, x, y, 16, 16, PlmKind::of(plm.type_).color(), 96);
+            draw_hex_label(&mut image, x, y, plm.type_);
+            let outline = if reference.contains(plm) {
+                [255, 255, 255]
+            } else {
+                ctx.colors.added
+            };
+            draw_rect(&mut image, x, y, 16, 16, outline);
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+fn enemies(room: &smart_xml::Room, state_idx: usize) -> &[smart_xml::Enemy] {
+    room.states
+        .state
+        .get(state_idx)
+        .map_or(&[], |s| s.enemies.enemy.as_slice())
+}
+
+// Marks each enemy's spawn point with a box around its center, labeled with its ID, so that
+// moved or deleted enemies stand out: enemies added or moved in the working copy are outlined
+// in the "added" color, and those only in the reference (at that position) in the "removed"
+// color.
+#[derive(Default)]
+pub struct EnemyOverlay {
+    enabled: bool,
+}
+
+impl Overlay for EnemyOverlay {
+    fn name(&self) -> &str {
+        "Enemies"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let working = enemies(&ctx.working.room_xml, ctx.state_idx);
+        let reference = ctx
+            .reference
+            .map_or(working, |r| enemies(&r.room_xml, ctx.state_idx));
+        // Enemy positions are of their center.
+        let corner = |e: &smart_xml::Enemy| (e.x.saturating_sub(8), e.y.saturating_sub(8));
+        for enemy in reference.iter().filter(|e| !working.contains(e)) {
+            let (x, y) = corner(enemy);
+            draw_hex_label(&mut image, x, y + 9, enemy.id);
+            draw_rect(&mut image, x, y, 16, 16, ctx.colors.removed);
+        }
+        for enemy in working {
+            let (x, y) = corner(enemy);
+            fill_rect(&mut image, x, y, 16, 16, [192, 96, 255], 96);
+            draw_hex_label(&mut image, x, y, enemy.id);
+            let outline = if reference.contains(enemy) {
+                [255, 255, 255]
+            } else {
+                ctx.colors.added
+            };
+            draw_rect(&mut image, x, y, 16, 16, outline);
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+// Color for each block type (the top 4 bits of layer 1 data), or None for air.
+fn block_type_color(block_type: u16) -> Option<[u8; 3]> {
+    match block_type {
+        0x1 => Some([0, 160, 255]),         // slope
+        0x2 | 0xA => Some([255, 0, 64]),    // spikes
+        0x3 => Some([255, 255, 0]),         // special air (e.g. treadmills)
+        0x4 | 0xC => Some([255, 128, 0]),   // shootable
+        0x5 | 0xD => Some([128, 128, 128]), // extensions
+        0x8 => Some([255, 255, 255]),       // solid
+        0x9 => Some([0, 255, 128]),         // door
+        0xB => Some([255, 0, 255]),         // special (e.g. crumble, speed)
+        0xE => Some([0, 255, 255]),         // grapple
+        0xF => Some([160, 96, 0]),          // bomb
+        _ => None,
+    }
+}
+
+// Collision of the current state: tiles are tinted by block type and labeled with their BTS
+// value (if not 0), and tiles whose block type or BTS differs from the reference are
+// outlined.
+#[derive(Default)]
+pub struct BtsOverlay {
+    enabled: bool,
+}
+
+impl Overlay for BtsOverlay {
+    fn name(&self) -> &str {
+        "BTS"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        let room = &ctx.working.room_xml;
+        let width = room.width * 16;
+        let collision = |room, state_idx| -> Vec<Option<(u16, u16)>> {
+            layer1_grid(room, state_idx)
+                .into_iter()
+                .zip(bts_grid(room, state_idx))
+                .map(|(layer1, bts)| layer1.map(|l| (l >> 12, bts.unwrap_or(0))))
+                .collect()
+        };
+        let working = collision(room, ctx.state_idx);
+        // Differences are only marked if there is a reference of the same size.
+        let reference = ctx
+            .reference
+            .map(|r| &r.room_xml)
+            .filter(|r| (r.width, r.height) == (room.width, room.height))
+            .map(|r| collision(r, ctx.state_idx));
+        for (i, &tile) in working.iter().enumerate() {
+            let (x, y) = (i % width * 16, i / width * 16);
+            if let Some((block_type, bts)) = tile {
+                if let Some(color) = block_type_color(block_type) {
+                    fill_rect(&mut image, x, y, 16, 16, color, 64);
+                }
+                if bts != 0 {
+                    draw_hex_digits(&mut image, x, y, bts as usize, 2);
+                }
+            }
+            if reference.as_ref().is_some_and(|r| r.get(i) != Some(&tile)) {
+                draw_rect(&mut image, x, y, 16, 16, ctx.colors.outline);
+            }
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+// Outlines the regions which hook scripts flagged in the current state.
+#[derive(Default)]
+pub struct HookMarkOverlay {
+    enabled: bool,
+}
+
+impl Overlay for HookMarkOverlay {
+    fn name(&self) -> &str {
+        "Hook marks"
+    }
+
+    fn render(&self, ctx: &RoomContext) -> Image {
+        let mut image = Image::new(ctx.width(), ctx.height());
+        for mark in ctx.hook_marks {
+            // Marks come from hook output unchecked; draw_rect clips them to the room.
+            if mark.state_idx == ctx.state_idx {
+                draw_rect(
+                    &mut image,
+                    mark.x,
+                    mark.y,
+                    mark.width,
+                    mark.height,
+                    ctx.colors.marker,
+                );
+            }
+        }
+        image
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::file_system::FileSystem;
+
+#[derive(Debug, Clone)]
+pub struct SettingChange {
+    pub key: String,
+    pub working: Option<String>,
+    pub reference: Option<String>,
+}
+
+impl SettingChange {
+    pub fn is_changed(&self) -> bool {
+        self.working != self.reference
+    }
+}
+
+// Flatten an XML document into (key, value) pairs, where keys are element paths such
+// as "Project/ROM/Path" (with attributes as "Project/ROM@Name"). Repeated sibling
+// elements are distinguished by an index, e.g. "Project/Patch[2]".
+pub(crate) fn flatten_xml(data: &[u8]) -> Result<Vec<(String, String)>> {
+    let mut out: Vec<(String, String)> = vec![];
+    let mut path: Vec<String> = vec![];
+    let mut sibling_counts: Vec<HashMap<String, usize>> = vec![HashMap::new()];
+    let mut text_content = String::new();
+    for event in EventReader::new(data) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let count = sibling_counts
+                    .last_mut()
+                    .unwrap()
+                    .entry(name.local_name.clone())
+                    .or_insert(0);
+                *count += 1;
+                let component = if *count > 1 {
+                    format!("{}[{}]", name.local_name, count)
+                } else {
+                    name.local_name.clone()
+                };
+                path.push(component);
+                sibling_counts.push(HashMap::new());
+                text_content.clear();
+                for attr in attributes {
+                    out.push((
+                        format!("{}@{}", path.join("/"), attr.name.local_name),
+                        attr.value,
+                    ));
+                }
+            }
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => {
+                text_content += &s;
+            }
+            XmlEvent::EndElement { .. } => {
+                if !text_content.trim().is_empty() {
+                    out.push((path.join("/"), text_content.trim().to_string()));
+                }
+                text_content.clear();
+                path.pop();
+                sibling_counts.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+fn load_settings<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    file_system: &F,
+) -> Result<Vec<(String, String)>> {
+    let path = project_dir.join("project.xml");
+    let data = file_system
+        .load(&path)
+        .with_context(|| format!("Unable to load {}", path.display()))?;
+    flatten_xml(&data).with_context(|| format!("Unable to parse XML in {}", path.display()))
+}
+
+// Field-level comparison of project.xml. A project.xml missing from the reference
+// is treated as empty, so that all of its fields show as added.
+pub fn compare_project_settings<W: FileSystem + ?Sized, R: FileSystem + ?Sized>(
+    project_dir: &Path,
+    working_fs: &W,
+    reference_fs: &R,
+) -> Result<Vec<SettingChange>> {
+    let working = load_settings(project_dir, working_fs)?;
+    let reference = load_settings(project_dir, reference_fs).unwrap_or_default();
+    let reference_map: HashMap<&str, &str> = reference
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let mut out: Vec<SettingChange> = working
+        .iter()
+        .map(|(key, value)| SettingChange {
+            key: key.clone(),
+            working: Some(value.clone()),
+            reference: reference_map.get(key.as_str()).map(|x| x.to_string()),
+        })
+        .collect();
+    for (key, value) in &reference {
+        if !working.iter().any(|(k, _)| k == key) {
+            out.push(SettingChange {
+                key: key.clone(),
+                working: None,
+                reference: Some(value.clone()),
+            });
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,162 @@
+// Rendering rooms and comparing versions of them without the GUI, for the GUI itself as well
+// as the command-line subcommands. A session keeps decoded tilesets and recent renders,
+// which are reused as long as the files they came from are unchanged (by blob id), so that
+// e.g. a report over many commits renders each version of a room only once.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use git2::Oid;
+
+use crate::{
+    file_system::FileSystem,
+    overlay::changed_tiles,
+    room::{Image, RenderOptions, RoomImages, TilesetCache, blob_id, render_room_with_cache},
+    tile_search::bts_grid,
+};
+
+// Number of renders kept (e.g. the working copy and reference of a few rooms)
+const MAX_CACHED_RENDERS: usize = 16;
+
+struct CachedRender {
+    project: PathBuf,
+    room: String,
+    options: RenderOptions,
+    // Blob id of each of the render's dependencies, or None if it couldn't be loaded
+    dependency_ids: Vec<Option<Oid>>,
+    images: RoomImages,
+}
+
+#[derive(Default)]
+pub struct RenderSession {
+    tilesets: Mutex<TilesetCache>,
+    // Oldest first
+    renders: Mutex<Vec<CachedRender>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffResult {
+    // States whose layers or BTS differ (including states missing from the other version)
+    pub differing_states: Vec<usize>,
+    // For each state, the 16x16 tiles whose pixels differ, or none if the state can't be
+    // compared tile by tile (e.g. the room was resized)
+    pub changed_tiles: Vec<Vec<(usize, usize)>>,
+}
+
+// Blob ids of the files a room was rendered from, as they are now. The first dependency is the
+// room's XML, and the rest are tileset files.
+fn dependency_ids<F: FileSystem + ?Sized, G: FileSystem + ?Sized>(
+    dependencies: &[PathBuf],
+    room_file_system: &F,
+    tileset_file_system: &G,
+) -> Vec<Option<Oid>> {
+    dependencies
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let bytes = if i == 0 {
+                room_file_system.load(path)
+            } else {
+                tileset_file_system.load(path)
+            };
+            bytes.ok().map(|b| blob_id(&b))
+        })
+        .collect()
+}
+
+impl RenderSession {
+    pub fn render<F: FileSystem + ?Sized>(
+        &self,
+        project_dir: &Path,
+        room_name: &str,
+        file_system: &F,
+    ) -> Result<RoomImages> {
+        self.render_with_sources(
+            project_dir,
+            room_name,
+            file_system,
+            file_system,
+            &RenderOptions::default(),
+        )
+    }
+
+    // Render a room, with its XML and tilesets from different file systems (as in
+    // `render_room_with_sources`), unless it was rendered from the same files before.
+    pub fn render_with_sources<F: FileSystem + ?Sized, G: FileSystem + ?Sized>(
+        &self,
+        project_dir: &Path,
+        room_name: &str,
+        room_file_system: &F,
+        tileset_file_system: &G,
+        options: &RenderOptions,
+    ) -> Result<RoomImages> {
+        let candidates: Vec<(usize, Vec<PathBuf>, Vec<Option<Oid>>)> = self
+            .renders
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| {
+                r.project == project_dir && r.room == room_name && r.options == *options
+            })
+            .map(|(i, r)| (i, r.images.dependencies.clone(), r.dependency_ids.clone()))
+            .collect();
+        for (i, dependencies, ids) in candidates {
+            if dependency_ids(&dependencies, room_file_system, tileset_file_system) == ids {
+                let mut renders = self.renders.lock().unwrap();
+                // Another thread may have changed the cache while the files were checked.
+                if renders.get(i).is_some_and(|r| r.dependency_ids == ids) {
+                    let render = renders.remove(i);
+                    let images = render.images.clone();
+                    renders.push(render);
+                    return Ok(images);
+                }
+            }
+        }
+
+        let images = render_room_with_cache(
+            project_dir,
+            room_name,
+            room_file_system,
+            tileset_file_system,
+            options,
+            &mut self.tilesets.lock().unwrap(),
+        )?;
+        let ids = dependency_ids(&images.dependencies, room_file_system, tileset_file_system);
+        let mut renders = self.renders.lock().unwrap();
+        if renders.len() == MAX_CACHED_RENDERS {
+            renders.remove(0);
+        }
+        renders.push(CachedRender {
+            project: project_dir.to_path_buf(),
+            room: room_name.to_string(),
+            options: *options,
+            dependency_ids: ids,
+            images: images.clone(),
+        });
+        Ok(images)
+    }
+
+    // Compare two renders of a room, state by state.
+    pub fn diff(&self, a: &RoomImages, b: &RoomImages) -> DiffResult {
+        let differing_states = (0..a.layer1.len())
+            .filter(|&i| {
+                let same_image =
+                    |x: &[Image], y: &[Image]| y.get(i).is_some_and(|y| x[i].pixels == y.pixels);
+                !(same_image(&a.layer1, &b.layer1)
+                    && same_image(&a.layer2, &b.layer2)
+                    && bts_grid(&a.room_xml, i) == bts_grid(&b.room_xml, i))
+            })
+            .collect();
+        let changed_tiles = (0..a.layer1.len())
+            .map(|i| changed_tiles(a, b, i))
+            .collect();
+        DiffResult {
+            differing_states,
+            changed_tiles,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// An illustrated changelog: the rooms changed by each commit in a range, with before and
+// after images of the states that changed, as a Markdown report.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use git2::Repository;
+use log::{info, warn};
+
+use crate::{
+    export::{check_output_dir, image_hash, list_files, remove_empty_dirs, save_png},
+    file_system::GitTreeFileSystem,
+    guarded_write::{PlannedWrite, WriteGuard},
+    render_session::RenderSession,
+    room::RoomImages,
+};
+
+const REPORT_FILENAME: &str = "report.md";
+
+#[derive(clap::Args)]
+pub struct ReportArgs {
+    /// Commits to report on, e.g. v1.0..main
+    #[arg(long)]
+    range: String,
+    /// Directory to write the report and its images to
+    #[arg(long, default_value = ".smartdiff/report")]
+    dir: PathBuf,
+}
+
+// The rooms (by project and name) whose XML a commit changed.
+fn changed_rooms(diff: &git2::Diff, projects: &[PathBuf]) -> BTreeSet<(PathBuf, String)> {
+    let mut out = BTreeSet::new();
+    for delta in diff.deltas() {
+        for path in [delta.old_file().path(), delta.new_file().path()]
+            .into_iter()
+            .flatten()
+        {
+            let (Some(dir), Some(name)) = (path.parent(), path.file_stem()) else {
+                continue;
+            };
+            if path.extension().is_none_or(|e| e != "xml") {
+                continue;
+            }
+            if let Some(project) = projects.iter().find(|p| p.join("Export/Rooms") == dir) {
+                out.insert((project.clone(), name.to_string_lossy().to_string()));
+            }
+        }
+    }
+    out
+}
+
+// A room as of a commit. Each commit's "after" is usually the next commit's "before", which
+// the session renders only once.
+fn render_at(
+    session: &RenderSession,
+    repo: &Repository,
+    tree: Option<&git2::Tree>,
+    project: &Path,
+    room: &str,
+) -> Option<RoomImages> {
+    let fs = GitTreeFileSystem {
+        repo,
+        tree: tree?.clone(),
+    };
+    session.render(project, room, &fs).ok()
+}
+
+// The report section for a room changed by a commit, with its images saved under `dir`
+// (relative to `report_dir`).
+fn room_section(
+    before: Option<&RoomImages>,
+    after: Option<&RoomImages>,
+    report_dir: &Path,
+    dir: &Path,
+) -> Result<String> {
+    let Some(after) = after else {
+        return Ok(if before.is_some() {
+            "Removed (or no longer renders).\n\n".to_string()
+        } else {
+            "Does not render before or after this commit.\n\n".to_string()
+        });
+    };
+    let mut out = String::new();
+    for (i, name) in after.room_state_names.iter().enumerate() {
+        let Some(after_image) = after.composite_state(i) else {
+            continue;
+        };
+        let before_image = before.and_then(|b| b.composite_state(i));
+        if before_image.as_ref().map(image_hash) == Some(image_hash(&after_image)) {
+            continue;
+        }
+        let after_path = dir.join(format!("{}_after.png", i));
+        save_png(&after_image, &report_dir.join(&after_path))?;
+        out += &format!("State {} ({})\n\n", i, name);
+        match before_image {
+            Some(before_image) => {
+                let before_path = dir.join(format!("{}_before.png", i));
+                save_png(&before_image, &report_dir.join(&before_path))?;
+                out += &format!(
+                    "| Before | After |\n|---|---|\n| ![]({}) | ![]({}) |\n\n",
+                    before_path.display(),
+                    after_path.display()
+                );
+            }
+            None => out += &format!("New: ![]({})\n\n", after_path.display()),
+        }
+    }
+    if out.is_empty() {
+        out = "No visible changes.\n\n".to_string();
+    }
+    Ok(out)
+}
+
+// Write the report of a commit range into `report_dir`, returning the numbers of commits and
+// room changes reported.
+fn write_report(
+    repo: &Repository,
+    args: &ReportArgs,
+    projects: &[PathBuf],
+    report_dir: &Path,
+) -> Result<(usize, usize)> {
+    let mut walk = repo.revwalk()?;
+    walk.push_range(&args.range)
+        .with_context(|| format!("Invalid commit range: {}", args.range))?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    let session = RenderSession::default();
+    let mut report = format!("# Room changes in {}\n\n", args.range);
+    let (mut commits, mut rooms) = (0, 0);
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let parent_tree = commit.parents().next().map(|p| p.tree()).transpose()?;
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        let changed = changed_rooms(&diff, projects);
+        if changed.is_empty() {
+            continue;
+        }
+        let short_id = commit.as_object().short_id()?;
+        let short_id = short_id.as_str().unwrap_or_default();
+        info!("{}: {} rooms changed", short_id, changed.len());
+        report += &format!(
+            "## {} {}\n\n{}\n\n",
+            short_id,
+            commit.summary().unwrap_or_default(),
+            commit.author().name().unwrap_or_default()
+        );
+        for (project, room) in &changed {
+            let before = render_at(&session, repo, parent_tree.as_ref(), project, room);
+            let after = render_at(&session, repo, Some(&tree), project, room);
+            if let Some(after) = &after {
+                for warning in &after.warnings {
+                    warn!("{} {}/{}: {}", short_id, project.display(), room, warning);
+                }
+            }
+            let dir = Path::new(short_id).join(project).join(room);
+            report += &format!("### {}/{}\n\n", project.display(), room);
+            report += &room_section(before.as_ref(), after.as_ref(), report_dir, &dir)?;
+        }
+        commits += 1;
+        rooms += changed.len();
+    }
+    std::fs::create_dir_all(report_dir)?;
+    let path = report_dir.join(REPORT_FILENAME);
+    std::fs::write(&path, report).with_context(|| format!("Unable to write {}", path.display()))?;
+    Ok((commits, rooms))
+}
+
+// Move the files of a finished report from `partial_dir` into `dir`, replacing the previous
+// report there.
+fn move_report(partial_dir: &Path, dir: &Path, guard: &WriteGuard) -> Result<()> {
+    let mut writes: Vec<PlannedWrite> = vec![];
+    for path in list_files(partial_dir) {
+        let contents =
+            std::fs::read(&path).with_context(|| format!("Unable to read {}", path.display()))?;
+        writes.push(PlannedWrite {
+            path: dir.join(path.strip_prefix(partial_dir)?),
+            contents: Some(contents),
+        });
+    }
+    guard.replace("report", list_files(dir), writes)?;
+    if !guard.dry_run {
+        remove_empty_dirs(dir);
+    }
+    Ok(())
+}
+
+pub fn run(args: &ReportArgs, projects: &[PathBuf], guard: &WriteGuard) -> Result<()> {
+    let repo = Repository::open(".").context("The report needs a git repository")?;
+    check_output_dir(&args.dir, REPORT_FILENAME)?;
+    // The report is written to a temporary directory and moved into place once it's
+    // complete, so that a failure leaves the previous report as it was.
+    let partial_dir = std::env::temp_dir().join(format!("smartdiff-report-{}", std::process::id()));
+    if partial_dir.exists() {
+        bail!("{} already exists", partial_dir.display());
+    }
+    let result = write_report(&repo, args, projects, &partial_dir)
+        .and_then(|counts| move_report(&partial_dir, &args.dir, guard).map(|_| counts));
+    let _ = std::fs::remove_dir_all(&partial_dir);
+    let (commits, rooms) = result?;
+    let path = args.dir.join(REPORT_FILENAME);
+    info!(
+        "Wrote a report of {} room changes in {} commits to {}",
+        rooms,
+        commits,
+        path.display()
+    );
+    Ok(())
+}
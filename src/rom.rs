@@ -0,0 +1,709 @@
+use std::{
+    fmt::Write,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    file_system::{FileSystem, LocalFileSystem},
+    room::load_room,
+};
+
+// Locations of the vanilla CRE graphics and tile table, and of the table of tileset entries
+// (each 9 bytes: 3-byte pointers to the tile table, graphics, and palette).
+const CRE_GFX_ADDR: usize = 0xB98000;
+const CRE_TILES_ADDR: usize = 0xB9A09D;
+const TILESET_ENTRIES_ADDR: usize = 0x8FE6A2;
+const TILESET_ENTRY_SIZE: usize = 9;
+const ROOM_BANK: usize = 0x8F;
+const STATE_DATA_SIZE: usize = 26;
+const DEFAULT_CONDITION: u16 = 0xE5E6;
+// Largest patched ROM accepted (twice the largest SNES ROM), as patches give their own size
+const MAX_ROM_SIZE: usize = 0x1000000;
+
+// Serves the exported files of a project (rooms and tilesets) by extracting them from a
+// built ROM, so that the ROM can be compared against the project it was built from.
+// Rooms are found by the area and index given in the working copy's room XML.
+pub struct RomFileSystem {
+    pub path: PathBuf,
+    rom: Vec<u8>,
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>> {
+    let mut rom =
+        std::fs::read(path).with_context(|| format!("Unable to read ROM at {}", path.display()))?;
+    // Skip a copier header, if present.
+    if rom.len() % 0x8000 == 0x200 {
+        rom.drain(..0x200);
+    }
+    Ok(rom)
+}
+
+fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, &patch[5..])
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, &patch[4..])
+    } else {
+        bail!("Unrecognized patch format (expected IPS or BPS)");
+    }
+}
+
+fn apply_ips(rom: &[u8], mut records: &[u8]) -> Result<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let mut take = |n: usize| -> Result<&[u8]> {
+        if records.len() < n {
+            bail!("IPS patch is truncated");
+        }
+        let (x, rest) = records.split_at(n);
+        records = rest;
+        Ok(x)
+    };
+    loop {
+        let offset = take(3)?;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset = (offset[0] as usize) << 16 | (offset[1] as usize) << 8 | offset[2] as usize;
+        let size = take(2)?;
+        let size = (size[0] as usize) << 8 | size[1] as usize;
+        let data = if size == 0 {
+            let run = take(3)?;
+            vec![run[2]; (run[0] as usize) << 8 | run[1] as usize]
+        } else {
+            take(size)?.to_vec()
+        };
+        if out.len() < offset + data.len() {
+            out.resize(offset + data.len(), 0);
+        }
+        out[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    // An optional size to truncate the ROM to may follow the end marker.
+    if let Ok(size) = take(3) {
+        out.truncate((size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize);
+    }
+    Ok(out)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// Read a variable-length number from a BPS patch.
+fn bps_number(data: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut out = 0usize;
+    let mut shift = 1usize;
+    loop {
+        let Some(&x) = data.get(*pos) else {
+            bail!("BPS patch is truncated");
+        };
+        *pos += 1;
+        let too_large = || anyhow::anyhow!("BPS patch has a number which is too large");
+        out = (x as usize & 0x7F)
+            .checked_mul(shift)
+            .and_then(|x| out.checked_add(x))
+            .ok_or_else(too_large)?;
+        if x & 0x80 != 0 {
+            return Ok(out);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+        out = out.checked_add(shift).ok_or_else(too_large)?;
+    }
+}
+
+fn apply_bps(rom: &[u8], body: &[u8]) -> Result<Vec<u8>> {
+    let Some(actions_end) = body.len().checked_sub(12) else {
+        bail!("BPS patch is truncated");
+    };
+    let footer = &body[actions_end..];
+    let footer_crc = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+    if crc32(rom) != footer_crc(0) {
+        bail!("The ROM is not the one the BPS patch was made for (checksum mismatch)");
+    }
+    let actions = &body[..actions_end];
+    let mut pos = 0;
+    let _source_size = bps_number(actions, &mut pos)?;
+    let target_size = bps_number(actions, &mut pos)?;
+    let metadata_size = bps_number(actions, &mut pos)?;
+    if target_size > MAX_ROM_SIZE {
+        bail!(
+            "BPS patch is for a ROM of {} bytes, which is too large",
+            target_size
+        );
+    }
+    pos = pos.saturating_add(metadata_size);
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    while out.len() < target_size {
+        let data = bps_number(actions, &mut pos)?;
+        let len = (data >> 2) + 1;
+        if len > target_size - out.len() {
+            bail!("BPS patch writes past the end of the ROM");
+        }
+        let invalid = || anyhow::anyhow!("BPS patch refers to data outside of the ROM");
+        match data & 3 {
+            // Copy from the same offset of the source
+            0 => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).ok_or_else(invalid)?);
+            }
+            // Copy from the patch
+            1 => {
+                out.extend_from_slice(actions.get(pos..pos + len).ok_or_else(invalid)?);
+                pos += len;
+            }
+            // Copy from a relative offset of the source, or of the output so far
+            kind => {
+                let delta = bps_number(actions, &mut pos)?;
+                let delta = if delta & 1 != 0 {
+                    -((delta >> 1) as isize)
+                } else {
+                    (delta >> 1) as isize
+                };
+                let offset = if kind == 2 {
+                    &mut source_offset
+                } else {
+                    &mut target_offset
+                };
+                *offset = offset.checked_add(delta).ok_or_else(invalid)?;
+                let start = usize::try_from(*offset).map_err(|_| invalid())?;
+                for i in start..start + len {
+                    let x = if kind == 2 {
+                        *rom.get(i).ok_or_else(invalid)?
+                    } else {
+                        *out.get(i).ok_or_else(invalid)?
+                    };
+                    out.push(x);
+                }
+                *offset = offset.checked_add(len as isize).ok_or_else(invalid)?;
+            }
+        }
+    }
+    if crc32(&out) != footer_crc(1) {
+        bail!("Patched ROM checksum mismatch");
+    }
+    Ok(out)
+}
+
+// Convert a LoROM address to an offset in the ROM file.
+fn snes_to_pc(addr: usize) -> usize {
+    ((addr >> 16) & 0x7F) << 15 | (addr & 0x7FFF)
+}
+
+impl RomFileSystem {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            rom: read_rom(path)?,
+        })
+    }
+
+    // A ROM built by applying an IPS or BPS patch to a (vanilla) ROM.
+    pub fn open_patched(rom_path: &Path, patch_path: &Path) -> Result<Self> {
+        let patch = std::fs::read(patch_path)
+            .with_context(|| format!("Unable to read patch at {}", patch_path.display()))?;
+        let rom = apply_patch(&read_rom(rom_path)?, &patch)
+            .with_context(|| format!("Unable to apply patch {}", patch_path.display()))?;
+        Ok(Self {
+            path: patch_path.to_path_buf(),
+            rom,
+        })
+    }
+
+    fn read(&self, addr: usize, len: usize) -> Result<&[u8]> {
+        let pc = snes_to_pc(addr);
+        self.rom
+            .get(pc..pc + len)
+            .with_context(|| format!("Address {:06X} is outside of the ROM", addr))
+    }
+
+    fn read_u8(&self, addr: usize) -> Result<usize> {
+        Ok(self.read(addr, 1)?[0] as usize)
+    }
+
+    fn read_u16(&self, addr: usize) -> Result<usize> {
+        let x = self.read(addr, 2)?;
+        Ok(u16::from_le_bytes([x[0], x[1]]) as usize)
+    }
+
+    fn read_u24(&self, addr: usize) -> Result<usize> {
+        let x = self.read(addr, 3)?;
+        Ok(x[0] as usize | (x[1] as usize) << 8 | (x[2] as usize) << 16)
+    }
+
+    // Decompress data in the game's LZ format.
+    fn decompress(&self, addr: usize) -> Result<Vec<u8>> {
+        let mut pc = snes_to_pc(addr);
+        let mut next = || -> Result<u8> {
+            let x = *self
+                .rom
+                .get(pc)
+                .with_context(|| format!("Compressed data at {:06X} runs past the ROM", addr))?;
+            pc += 1;
+            Ok(x)
+        };
+        let mut out: Vec<u8> = vec![];
+        loop {
+            let b = next()?;
+            if b == 0xFF {
+                break;
+            }
+            let (cmd, len) = if b >> 5 == 7 {
+                (
+                    (b >> 2) & 7,
+                    (((b as usize & 3) << 8) | next()? as usize) + 1,
+                )
+            } else {
+                (b >> 5, (b as usize & 0x1F) + 1)
+            };
+            match cmd {
+                0 => {
+                    for _ in 0..len {
+                        out.push(next()?);
+                    }
+                }
+                1 => {
+                    let x = next()?;
+                    out.extend(std::iter::repeat_n(x, len));
+                }
+                2 => {
+                    let pair = [next()?, next()?];
+                    out.extend((0..len).map(|i| pair[i % 2]));
+                }
+                3 => {
+                    let x = next()?;
+                    out.extend((0..len).map(|i| x.wrapping_add(i as u8)));
+                }
+                _ => {
+                    let start = match cmd {
+                        4 | 5 => next()? as usize | (next()? as usize) << 8,
+                        _ => out.len().checked_sub(next()? as usize).with_context(|| {
+                            format!("Invalid back-reference in compressed data at {:06X}", addr)
+                        })?,
+                    };
+                    let invert = if cmd == 5 || cmd == 7 { 0xFF } else { 0 };
+                    for i in start..start + len {
+                        let x = *out.get(i).with_context(|| {
+                            format!("Invalid back-reference in compressed data at {:06X}", addr)
+                        })?;
+                        out.push(x ^ invert);
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    // States of the room whose header is at the given address, as (condition, argument,
+    // state data address), in the order the game checks them (ending with the default).
+    fn room_states(&self, header_addr: usize) -> Result<Vec<(u16, usize, usize)>> {
+        let mut addr = header_addr + 11;
+        let mut states = vec![];
+        loop {
+            let condition = self.read_u16(addr)? as u16;
+            addr += 2;
+            let arg = match condition {
+                DEFAULT_CONDITION => {
+                    states.push((condition, 0, addr));
+                    return Ok(states);
+                }
+                0xE612 | 0xE629 => {
+                    addr += 1;
+                    self.read_u8(addr - 1)?
+                }
+                0xE5EB => {
+                    addr += 2;
+                    self.read_u16(addr - 2)?
+                }
+                0xE5FF | 0xE640 | 0xE652 | 0xE669 | 0xE678 => 0,
+                _ => bail!("Unknown state condition {:04X}", condition),
+            };
+            states.push((condition, arg, ROOM_BANK << 16 | self.read_u16(addr)?));
+            addr += 2;
+        }
+    }
+
+    // Search the room header bank for the header of the room with the given area and index.
+    fn find_room_header(&self, area: usize, index: usize) -> Result<usize> {
+        for offset in 0x8000..0x10000 - 11 {
+            let addr = ROOM_BANK << 16 | offset;
+            let header = self.read(addr, 11)?;
+            let plausible = header[0] as usize == index
+                && header[1] as usize == area
+                && (1..=16).contains(&header[4])
+                && (1..=16).contains(&header[5])
+                && header[10] >= 0x80;
+            if plausible && self.room_states(addr).is_ok() {
+                return Ok(addr);
+            }
+        }
+        bail!(
+            "No room with area {:X} and index {:X} found in the ROM",
+            area,
+            index
+        );
+    }
+
+    // The BG data entries that decompress a tilemap, as lists of tilemap words.
+    fn bg_tilemaps(&self, mut addr: usize) -> Result<Vec<Vec<u16>>> {
+        let mut out = vec![];
+        loop {
+            let kind = self.read_u16(addr)?;
+            let arg_size = match kind {
+                0x0000 => return Ok(out),
+                0x0004 => {
+                    let data = self.decompress(self.read_u24(addr + 2)?)?;
+                    out.push(
+                        data.chunks_exact(2)
+                            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                            .collect(),
+                    );
+                    5
+                }
+                0x0002 | 0x0008 => 7,
+                0x0006 | 0x000A | 0x000C => 0,
+                0x000E => 9,
+                _ => bail!("Unknown BG data entry type {:04X} at {:06X}", kind, addr),
+            };
+            addr += 2 + arg_size;
+        }
+    }
+
+    fn room_xml(&self, area: usize, index: usize) -> Result<Vec<u8>> {
+        let header_addr = self.find_room_header(area, index)?;
+        let header = self.read(header_addr, 11)?;
+        let width = header[4] as usize;
+        let height = header[5] as usize;
+        let mut xml = format!(
+            "<Room><area>{:X}</area><index>{:X}</index><x>{:X}</x><y>{:X}</y><width>{:X}</width><height>{:X}</height><upscroller>{:02X}</upscroller><dnscroller>{:02X}</dnscroller><specialGFX>{:02X}</specialGFX><States>",
+            area, index, header[2], header[3], width, height, header[6], header[7], header[8]
+        );
+        for (condition, arg, state_addr) in self.room_states(header_addr)? {
+            let state = self.read(state_addr, STATE_DATA_SIZE)?;
+            let level_data = self.decompress(self.read_u24(state_addr)?)?;
+            let condition = if condition == DEFAULT_CONDITION {
+                "default".to_string()
+            } else {
+                format!("{:04X}", condition)
+            };
+            write!(
+                xml,
+                "<State><condition>{}</condition><Arg>{:X}</Arg><GFXset>{:X}</GFXset><music>{:02X}{:02X}</music><LevelData>",
+                condition, arg, state[3], state[4], state[5]
+            )?;
+            // Level data is the size of layer 1 (in bytes), then layer 1, BTS, and
+            // optionally layer 2, each row-major over the whole room.
+            let Some(layer1_size) = level_data
+                .get(..2)
+                .map(|x| u16::from_le_bytes([x[0], x[1]]) as usize)
+            else {
+                bail!("Level data at {:06X} is empty", self.read_u24(state_addr)?);
+            };
+            let layer1_start = 2;
+            let layer2_start = layer1_start + layer1_size + layer1_size / 2;
+            for (name, start) in [("Layer1", layer1_start), ("Layer2", layer2_start)] {
+                let Some(data) = level_data.get(start..start + layer1_size) else {
+                    continue;
+                };
+                let words: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .collect();
+                write!(xml, "<{}>", name)?;
+                for screen_y in 0..height {
+                    for screen_x in 0..width {
+                        write!(xml, "<Screen X=\"{:X}\" Y=\"{:X}\">", screen_x, screen_y)?;
+                        for i in 0..256 {
+                            let x = screen_x * 16 + i % 16;
+                            let y = screen_y * 16 + i / 16;
+                            let word = words.get(y * width * 16 + x).copied().unwrap_or(0);
+                            write!(xml, "{:04X} ", word)?;
+                        }
+                        xml.push_str("</Screen>");
+                    }
+                }
+                write!(xml, "</{}>", name)?;
+            }
+            xml.push_str("</LevelData><BGData>");
+            let bg_data_ptr = u16::from_le_bytes([state[22], state[23]]) as usize;
+            if bg_data_ptr != 0 {
+                for tilemap in self.bg_tilemaps(ROOM_BANK << 16 | bg_data_ptr)? {
+                    xml.push_str("<Data><Type>DECOMP</Type><SOURCE>");
+                    for word in tilemap {
+                        write!(xml, "{:04X} ", word)?;
+                    }
+                    xml.push_str("</SOURCE></Data>");
+                }
+            }
+            xml.push_str("</BGData></State>");
+        }
+        xml.push_str("</States></Room>");
+        Ok(xml.into_bytes())
+    }
+
+    fn tileset_file(&self, kind: &str, idx: usize, filename: &str) -> Result<Vec<u8>> {
+        match (kind, filename) {
+            ("CRE", "8x8tiles.gfx") => self.decompress(CRE_GFX_ADDR),
+            ("CRE", "16x16tiles.ttb") => self.decompress(CRE_TILES_ADDR),
+            ("SCE", _) => {
+                let entry_addr = TILESET_ENTRIES_ADDR + idx * TILESET_ENTRY_SIZE;
+                let offset = match filename {
+                    "16x16tiles.ttb" => 0,
+                    "8x8tiles.gfx" => 3,
+                    "palette.snes" => 6,
+                    _ => bail!("Unknown tileset file {}", filename),
+                };
+                self.decompress(self.read_u24(entry_addr + offset)?)
+            }
+            _ => bail!("Unknown tileset file {}/{:02X}/{}", kind, idx, filename),
+        }
+    }
+}
+
+impl FileSystem for RomFileSystem {
+    fn load(&self, path: &Path) -> Result<Vec<u8>> {
+        let components: Vec<String> = path
+            .components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let export_pos = components.iter().position(|c| c == "Export");
+        let (project_parts, rest) = match export_pos {
+            Some(pos) => (&components[..pos], &components[pos + 1..]),
+            None => (
+                &components[..components.len().saturating_sub(1)],
+                &components[components.len().saturating_sub(1)..],
+            ),
+        };
+        let project_dir: PathBuf = project_parts.iter().collect();
+        let rest: Vec<&str> = rest.iter().map(|x| x.as_str()).collect();
+        match rest.as_slice() {
+            // The ROM has no project file; use the working copy's so that the project
+            // counts as the same one.
+            ["project.xml"] if export_pos.is_none() => LocalFileSystem {}.load(path),
+            ["Rooms", filename] => {
+                let Some(room_name) = filename.strip_suffix(".xml") else {
+                    bail!("{} is not in the ROM", path.display());
+                };
+                let room = load_room(&project_dir, room_name, &LocalFileSystem {})?;
+                self.room_xml(room.area, room.index)
+                    .with_context(|| format!("Unable to extract room {} from the ROM", room_name))
+            }
+            ["Tileset", kind, idx, filename] => {
+                let idx = usize::from_str_radix(idx, 16)
+                    .with_context(|| format!("Invalid tileset index {}", idx))?;
+                self.tileset_file(kind, idx, filename).with_context(|| {
+                    format!(
+                        "Unable to extract tileset file {} from the ROM",
+                        path.display()
+                    )
+                })
+            }
+            _ => bail!("{} is not in the ROM", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encode a number in the BPS variable-length format.
+    fn bps_encode(mut n: usize) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            let x = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | x);
+                return out;
+            }
+            out.push(x);
+            n -= 1;
+        }
+    }
+
+    // A BPS patch (without the "BPS1" magic) from its actions, for the given source and target.
+    fn bps_body(actions: &[u8], source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut body = actions.to_vec();
+        body.extend(crc32(source).to_le_bytes());
+        body.extend(crc32(target).to_le_bytes());
+        // The checksum of the patch itself isn't checked.
+        body.extend([0; 4]);
+        body
+    }
+
+    fn rom_with(data: &[u8]) -> RomFileSystem {
+        RomFileSystem {
+            path: PathBuf::new(),
+            rom: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn ips_records() {
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at offset 1
+        patch.extend([0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        // A run of 3 bytes at offset 4
+        patch.extend([0, 0, 4, 0, 0, 0, 3, 0xCC]);
+        // Past the end of the ROM, which extends it
+        patch.extend([0, 0, 9, 0, 1, 0xDD]);
+        patch.extend(b"EOF");
+        let out = apply_patch(&[0; 8], &patch).unwrap();
+        assert_eq!(out, [0, 0xAA, 0xBB, 0, 0xCC, 0xCC, 0xCC, 0, 0, 0xDD]);
+    }
+
+    #[test]
+    fn ips_truncation_after_eof() {
+        let mut patch = vec![0, 0, 0, 0, 1, 0xAA];
+        patch.extend(b"EOF");
+        patch.extend([0, 0, 4]);
+        assert_eq!(apply_ips(&[0; 8], &patch).unwrap(), [0xAA, 0, 0, 0]);
+    }
+
+    #[test]
+    fn ips_truncated() {
+        // A record whose data is cut short
+        assert!(apply_ips(&[0; 8], &[0, 0, 0, 0, 4, 0xAA]).is_err());
+        // No end marker
+        assert!(apply_ips(&[0; 8], &[0, 0, 0, 0, 1, 0xAA]).is_err());
+    }
+
+    #[test]
+    fn bps_numbers() {
+        for n in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x123456] {
+            let data = bps_encode(n);
+            let mut pos = 0;
+            assert_eq!(bps_number(&data, &mut pos).unwrap(), n);
+            assert_eq!(pos, data.len());
+        }
+        // The last byte (with the high bit set) is missing.
+        assert!(bps_number(&[0x00], &mut 0).is_err());
+        // More digits than fit in a usize
+        let mut data = vec![0x7F; 12];
+        data.push(0xFF);
+        assert!(bps_number(&data, &mut 0).is_err());
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = b"abcd";
+        let target = b"abXabX";
+        let mut actions = vec![];
+        for n in [source.len(), target.len(), 0] {
+            actions.extend(bps_encode(n));
+        }
+        // Source read of "ab"
+        actions.extend(bps_encode(1 << 2));
+        // Target read of "X"
+        actions.extend(bps_encode(1));
+        actions.push(b'X');
+        // Target copy of "abX" from the start of the output
+        actions.extend(bps_encode(2 << 2 | 3));
+        actions.extend(bps_encode(0));
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(bps_body(&actions, source, target));
+        assert_eq!(apply_patch(source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_checksum_mismatch() {
+        let mut actions = vec![];
+        for n in [4, 4, 0] {
+            actions.extend(bps_encode(n));
+        }
+        actions.extend(bps_encode(3 << 2));
+        // Made for a different source ROM
+        let body = bps_body(&actions, b"abcd", b"abcd");
+        assert!(apply_bps(b"abce", &body).is_err());
+        // Producing something other than the expected target
+        let body = bps_body(&actions, b"abcd", b"abce");
+        assert!(apply_bps(b"abcd", &body).is_err());
+    }
+
+    #[test]
+    fn bps_truncated() {
+        assert!(apply_bps(b"abcd", &[0x84; 8]).is_err());
+        // Actions which end before the target is complete
+        let mut actions = vec![];
+        for n in [4, 4, 0] {
+            actions.extend(bps_encode(n));
+        }
+        let body = bps_body(&actions, b"abcd", b"abcd");
+        assert!(apply_bps(b"abcd", &body).is_err());
+    }
+
+    #[test]
+    fn bps_oversized() {
+        // A target far larger than any ROM
+        let mut actions = vec![];
+        for n in [4, usize::MAX >> 8, 0] {
+            actions.extend(bps_encode(n));
+        }
+        let body = bps_body(&actions, b"abcd", b"abcd");
+        assert!(apply_bps(b"abcd", &body).is_err());
+        // An action writing past the end of the target
+        let mut actions = vec![];
+        for n in [4, 4, 0] {
+            actions.extend(bps_encode(n));
+        }
+        actions.extend(bps_encode(7 << 2 | 3));
+        actions.extend(bps_encode(0));
+        let body = bps_body(&actions, b"abcd", b"abcd");
+        assert!(apply_bps(b"abcd", &body).is_err());
+    }
+
+    #[test]
+    fn decompress_commands() {
+        let mut stream = vec![];
+        // Direct copy of 3 bytes
+        stream.extend([0x02, 1, 2, 3]);
+        // Byte fill of 4
+        stream.extend([0x23, 7]);
+        // Word fill of 3
+        stream.extend([0x42, 0x0A, 0x0B]);
+        // Increasing fill of 3
+        stream.extend([0x62, 0x10]);
+        // Copy of 2 bytes from output offset 0
+        stream.extend([0x81, 0, 0]);
+        // Inverted copy of 2 bytes from output offset 0
+        stream.extend([0xA1, 0, 0]);
+        // Copy of 2 bytes from 17 bytes back
+        stream.extend([0xC1, 17]);
+        // Byte fill of 300, with an extended length
+        stream.extend([0xE5, 0x2B, 0x55]);
+        stream.push(0xFF);
+        let mut expected = vec![1, 2, 3, 7, 7, 7, 7, 0x0A, 0x0B, 0x0A, 0x10, 0x11, 0x12];
+        expected.extend([1, 2, 0xFE, 0xFD, 1, 2]);
+        expected.extend([0x55; 300]);
+        let rom = rom_with(&stream);
+        assert_eq!(rom.decompress(0x808000).unwrap(), expected);
+    }
+
+    #[test]
+    fn decompress_invalid() {
+        // No end marker
+        assert!(rom_with(&[0x02, 1, 2, 3]).decompress(0x808000).is_err());
+        // A back-reference before the start of the output
+        assert!(rom_with(&[0xC1, 1, 0xFF]).decompress(0x808000).is_err());
+    }
+}
@@ -1,13 +1,23 @@
 use crate::{
-    file_system::FileSystem,
+    export::composite,
+    file_system::{FileSystem, list_files_with_extension},
     smart_xml::{self, BGData, Screen},
 };
-use anyhow::{Context, Result};
-use std::path::Path;
+use anyhow::{Context, Result, anyhow, bail};
+use git2::{ObjectType, Oid};
+use hashbrown::HashMap;
+use log::info;
+use serde::Deserialize;
+use std::{
+    any::Any,
+    collections::BTreeSet,
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::{Path, PathBuf},
+};
 
-type Color = [u8; 3];
+pub(crate) type Color = [u8; 3];
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash)]
 pub struct Image {
     pub width: usize,
     pub height: usize,
@@ -34,28 +44,63 @@ impl Image {
     }
 
     pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.set_pixel_alpha(x, y, color, 255);
+    }
+
+    pub fn set_pixel_alpha(&mut self, x: usize, y: usize, color: Color, alpha: u8) {
         let i = (y * self.width + x) * 4;
         self.pixels[i] = color[0];
         self.pixels[i + 1] = color[1];
         self.pixels[i + 2] = color[2];
-        self.pixels[i + 3] = 255;
+        self.pixels[i + 3] = alpha;
     }
 }
 
 #[derive(Clone)]
 pub struct RoomImages {
     pub room_state_names: Vec<String>,
+    // Files that were loaded in order to render the room (its XML first)
+    pub dependencies: Vec<PathBuf>,
+    pub room_xml: smart_xml::Room,
+    // Problems that were worked around while rendering (e.g. missing tilesets)
+    pub warnings: Vec<String>,
+    // Tileset files among the above problems whose contents couldn't be decoded
+    pub malformed_assets: Vec<MalformedAsset>,
     pub layer1: Vec<Image>,
     pub layer2: Vec<Image>,
+    // Pixels of layer 2 from high-priority tiles which appear in front of layer 1 (where it
+    // isn't high-priority too), drawn over layer 1
+    pub layer2_front: Vec<Image>,
+    // Layer 3 effects of each state, drawn in front of layer 1
+    pub fx: Vec<Image>,
+    // For each state, the palette lines used by each 16x16 tile of layer 1 (as a bitmask,
+    // in rows of `width / 16` tiles)
+    pub palette_lines: Vec<Vec<u8>>,
+    // Backdrop color of each state (color 0 of its tileset's palette), shown where all layers
+    // are transparent
+    pub backdrop: Vec<Color>,
+    // Number of frames of the longest tile animation in the room's tilesets (1 if none)
+    pub animation_frames: usize,
+}
+
+impl RoomImages {
+    // Layers 1 and 2 of a state combined as the game shows them (without FX).
+    pub fn composite_state(&self, idx: usize) -> Option<Image> {
+        Some(composite(&[
+            self.layer2.get(idx)?,
+            self.layer1.get(idx)?,
+            self.layer2_front.get(idx)?,
+        ]))
+    }
 }
 
 #[derive(Copy, Clone)]
-struct Tile8x8 {
-    idx: usize,
-    palette: usize,
-    flip_x: bool,
-    flip_y: bool,
-    _priority: bool,
+pub(crate) struct Tile8x8 {
+    pub idx: usize,
+    pub palette: usize,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub priority: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -71,37 +116,133 @@ struct CRETileset {
     tiles: Vec<Tile16x16>,
 }
 
+// Number of 16x16 tiles in the CRE, which come before the SCE tiles.
+const CRE_TILE_COUNT: usize = 0x100;
+
+// 8x8 tile index which never refers to any graphics, used for placeholder tiles.
+const MISSING_GFX_IDX: usize = usize::MAX;
+
+const MISSING_TILE_8X8: Tile8x8 = Tile8x8 {
+    idx: MISSING_GFX_IDX,
+    palette: 0,
+    flip_x: false,
+    flip_y: false,
+    priority: false,
+};
+
+const MISSING_TILE_16X16: Tile16x16 = Tile16x16 {
+    top_left: MISSING_TILE_8X8,
+    top_right: MISSING_TILE_8X8,
+    bottom_left: MISSING_TILE_8X8,
+    bottom_right: MISSING_TILE_8X8,
+};
+
+impl CRETileset {
+    // Stand-in for a CRE that could not be loaded. Its tiles render as placeholders,
+    // while keeping the SCE tile indices in place.
+    fn missing() -> Self {
+        CRETileset {
+            gfx: vec![],
+            tiles: vec![MISSING_TILE_16X16; CRE_TILE_COUNT],
+        }
+    }
+}
+
 struct SCETileset {
     palette: Vec<Color>,
     gfx: Vec<[[u8; 8]; 8]>,
     tiles: Vec<Tile16x16>,
+    // Whether this stands in for a tileset that could not be loaded
+    missing: bool,
+}
+
+// References to 8x8 graphics, 16x16 tiles, or colors beyond the end of a tileset,
+// collected while rendering.
+#[derive(Default)]
+pub(crate) struct OutOfRange {
+    pub gfx: BTreeSet<usize>,
+    pub tiles: BTreeSet<usize>,
+    pub colors: BTreeSet<usize>,
+}
+
+// Maximum number of indices of each kind listed in a warning
+const MAX_LISTED_OUT_OF_RANGE: usize = 16;
+
+impl OutOfRange {
+    pub fn describe(&self) -> Vec<String> {
+        let list = |x: &BTreeSet<usize>| {
+            let mut s = x
+                .iter()
+                .take(MAX_LISTED_OUT_OF_RANGE)
+                .map(|i| format!("{:X}", i))
+                .collect::<Vec<String>>()
+                .join(", ");
+            if x.len() > MAX_LISTED_OUT_OF_RANGE {
+                s += &format!(", ... ({} total)", x.len());
+            }
+            s
+        };
+        let mut out = vec![];
+        if !self.gfx.is_empty() {
+            out.push(format!("out-of-range 8x8 graphics: {}", list(&self.gfx)));
+        }
+        if !self.tiles.is_empty() {
+            out.push(format!("out-of-range 16x16 tiles: {}", list(&self.tiles)));
+        }
+        if !self.colors.is_empty() {
+            out.push(format!("out-of-range colors: {}", list(&self.colors)));
+        }
+        out
+    }
+}
+
+impl SCETileset {
+    // Stand-in for an SCE tileset that could not be loaded. With no palette, all
+    // (non-transparent) pixels render with the placeholder pattern.
+    fn missing(cre_tileset: &CRETileset) -> Self {
+        SCETileset {
+            palette: vec![],
+            gfx: cre_tileset.gfx.clone(),
+            tiles: cre_tileset.tiles.clone(),
+            missing: true,
+        }
+    }
+}
+
+// Magenta/black checkerboard drawn in place of graphics that are missing.
+pub(crate) fn missing_color(x: usize, y: usize) -> Color {
+    if (x / 2 + y / 2).is_multiple_of(2) {
+        [255, 0, 255]
+    } else {
+        [0, 0, 0]
+    }
 }
 
 fn decode_8x8_tile_data_4bpp(data: &[u8]) -> Result<[[u8; 8]; 8]> {
     let mut out: [[u8; 8]; 8] = [[0; 8]; 8];
-    for y in 0..8 {
+    for (y, row) in out.iter_mut().enumerate() {
         let addr = y * 2;
         let data_0 = data[addr];
         let data_1 = data[addr + 1];
         let data_2 = data[addr + 16];
         let data_3 = data[addr + 17];
-        for x in 0..8 {
+        for (x, pixel) in row.iter_mut().enumerate() {
             let bit_0 = (data_0 >> (7 - x)) & 1;
             let bit_1 = (data_1 >> (7 - x)) & 1;
             let bit_2 = (data_2 >> (7 - x)) & 1;
             let bit_3 = (data_3 >> (7 - x)) & 1;
             let c = bit_0 | (bit_1 << 1) | (bit_2 << 2) | (bit_3 << 3);
-            out[y][x] = c as u8;
+            *pixel = c;
         }
     }
     Ok(out)
 }
 
-fn decode_8x8_tile(x: u16) -> Tile8x8 {
+pub(crate) fn decode_8x8_tile(x: u16) -> Tile8x8 {
     Tile8x8 {
         idx: (x & 0x3FF) as usize,
         palette: ((x >> 10) & 7) as usize,
-        _priority: ((x >> 13) & 1) == 1,
+        priority: ((x >> 13) & 1) == 1,
         flip_x: ((x >> 14) & 1) == 1,
         flip_y: ((x >> 15) & 1) == 1,
     }
@@ -116,73 +257,269 @@ fn decode_16x16_tile(data: &[u8]) -> Tile16x16 {
     }
 }
 
-fn decode_color(data: u16) -> Color {
+// A binary asset whose size doesn't fit its format, so that it can't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedAsset {
+    pub path: PathBuf,
+    pub kind: &'static str,
+    pub size: usize,
+    // Size in bytes of each entry (color, tile, etc.) of the file
+    pub entry_size: usize,
+    // Description of each entry
+    pub entry_format: &'static str,
+}
+
+impl MalformedAsset {
+    fn check(
+        path: &Path,
+        kind: &'static str,
+        data: &[u8],
+        entry_size: usize,
+        entry_format: &'static str,
+    ) -> Result<()> {
+        if data.len().is_multiple_of(entry_size) {
+            return Ok(());
+        }
+        Err(MalformedAsset {
+            path: path.to_path_buf(),
+            kind,
+            size: data.len(),
+            entry_size,
+            entry_format,
+        }
+        .into())
+    }
+
+    // Detailed description of the problem, for bug reports.
+    pub fn diagnostics(&self) -> String {
+        format!(
+            "File: {}\nAsset: {}\nSize: {} bytes\nExpected: a multiple of {} bytes ({})\nComplete entries: {}\nTrailing bytes: {}",
+            self.path.display(),
+            self.kind,
+            self.size,
+            self.entry_size,
+            self.entry_format,
+            self.size / self.entry_size,
+            self.size % self.entry_size,
+        )
+    }
+}
+
+impl std::fmt::Display for MalformedAsset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {} is truncated: {} bytes is not a multiple of {}",
+            self.kind,
+            self.path.display(),
+            self.size,
+            self.entry_size
+        )
+    }
+}
+
+impl std::error::Error for MalformedAsset {}
+
+// The malformed asset that caused an error, if any.
+pub fn malformed_asset(error: &anyhow::Error) -> Option<&MalformedAsset> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<MalformedAsset>())
+}
+
+pub fn decode_color(data: u16) -> Color {
     let r = data & 0x1f;
     let g = (data >> 5) & 0x1f;
     let b = (data >> 10) & 0x1f;
     [(r * 8) as u8, (g * 8) as u8, (b * 8) as u8]
 }
 
-fn load_palette<T: FileSystem>(palette_path: &Path, file_system: &T) -> Result<Vec<Color>> {
+pub(crate) fn load_palette<T: FileSystem + ?Sized>(
+    palette_path: &Path,
+    file_system: &T,
+) -> Result<Vec<Color>> {
     let palette_bytes = file_system
         .load(palette_path)
         .with_context(|| format!("Unable to load palette at {}", palette_path.display()))?;
-    let mut palette: Vec<Color> = vec![];
-    let mut i = 0;
-    while i < palette_bytes.len() {
-        let data = (palette_bytes[i] as u16) | ((palette_bytes[i + 1] as u16) << 8);
-        let color = decode_color(data);
-        palette.push(color);
-        i += 2;
-    }
+    MalformedAsset::check(
+        palette_path,
+        "Palette",
+        &palette_bytes,
+        2,
+        "15-bit BGR colors",
+    )?;
+    let palette: Vec<Color> = palette_bytes
+        .chunks_exact(2)
+        .map(|x| decode_color(u16::from_le_bytes([x[0], x[1]])))
+        .collect();
     Ok(palette)
 }
 
-fn load_8x8_gfx<T: FileSystem>(gfx8x8_path: &Path, file_system: &T) -> Result<Vec<[[u8; 8]; 8]>> {
+// Decoded 8x8 graphics and 16x16 tile tables by the blob id of their file, so that rendering
+// several rooms (or versions of a room) with the same tilesets only decodes them once.
+#[derive(Default)]
+pub struct TilesetCache {
+    gfx: HashMap<Oid, Vec<[[u8; 8]; 8]>>,
+    tiles: HashMap<Oid, Vec<Tile16x16>>,
+}
+
+pub fn blob_id(bytes: &[u8]) -> Oid {
+    Oid::hash_object(ObjectType::Blob, bytes).unwrap_or(Oid::zero())
+}
+
+fn load_8x8_gfx<T: FileSystem + ?Sized>(
+    gfx8x8_path: &Path,
+    file_system: &T,
+    cache: &mut TilesetCache,
+) -> Result<Vec<[[u8; 8]; 8]>> {
     let gfx8x8_bytes = file_system
-        .load(&gfx8x8_path)
+        .load(gfx8x8_path)
         .with_context(|| format!("Unable to load CRE 8x8 gfx at {}", gfx8x8_path.display()))?;
+    let id = blob_id(&gfx8x8_bytes);
+    if let Some(gfx) = cache.gfx.get(&id) {
+        return Ok(gfx.clone());
+    }
+    MalformedAsset::check(
+        gfx8x8_path,
+        "8x8 gfx",
+        &gfx8x8_bytes,
+        32,
+        "4bpp planar 8x8 tiles",
+    )?;
     let mut gfx: Vec<[[u8; 8]; 8]> = vec![];
-    let mut i = 0;
-    while i < gfx8x8_bytes.len() {
-        let tile = decode_8x8_tile_data_4bpp(&gfx8x8_bytes[i..(i + 32)])?;
-        gfx.push(tile);
-        i += 32;
+    for data in gfx8x8_bytes.chunks_exact(32) {
+        gfx.push(decode_8x8_tile_data_4bpp(data)?);
     }
+    cache.gfx.insert(id, gfx.clone());
     Ok(gfx)
 }
 
-fn load_16x16_gfx<T: FileSystem>(gfx16x16_path: &Path, file_system: &T) -> Result<Vec<Tile16x16>> {
-    let gfx16x16_bytes = file_system.load(&gfx16x16_path).with_context(|| {
+fn load_16x16_gfx<T: FileSystem + ?Sized>(
+    gfx16x16_path: &Path,
+    file_system: &T,
+    cache: &mut TilesetCache,
+) -> Result<Vec<Tile16x16>> {
+    let gfx16x16_bytes = file_system.load(gfx16x16_path).with_context(|| {
         format!(
             "Unable to load CRE 16x16 tiles at {}",
             gfx16x16_path.display()
         )
     })?;
-    let mut tiles: Vec<Tile16x16> = vec![];
-    let mut i = 0;
-    while i < gfx16x16_bytes.len() {
-        let tile = decode_16x16_tile(&gfx16x16_bytes[i..(i + 8)]);
-        tiles.push(tile);
-        i += 8;
+    let id = blob_id(&gfx16x16_bytes);
+    if let Some(tiles) = cache.tiles.get(&id) {
+        return Ok(tiles.clone());
     }
+    MalformedAsset::check(
+        gfx16x16_path,
+        "16x16 tiles",
+        &gfx16x16_bytes,
+        8,
+        "four 16-bit 8x8 tile entries per 16x16 tile",
+    )?;
+    let tiles: Vec<Tile16x16> = gfx16x16_bytes
+        .chunks_exact(8)
+        .map(decode_16x16_tile)
+        .collect();
+    cache.tiles.insert(id, tiles.clone());
     Ok(tiles)
 }
 
-fn load_cre_tileset<T: FileSystem>(tileset_path: &Path, file_system: &T) -> Result<CRETileset> {
-    let gfx = load_8x8_gfx(&tileset_path.join("8x8tiles.gfx"), file_system)?;
-    let tiles = load_16x16_gfx(&tileset_path.join("16x16tiles.ttb"), file_system)?;
+fn load_cre_tileset<T: FileSystem + ?Sized>(
+    tileset_path: &Path,
+    file_system: &T,
+    cache: &mut TilesetCache,
+) -> Result<CRETileset> {
+    let gfx = load_8x8_gfx(&tileset_path.join("8x8tiles.gfx"), file_system, cache)?;
+    let tiles = load_16x16_gfx(&tileset_path.join("16x16tiles.ttb"), file_system, cache)?;
     Ok(CRETileset { gfx, tiles })
 }
 
-fn load_sce_tileset<T: FileSystem>(
+// Number of colors in a full SCE palette (8 lines of 16 colors)
+const SCE_PALETTE_SIZE: usize = 128;
+
+// For each palette line, the line whose colors tiles using it are drawn with, to preview a
+// reorganization of the palette (e.g. [0, 1, 2, 5, 4, 5, 6, 7] draws line 3 as line 5).
+pub type PaletteRemap = [usize; 8];
+
+pub const IDENTITY_PALETTE_REMAP: PaletteRemap = [0, 1, 2, 3, 4, 5, 6, 7];
+
+// SNES color math between layer 1 (on the main screen) and layer 2 (on the subscreen), as
+// some rooms use for a translucent layer 2. Without it, layer 2 is simply drawn behind layer 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMath {
+    #[default]
+    Off,
+    HalfAdd,
+    Add,
+    Subtract,
+}
+
+impl std::fmt::Display for ColorMath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorMath::Off => write!(f, "No color math"),
+            ColorMath::HalfAdd => write!(f, "Half add (translucent)"),
+            ColorMath::Add => write!(f, "Add"),
+            ColorMath::Subtract => write!(f, "Subtract"),
+        }
+    }
+}
+
+// Choices about how a room is drawn, other than where it comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub palette_remap: PaletteRemap,
+    // Frame of the tile animations to draw (each wraps around at its number of frames)
+    pub animation_frame: usize,
+    pub color_math: ColorMath,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            palette_remap: IDENTITY_PALETTE_REMAP,
+            animation_frame: 0,
+            color_math: ColorMath::Off,
+        }
+    }
+}
+
+// Lines missing from a short palette are left as they are (the colors out of range are then
+// reported as usual when rendering).
+fn remap_palette(palette: &[Color], remap: &PaletteRemap) -> Vec<Color> {
+    let mut out = palette.to_vec();
+    for (line, &source) in remap.iter().enumerate() {
+        for i in 0..16 {
+            if let (Some(&color), Some(out_color)) =
+                (palette.get(source * 16 + i), out.get_mut(line * 16 + i))
+            {
+                *out_color = color;
+            }
+        }
+    }
+    out
+}
+
+fn load_sce_tileset<T: FileSystem + ?Sized>(
     tileset_path: &Path,
     cre_tileset: &CRETileset,
     file_system: &T,
+    cache: &mut TilesetCache,
+    warnings: &mut Vec<String>,
 ) -> Result<SCETileset> {
-    let palette = load_palette(&tileset_path.join("palette.snes"), file_system)?;
-    let mut gfx = load_8x8_gfx(&tileset_path.join("8x8tiles.gfx"), file_system)?;
-    let sce_tiles = load_16x16_gfx(&tileset_path.join("16x16tiles.ttb"), file_system)?;
+    let palette_path = tileset_path.join("palette.snes");
+    let mut palette = load_palette(&palette_path, file_system)?;
+    if palette.len() < SCE_PALETTE_SIZE {
+        warnings.push(format!(
+            "Palette at {} has {} colors (expected {}); the rest are shown as magenta",
+            palette_path.display(),
+            palette.len(),
+            SCE_PALETTE_SIZE
+        ));
+        palette.resize(SCE_PALETTE_SIZE, [255, 0, 255]);
+    }
+    let mut gfx = load_8x8_gfx(&tileset_path.join("8x8tiles.gfx"), file_system, cache)?;
+    let sce_tiles = load_16x16_gfx(&tileset_path.join("16x16tiles.ttb"), file_system, cache)?;
 
     gfx.extend(cre_tileset.gfx.clone());
     let mut tiles = cre_tileset.tiles.clone();
@@ -191,11 +528,187 @@ fn load_sce_tileset<T: FileSystem>(
         palette,
         gfx,
         tiles,
+        missing: false,
     })
 }
 
-fn render_tile_8x8(image: &mut Image, x0: usize, y0: usize, tile: Tile8x8, tileset: &SCETileset) {
-    let gfx = &tileset.gfx[tile.idx];
+// Animated tiles of an SCE tileset are listed in `animated.toml` in its directory, if any:
+//
+//   [[animation]]
+//   gfx = "lava.gfx"  # frames of 4bpp 8x8 tiles, one after another
+//   first_tile = 0x1A0  # index of the first 8x8 tile which the frames replace
+//   tiles = 4  # number of 8x8 tiles in each frame
+//
+// Without it, tiles are drawn with the (static) graphics of the tileset.
+const ANIMATED_TILES_FILENAME: &str = "animated.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TileAnimation {
+    gfx: PathBuf,
+    first_tile: usize,
+    tiles: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct TileAnimationList {
+    #[serde(rename = "animation", default)]
+    animations: Vec<TileAnimation>,
+}
+
+// Replace the graphics of a tileset's animated tiles with the given frame of their animations.
+// Returns the files the animations came from (including the list, whether or not it exists)
+// and the number of frames of the longest animation.
+fn apply_tile_animations<T: FileSystem + ?Sized>(
+    tileset_path: &Path,
+    gfx: &mut [[[u8; 8]; 8]],
+    frame: usize,
+    file_system: &T,
+    cache: &mut TilesetCache,
+    warnings: &mut Vec<String>,
+) -> (Vec<PathBuf>, usize) {
+    let list_path = tileset_path.join(ANIMATED_TILES_FILENAME);
+    let mut files = vec![list_path.clone()];
+    let Ok(bytes) = file_system.load(&list_path) else {
+        return (files, 1);
+    };
+    let list: TileAnimationList = match std::str::from_utf8(&bytes)
+        .map_err(anyhow::Error::from)
+        .and_then(|s| toml::from_str(s).map_err(anyhow::Error::from))
+    {
+        Ok(list) => list,
+        Err(e) => {
+            warnings.push(format!("Unable to parse {}: {:#}", list_path.display(), e));
+            return (files, 1);
+        }
+    };
+    let mut max_frames = 1;
+    for animation in list.animations {
+        let path = tileset_path.join(&animation.gfx);
+        files.push(path.clone());
+        let frames_gfx = match load_8x8_gfx(&path, file_system, cache) {
+            Ok(frames_gfx) => frames_gfx,
+            Err(e) => {
+                warnings.push(format!("{:#}", e));
+                continue;
+            }
+        };
+        let frames = frames_gfx.len().checked_div(animation.tiles).unwrap_or(0);
+        let end = animation.first_tile + animation.tiles;
+        if frames == 0 || end > gfx.len() {
+            warnings.push(format!(
+                "Animated tiles in {} don't fit the tileset ({} tiles of graphics for {} tiles at {:X})",
+                path.display(),
+                frames_gfx.len(),
+                animation.tiles,
+                animation.first_tile
+            ));
+            continue;
+        }
+        let start = (frame % frames) * animation.tiles;
+        gfx[animation.first_tile..end].copy_from_slice(&frames_gfx[start..start + animation.tiles]);
+        max_frames = max_frames.max(frames);
+    }
+    (files, max_frames)
+}
+
+// A layer being rendered, with whether each pixel is from a high-priority 8x8 tile.
+struct Layer {
+    image: Image,
+    priority: Vec<bool>,
+}
+
+impl Layer {
+    fn new(width: usize, height: usize) -> Self {
+        Layer {
+            image: Image::new(width, height),
+            priority: vec![false; width * height],
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color, priority: bool) {
+        self.image.set_pixel(x, y, color);
+        self.priority[y * self.image.width + x] = priority;
+    }
+}
+
+// The pixels of layer 2 which the game draws in front of layer 1: on the SNES, high-priority
+// layer 1 tiles are in front of high-priority layer 2 tiles, which are in front of
+// low-priority layer 1 tiles.
+fn layer2_front(layer1: &Layer, layer2: &Layer) -> Image {
+    let (width, height) = (layer2.image.width, layer2.image.height);
+    let mut out = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            if layer2.priority[i]
+                && !layer2.image.get_transparent(x, y)
+                && (!layer1.priority[i] || layer1.image.get_transparent(x, y))
+            {
+                out.set_pixel(x, y, layer2.image.get_pixel(x, y));
+            }
+        }
+    }
+    out
+}
+
+// Combine layer 2 into layer 1 with color math, so that drawing layer 1 over layer 2 shows what
+// the game would. Where layer 1 is transparent, layer 2 is combined with the backdrop color,
+// and where layer 2 is, layer 1 is left as it is. Layer 2 being on the subscreen, none of it
+// is in front of layer 1.
+fn apply_color_math(
+    layer1: &mut Image,
+    layer2: &mut Image,
+    front: &mut Image,
+    backdrop: Color,
+    mode: ColorMath,
+) {
+    if mode == ColorMath::Off {
+        return;
+    }
+    let combine = |main: u8, sub: u8| match mode {
+        ColorMath::Off => main,
+        ColorMath::HalfAdd => ((main as u16 + sub as u16) / 2) as u8,
+        ColorMath::Add => main.saturating_add(sub),
+        ColorMath::Subtract => main.saturating_sub(sub),
+    };
+    for y in 0..layer1.height {
+        for x in 0..layer1.width {
+            if layer2.get_transparent(x, y) {
+                continue;
+            }
+            let sub = layer2.get_pixel(x, y);
+            if layer1.get_transparent(x, y) {
+                layer2.set_pixel(x, y, [0, 1, 2].map(|i| combine(backdrop[i], sub[i])));
+            } else {
+                let main = layer1.get_pixel(x, y);
+                layer1.set_pixel(x, y, [0, 1, 2].map(|i| combine(main[i], sub[i])));
+            }
+        }
+    }
+    *front = Image::new(front.width, front.height);
+}
+
+fn render_tile_8x8(
+    image: &mut Layer,
+    x0: usize,
+    y0: usize,
+    tile: Tile8x8,
+    tileset: &SCETileset,
+    out_of_range: &mut OutOfRange,
+) {
+    let Some(gfx) = tileset.gfx.get(tile.idx) else {
+        // Placeholder tiles and missing tilesets are already reported.
+        if tile.idx != MISSING_GFX_IDX && !tileset.missing {
+            out_of_range.gfx.insert(tile.idx);
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                image.set_pixel(x0 + x, y0 + y, missing_color(x, y), false);
+            }
+        }
+        return;
+    };
     for y in 0..8 {
         for x in 0..8 {
             let x1 = if tile.flip_x { 7 - x } else { x };
@@ -205,26 +718,47 @@ fn render_tile_8x8(image: &mut Image, x0: usize, y0: usize, tile: Tile8x8, tiles
                 continue;
             }
             let color_idx = tile.palette * 16 + (gfx[y1][x1] as usize);
-            let color = tileset.palette[color_idx];
-            image.set_pixel(x0 + x, y0 + y, color);
+            let color = match tileset.palette.get(color_idx) {
+                Some(&color) => color,
+                None => {
+                    if !tileset.missing {
+                        out_of_range.colors.insert(color_idx);
+                    }
+                    missing_color(x, y)
+                }
+            };
+            image.set_pixel(x0 + x, y0 + y, color, tile.priority);
         }
     }
 }
 
 fn render_tile_16x16(
-    image: &mut Image,
+    image: &mut Layer,
     x0: usize,
     y0: usize,
     tile: Tile16x16,
     tileset: &SCETileset,
+    out_of_range: &mut OutOfRange,
 ) {
-    render_tile_8x8(image, x0, y0, tile.top_left, tileset);
-    render_tile_8x8(image, x0 + 8, y0, tile.top_right, tileset);
-    render_tile_8x8(image, x0, y0 + 8, tile.bottom_left, tileset);
-    render_tile_8x8(image, x0 + 8, y0 + 8, tile.bottom_right, tileset);
+    render_tile_8x8(image, x0, y0, tile.top_left, tileset, out_of_range);
+    render_tile_8x8(image, x0 + 8, y0, tile.top_right, tileset, out_of_range);
+    render_tile_8x8(image, x0, y0 + 8, tile.bottom_left, tileset, out_of_range);
+    render_tile_8x8(
+        image,
+        x0 + 8,
+        y0 + 8,
+        tile.bottom_right,
+        tileset,
+        out_of_range,
+    );
 }
 
-fn render_bgdata(bgdata: &BGData, image: &mut Image, tileset: &SCETileset) -> Result<()> {
+fn render_bgdata(
+    bgdata: &BGData,
+    image: &mut Layer,
+    tileset: &SCETileset,
+    out_of_range: &mut OutOfRange,
+) -> Result<()> {
     for data in &bgdata.data {
         if data.type_ != "DECOMP" {
             continue;
@@ -235,27 +769,27 @@ fn render_bgdata(bgdata: &BGData, image: &mut Image, tileset: &SCETileset) -> Re
             tiles.push(tile);
         }
         if tiles.len() == 1024 {
-            for screen_y in 0..image.height / 256 {
-                for screen_x in 0..image.width / 256 {
+            for screen_y in 0..image.image.height / 256 {
+                for screen_x in 0..image.image.width / 256 {
                     for (i, &tile) in tiles.iter().enumerate() {
                         let x = screen_x * 256 + (i % 32) * 8;
                         let y = screen_y * 256 + (i / 32) * 8;
-                        render_tile_8x8(image, x, y, tile, tileset);
+                        render_tile_8x8(image, x, y, tile, tileset, out_of_range);
                     }
                 }
             }
         } else if tiles.len() == 2048 {
-            for screen_y in 0..image.height / 256 {
-                for screen_x2 in 0..image.width / 512 {
+            for screen_y in 0..image.image.height / 256 {
+                for screen_x2 in 0..image.image.width / 512 {
                     for (i, &tile) in tiles.iter().enumerate() {
                         if i < 1024 {
                             let x = screen_x2 * 512 + (i % 32) * 8;
                             let y = screen_y * 256 + (i / 32) * 8;
-                            render_tile_8x8(image, x, y, tile, tileset);
+                            render_tile_8x8(image, x, y, tile, tileset, out_of_range);
                         } else {
                             let x = screen_x2 * 512 + 256 + (i % 32) * 8;
                             let y = screen_y * 256 + ((i - 1024) / 32) * 8;
-                            render_tile_8x8(image, x, y, tile, tileset);
+                            render_tile_8x8(image, x, y, tile, tileset, out_of_range);
                         }
                     }
                 }
@@ -265,18 +799,31 @@ fn render_bgdata(bgdata: &BGData, image: &mut Image, tileset: &SCETileset) -> Re
     Ok(())
 }
 
-fn render_screens(screens: &[Screen], image: &mut Image, tileset: &SCETileset) {
+fn render_screens(
+    screens: &[Screen],
+    image: &mut Layer,
+    tileset: &SCETileset,
+    out_of_range: &mut OutOfRange,
+) {
     for screen in screens {
         let x0 = screen.x * 16;
         let y0 = screen.y * 16;
 
-        for (i, data) in screen.data.iter().enumerate() {
+        for (i, data) in screen.data.iter().take(SCREEN_TILES).enumerate() {
             let x = (i % 16) + x0;
             let y = (i / 16) + y0;
             let tile_idx = (data & 0x3FF) as usize;
             let tile_flip_x = (data & 0x400) != 0;
             let tile_flip_y = (data & 0x800) != 0;
-            let mut tile = tileset.tiles[tile_idx];
+            let mut tile = match tileset.tiles.get(tile_idx) {
+                Some(&tile) => tile,
+                None => {
+                    if !tileset.missing {
+                        out_of_range.tiles.insert(tile_idx);
+                    }
+                    MISSING_TILE_16X16
+                }
+            };
             if tile_flip_x {
                 (tile.top_left, tile.top_right) = (tile.top_right, tile.top_left);
                 (tile.bottom_left, tile.bottom_right) = (tile.bottom_right, tile.bottom_left);
@@ -293,55 +840,570 @@ fn render_screens(screens: &[Screen], image: &mut Image, tileset: &SCETileset) {
                 tile.bottom_left.flip_y = !tile.bottom_left.flip_y;
                 tile.bottom_right.flip_y = !tile.bottom_right.flip_y;
             }
-            render_tile_16x16(image, x * 16, y * 16, tile, tileset);
+            render_tile_16x16(image, x * 16, y * 16, tile, tileset, out_of_range);
+        }
+    }
+}
+
+// List the indices of the SCE tilesets in a project (in the working copy).
+pub fn list_tilesets(project_dir: &Path) -> Result<Vec<usize>> {
+    let sce_tilesets_dir = project_dir.join("Export/Tileset/SCE");
+    let mut tilesets: Vec<usize> = vec![];
+    for entry in std::fs::read_dir(&sce_tilesets_dir)
+        .with_context(|| format!("Unable to list {}", sce_tilesets_dir.display()))?
+    {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(idx) = entry
+            .file_name()
+            .to_str()
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+        {
+            tilesets.push(idx);
+        }
+    }
+    tilesets.sort();
+    Ok(tilesets)
+}
+
+// Width of a rendered tileset sheet, in 16x16 tiles.
+pub const TILESET_SHEET_WIDTH: usize = 32;
+
+// Render all 16x16 tiles of a tileset (including the CRE tiles) as a sheet.
+pub fn render_tileset<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    tileset_idx: usize,
+    file_system: &F,
+) -> Result<Image> {
+    let cache = &mut TilesetCache::default();
+    let cre_tileset = load_cre_tileset(
+        &project_dir.join("Export/Tileset/CRE/00/"),
+        file_system,
+        cache,
+    )?;
+    let tileset_path = project_dir
+        .join("Export/Tileset/SCE")
+        .join(format!("{:02X}", tileset_idx));
+    let mut warnings: Vec<String> = vec![];
+    let tileset = load_sce_tileset(
+        &tileset_path,
+        &cre_tileset,
+        file_system,
+        cache,
+        &mut warnings,
+    )?;
+    for warning in warnings {
+        info!("{}", warning);
+    }
+    let rows = tileset.tiles.len().div_ceil(TILESET_SHEET_WIDTH);
+    let mut sheet = Layer::new(TILESET_SHEET_WIDTH * 16, rows * 16);
+    for (i, &tile) in tileset.tiles.iter().enumerate() {
+        let x = (i % TILESET_SHEET_WIDTH) * 16;
+        let y = (i / TILESET_SHEET_WIDTH) * 16;
+        render_tile_16x16(&mut sheet, x, y, tile, &tileset, &mut OutOfRange::default());
+    }
+    Ok(sheet.image)
+}
+
+// The raw 15-bit BGR entries of an SCE tileset's palette, for inspecting them one by one.
+pub fn load_palette_entries<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    tileset_idx: usize,
+    file_system: &F,
+) -> Result<Vec<u16>> {
+    let palette_path = project_dir
+        .join("Export/Tileset/SCE")
+        .join(format!("{:02X}", tileset_idx))
+        .join("palette.snes");
+    let palette_bytes = file_system
+        .load(&palette_path)
+        .with_context(|| format!("Unable to load palette at {}", palette_path.display()))?;
+    Ok(palette_bytes
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect())
+}
+
+// Width of a rendered CRE graphics sheet, in 8x8 tiles.
+pub const CRE_GFX_SHEET_WIDTH: usize = 16;
+
+// Render all raw 8x8 graphics of the CRE, including the sprite, HUD and door tiles that
+// the 16x16 tiles don't use. These have no palette of their own, so are drawn in
+// grayscale.
+pub fn render_cre_gfx<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    file_system: &F,
+) -> Result<Image> {
+    let gfx = load_8x8_gfx(
+        &project_dir.join("Export/Tileset/CRE/00/8x8tiles.gfx"),
+        file_system,
+        &mut TilesetCache::default(),
+    )?;
+    let rows = gfx.len().div_ceil(CRE_GFX_SHEET_WIDTH);
+    let mut image = Image::new(CRE_GFX_SHEET_WIDTH * 8, rows * 8);
+    for (i, tile) in gfx.iter().enumerate() {
+        let x0 = (i % CRE_GFX_SHEET_WIDTH) * 8;
+        let y0 = (i / CRE_GFX_SHEET_WIDTH) * 8;
+        for (y, row) in tile.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                image.set_pixel(x0 + x, y0 + y, [c * 17; 3]);
+            }
         }
     }
+    Ok(image)
+}
+
+// List the names of the rooms in a project (in the working copy).
+pub fn list_rooms(project_dir: &Path) -> Result<Vec<String>> {
+    let mut room_list: Vec<String> = vec![];
+    for room in list_files_with_extension(&project_dir.join("Export/Rooms"), "xml")? {
+        room_list.push(
+            room.file_stem()
+                .context("file_stem")?
+                .to_string_lossy()
+                .to_string(),
+        )
+    }
+    room_list.sort();
+    Ok(room_list)
+}
+
+pub fn room_state_name(state_xml: &smart_xml::RoomState) -> String {
+    format!("{}: {}", state_xml.condition, state_xml.arg)
 }
 
-pub fn render_room<F: FileSystem>(
+pub fn load_room<F: FileSystem + ?Sized>(
     project_dir: &Path,
     room_name: &str,
     file_system: &F,
-) -> Result<RoomImages> {
+) -> Result<smart_xml::Room> {
     let room_path = project_dir.join(format!("Export/Rooms/{}.xml", room_name));
     let room_bytes = file_system
         .load(&room_path)
         .with_context(|| format!("Unable to load room at {}", room_path.display()))?;
-    let room_str = String::from_utf8(room_bytes)?;
-    let room: smart_xml::Room = serde_xml_rs::from_str(room_str.as_str())
+    parse_room(&room_bytes, &room_path)
+}
+
+// Parse a room's XML, loaded from `room_path` (which is used in errors).
+pub fn parse_room(room_bytes: &[u8], room_path: &Path) -> Result<smart_xml::Room> {
+    let room_str = std::str::from_utf8(room_bytes)?;
+    // Track the path of the field being parsed, so that errors name the element or
+    // attribute at fault (e.g. "States.State[0].GFXset").
+    let mut deserializer = serde_xml_rs::Deserializer::new_from_reader(room_str.as_bytes());
+    let room: smart_xml::Room = serde_path_to_error::deserialize(&mut deserializer)
         .with_context(|| format!("Unable to parse XML in {}", room_path.display()))?;
+    Ok(room)
+}
+
+// Number of 16x16 tiles in a screen
+const SCREEN_TILES: usize = 256;
+
+// Screens outside the room are an error. Screens with the wrong number of tiles are
+// rendered truncated (or with the missing tiles left blank), with a warning.
+fn check_screens(
+    screens: &[Screen],
+    room: &smart_xml::Room,
+    layer_name: &str,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    for screen in screens {
+        if screen.x >= room.width || screen.y >= room.height {
+            bail!(
+                "{} screen ({:X}, {:X}) is outside the {:X} x {:X} room",
+                layer_name,
+                screen.x,
+                screen.y,
+                room.width,
+                room.height
+            );
+        }
+        if screen.data.len() != SCREEN_TILES {
+            warnings.push(format!(
+                "{} screen ({:X}, {:X}) has {} tiles (expected {})",
+                layer_name,
+                screen.x,
+                screen.y,
+                screen.data.len(),
+                SCREEN_TILES
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Render layers 1 and 2 of one room state, and the part of layer 2 in front of layer 1.
+fn render_state(
+    room: &smart_xml::Room,
+    state_xml: &smart_xml::RoomState,
+    tileset: &SCETileset,
+    out_of_range: &mut OutOfRange,
+    warnings: &mut Vec<String>,
+) -> Result<(Image, Image, Image)> {
+    check_screens(
+        &state_xml.level_data.layer_1.screen,
+        room,
+        "Layer 1",
+        warnings,
+    )?;
+    check_screens(
+        &state_xml.level_data.layer_2.screen,
+        room,
+        "Layer 2",
+        warnings,
+    )?;
+    let width = room.width * 256;
+    let height = room.height * 256;
+
+    let mut layer1 = Layer::new(width, height);
+    render_screens(
+        &state_xml.level_data.layer_1.screen,
+        &mut layer1,
+        tileset,
+        out_of_range,
+    );
+
+    let mut layer2 = Layer::new(width, height);
+    render_bgdata(&state_xml.bg_data, &mut layer2, tileset, out_of_range)?;
+    render_screens(
+        &state_xml.level_data.layer_2.screen,
+        &mut layer2,
+        tileset,
+        out_of_range,
+    );
+    let front = layer2_front(&layer1, &layer2);
+    Ok((layer1.image, layer2.image, front))
+}
+
+// Approximate the layer 3 effect of a room state: its graphics come from the ROM rather than
+// the project, so liquids are drawn as a translucent fill below their surface, and the other
+// effects as a tint or pattern over the whole room. The state's first FX entry is used (the
+// others only apply when entering through particular doors).
+fn render_fx(state_xml: &smart_xml::RoomState, width: usize, height: usize) -> Image {
+    let mut image = Image::new(width, height);
+    let Some(fx) = state_xml.fx.fx.first() else {
+        return image;
+    };
+    let liquid = match fx.type_ {
+        0x02 => Some([224, 80, 16]),
+        0x04 => Some([64, 200, 32]),
+        0x06 => Some([32, 96, 224]),
+        _ => None,
+    };
+    if let Some(color) = liquid {
+        if fx.surface_start == 0xFFFF {
+            return image;
+        }
+        for y in fx.surface_start.min(height)..height {
+            // A brighter line at the surface
+            let alpha = if y < fx.surface_start + 2 { 192 } else { 96 };
+            for x in 0..width {
+                image.set_pixel_alpha(x, y, color, alpha);
+            }
+        }
+        return image;
+    }
+    for y in 0..height {
+        for x in 0..width {
+            match fx.type_ {
+                // Spores
+                0x08 if x % 32 == 0 && y % 32 == (x / 32 * 11) % 32 => {
+                    image.set_pixel_alpha(x, y, [224, 224, 160], 160)
+                }
+                // Rain
+                0x0A if (x + y / 2) % 24 == 0 && y % 16 < 8 => {
+                    image.set_pixel_alpha(x, y, [160, 192, 255], 128)
+                }
+                // Fog and haze
+                0x0C | 0x2C => image.set_pixel_alpha(x, y, [160, 160, 176], 64),
+                // Ceres Ridley and elevator (ripple)
+                0x28 | 0x2A if y % 8 < 2 => image.set_pixel_alpha(x, y, [96, 128, 192], 48),
+                _ => {}
+            }
+        }
+    }
+    image
+}
+
+// Palette lines used by each 16x16 tile of layer 1, as bitmasks.
+fn palette_lines(
+    room: &smart_xml::Room,
+    state_xml: &smart_xml::RoomState,
+    tileset: &SCETileset,
+) -> Vec<u8> {
+    let width = room.width * 16;
+    let mut out = vec![0; width * room.height * 16];
+    for screen in &state_xml.level_data.layer_1.screen {
+        for (i, data) in screen.data.iter().take(SCREEN_TILES).enumerate() {
+            let x = screen.x * 16 + i % 16;
+            let y = screen.y * 16 + i / 16;
+            let Some(tile) = tileset.tiles.get((data & 0x3FF) as usize) else {
+                continue;
+            };
+            if let Some(mask) = out.get_mut(y * width + x) {
+                for t in [
+                    tile.top_left,
+                    tile.top_right,
+                    tile.bottom_left,
+                    tile.bottom_right,
+                ] {
+                    *mask |= 1 << (t.palette & 7);
+                }
+            }
+        }
+    }
+    out
+}
+
+// Red diagonal stripes, shown in place of a room state that failed to render.
+fn error_placeholder(width: usize, height: usize) -> Image {
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            if (x + y) % 32 < 16 {
+                image.set_pixel(x, y, [128, 0, 0]);
+            } else {
+                image.set_pixel(x, y, [48, 0, 0]);
+            }
+        }
+    }
+    image
+}
+
+pub fn render_room<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    room_name: &str,
+    file_system: &F,
+) -> Result<RoomImages> {
+    render_room_with_options(
+        project_dir,
+        room_name,
+        file_system,
+        &RenderOptions::default(),
+    )
+}
+
+pub fn render_room_with_options<F: FileSystem + ?Sized>(
+    project_dir: &Path,
+    room_name: &str,
+    file_system: &F,
+    options: &RenderOptions,
+) -> Result<RoomImages> {
+    render_room_with_sources(project_dir, room_name, file_system, file_system, options)
+}
+
+// The message of a caught panic (e.g. from indexing out of bounds).
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string())
+}
+
+// Render a room with its XML and its tilesets from different file systems, e.g. to show one
+// version's level data with the other version's graphics.
+pub fn render_room_with_sources<F: FileSystem + ?Sized, G: FileSystem + ?Sized>(
+    project_dir: &Path,
+    room_name: &str,
+    room_file_system: &F,
+    tileset_file_system: &G,
+    options: &RenderOptions,
+) -> Result<RoomImages> {
+    render_room_with_cache(
+        project_dir,
+        room_name,
+        room_file_system,
+        tileset_file_system,
+        options,
+        &mut TilesetCache::default(),
+    )
+}
+
+// As `render_room_with_sources`, reusing tilesets decoded for earlier renders.
+//
+// A bug triggered by malformed data shouldn't end a long review session, so a panic while
+// rendering a state gives that state the error placeholder, and a panic elsewhere (e.g. while
+// loading the tilesets) is returned as an error.
+pub fn render_room_with_cache<F: FileSystem + ?Sized, G: FileSystem + ?Sized>(
+    project_dir: &Path,
+    room_name: &str,
+    room_file_system: &F,
+    tileset_file_system: &G,
+    options: &RenderOptions,
+    cache: &mut TilesetCache,
+) -> Result<RoomImages> {
+    catch_unwind(AssertUnwindSafe(|| {
+        render_room_unguarded(
+            project_dir,
+            room_name,
+            room_file_system,
+            tileset_file_system,
+            options,
+            cache,
+        )
+    }))
+    .unwrap_or_else(|panic| {
+        Err(anyhow!(
+            "Rendering {} panicked: {}",
+            room_name,
+            panic_message(panic.as_ref())
+        ))
+    })
+}
+
+fn render_room_unguarded<F: FileSystem + ?Sized, G: FileSystem + ?Sized>(
+    project_dir: &Path,
+    room_name: &str,
+    room_file_system: &F,
+    tileset_file_system: &G,
+    options: &RenderOptions,
+    cache: &mut TilesetCache,
+) -> Result<RoomImages> {
+    let room = load_room(project_dir, room_name, room_file_system)?;
+    let file_system = tileset_file_system;
 
     let cre_tileset_dir = project_dir.join("Export/Tileset/CRE/00/");
-    let cre_tileset = load_cre_tileset(&cre_tileset_dir, file_system)?;
+    let mut warnings: Vec<String> = vec![];
+    let mut malformed_assets: Vec<MalformedAsset> = vec![];
+    let cre_tileset = load_cre_tileset(&cre_tileset_dir, file_system, cache).unwrap_or_else(|e| {
+        warnings.push(format!("{:#}", e));
+        malformed_assets.extend(malformed_asset(&e).cloned());
+        CRETileset::missing()
+    });
 
     let sce_tilesets_dir = project_dir.join("Export/Tileset/SCE");
 
     let mut room_state_name_list: Vec<String> = vec![];
     let mut layer1_list: Vec<Image> = vec![];
+    let mut palette_lines_list: Vec<Vec<u8>> = vec![];
     let mut layer2_list: Vec<Image> = vec![];
+    let mut layer2_front_list: Vec<Image> = vec![];
+    let mut fx_list: Vec<Image> = vec![];
+    let mut backdrop_list: Vec<Color> = vec![];
+    let mut animation_frames = 1;
+    let mut dependencies: Vec<PathBuf> = vec![
+        project_dir.join(format!("Export/Rooms/{}.xml", room_name)),
+        cre_tileset_dir.join("8x8tiles.gfx"),
+        cre_tileset_dir.join("16x16tiles.ttb"),
+    ];
 
     for state_xml in room.states.state.iter() {
-        let room_state_name = format!("{}: {}", state_xml.condition, state_xml.arg);
-        room_state_name_list.push(room_state_name);
-
         let gfx_set_str = format!("{:02X}", state_xml.gfx_set);
         let tileset_path = sce_tilesets_dir.join(gfx_set_str);
-        let tileset = load_sce_tileset(&tileset_path, &cre_tileset, file_system)?;
-        let width = room.width * 256;
-        let height = room.height * 256;
-
-        let mut layer1 = Image::new(width, height);
-        render_screens(&state_xml.level_data.layer_1.screen, &mut layer1, &tileset);
-        layer1_list.push(layer1);
-
-        let mut layer2 = Image::new(width, height);
-        render_bgdata(&state_xml.bg_data, &mut layer2, &tileset)?;
-        render_screens(&state_xml.level_data.layer_2.screen, &mut layer2, &tileset);
-        layer2_list.push(layer2);
+        let mut tileset_warnings: Vec<String> = vec![];
+        let mut animation_files: Vec<PathBuf> = vec![];
+        let tileset = match load_sce_tileset(
+            &tileset_path,
+            &cre_tileset,
+            file_system,
+            cache,
+            &mut tileset_warnings,
+        ) {
+            Ok(mut tileset) => {
+                if options.palette_remap != IDENTITY_PALETTE_REMAP {
+                    tileset.palette = remap_palette(&tileset.palette, &options.palette_remap);
+                }
+                let (files, frames) = apply_tile_animations(
+                    &tileset_path,
+                    &mut tileset.gfx,
+                    options.animation_frame,
+                    file_system,
+                    cache,
+                    &mut tileset_warnings,
+                );
+                animation_files.extend(files);
+                animation_frames = animation_frames.max(frames);
+                tileset
+            }
+            Err(e) => {
+                tileset_warnings.push(format!("{:#}", e));
+                if let Some(asset) = malformed_asset(&e).filter(|a| !malformed_assets.contains(a)) {
+                    malformed_assets.push(asset.clone());
+                }
+                SCETileset::missing(&cre_tileset)
+            }
+        };
+        // States often share a tileset, so only report its problems once.
+        for warning in tileset_warnings {
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        let tileset_files = ["palette.snes", "8x8tiles.gfx", "16x16tiles.ttb"]
+            .into_iter()
+            .map(|filename| tileset_path.join(filename));
+        for path in tileset_files.chain(animation_files) {
+            if !dependencies.contains(&path) {
+                dependencies.push(path);
+            }
+        }
+        let state_idx = room_state_name_list.len();
+        let mut out_of_range = OutOfRange::default();
+        let mut state_warnings: Vec<String> = vec![];
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            render_state(
+                &room,
+                state_xml,
+                &tileset,
+                &mut out_of_range,
+                &mut state_warnings,
+            )
+        }))
+        .unwrap_or_else(|panic| {
+            Err(anyhow!(
+                "Rendering panicked: {}",
+                panic_message(panic.as_ref())
+            ))
+        });
+        for problem in state_warnings {
+            warnings.push(format!("State {}: {}", state_idx, problem));
+        }
+        let backdrop = tileset.palette.first().copied().unwrap_or([0, 0, 0]);
+        backdrop_list.push(backdrop);
+        match result {
+            Ok((mut layer1, mut layer2, mut layer2_front)) => {
+                apply_color_math(
+                    &mut layer1,
+                    &mut layer2,
+                    &mut layer2_front,
+                    backdrop,
+                    options.color_math,
+                );
+                for problem in out_of_range.describe() {
+                    warnings.push(format!("State {}: {}", state_idx, problem));
+                }
+                room_state_name_list.push(room_state_name(state_xml));
+                layer1_list.push(layer1);
+                palette_lines_list.push(palette_lines(&room, state_xml, &tileset));
+                layer2_list.push(layer2);
+                layer2_front_list.push(layer2_front);
+                fx_list.push(render_fx(state_xml, room.width * 256, room.height * 256));
+            }
+            Err(e) => {
+                warnings.push(format!("State {}: {:#}", state_idx, e));
+                room_state_name_list.push(format!("{} (error)", room_state_name(state_xml)));
+                layer1_list.push(error_placeholder(room.width * 256, room.height * 256));
+                palette_lines_list.push(vec![0; room.width * room.height * SCREEN_TILES]);
+                layer2_list.push(Image::new(room.width * 256, room.height * 256));
+                layer2_front_list.push(Image::new(room.width * 256, room.height * 256));
+                fx_list.push(Image::new(room.width * 256, room.height * 256));
+            }
+        }
     }
     Ok(RoomImages {
         room_state_names: room_state_name_list,
+        dependencies,
+        room_xml: room,
+        warnings,
+        malformed_assets,
         layer1: layer1_list,
+        palette_lines: palette_lines_list,
         layer2: layer2_list,
+        layer2_front: layer2_front_list,
+        fx: fx_list,
+        backdrop: backdrop_list,
+        animation_frames,
     })
 }
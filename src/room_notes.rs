@@ -0,0 +1,37 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::guarded_write::{PlannedWrite, WriteGuard};
+
+// Review notes on rooms are kept in the repository, so that they travel with branches.
+pub const NOTES_DIR: &str = ".smartdiff/notes";
+
+pub fn note_path(project: &Path, room: &str) -> PathBuf {
+    Path::new(NOTES_DIR)
+        .join(project)
+        .join(format!("{}.md", room))
+}
+
+// A missing note is the same as an empty one.
+pub fn load_note(project: &Path, room: &str) -> Result<String> {
+    let path = note_path(project, room);
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    std::fs::read_to_string(&path)
+        .with_context(|| format!("Unable to read room note {}", path.display()))
+}
+
+// Saving an empty note removes its file, so that rooms without notes leave nothing behind.
+// Returns a description of what was (or in a dry run, would have been) done.
+pub fn save_note(guard: &WriteGuard, project: &Path, room: &str, note: &str) -> Result<String> {
+    let write = PlannedWrite {
+        path: note_path(project, room),
+        contents: (!note.trim().is_empty()).then(|| note.as_bytes().to_vec()),
+    };
+    let mut descriptions = guard
+        .apply("Save room note", std::slice::from_ref(&write))
+        .with_context(|| format!("Unable to save room note {}", write.path.display()))?;
+    Ok(descriptions.pop().unwrap_or_default())
+}
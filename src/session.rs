@@ -0,0 +1,81 @@
+// Where the user was in the UI, saved as they go, so that it can be restored if smartdiff
+// doesn't exit cleanly (e.g. crashes or is killed) in the middle of a long review.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config::create_state_dir;
+
+pub const SESSION_FILENAME: &str = ".smartdiff/session.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SavedSource {
+    WorkingCopy,
+    Reference,
+    Difference,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Navigation {
+    pub project: PathBuf,
+    pub room: String,
+    pub state_idx: usize,
+    pub source: SavedSource,
+    pub scroll_x: f32,
+    pub scroll_y: f32,
+    pub zoom: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    clean_exit: bool,
+    navigation: Navigation,
+}
+
+// The navigation saved by the previous run, if it didn't exit cleanly.
+pub fn load_unclean(path: &Path) -> Option<Navigation> {
+    let s = std::fs::read_to_string(path).ok()?;
+    match toml::from_str::<SessionFile>(&s) {
+        Ok(file) => (!file.clean_exit).then_some(file.navigation),
+        Err(e) => {
+            warn!("Ignoring saved session at {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn write(path: &Path, file: &SessionFile) -> Result<()> {
+    create_state_dir()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create directory {}", parent.display()))?;
+    }
+    std::fs::write(path, toml::to_string(file)?)
+        .with_context(|| format!("Unable to save session to {}", path.display()))
+}
+
+pub fn save(path: &Path, navigation: &Navigation) -> Result<()> {
+    write(
+        path,
+        &SessionFile {
+            clean_exit: false,
+            navigation: navigation.clone(),
+        },
+    )
+}
+
+// Record that the session ended normally, so that it isn't offered for restoring.
+pub fn mark_clean(path: &Path) -> Result<()> {
+    let Ok(s) = std::fs::read_to_string(path) else {
+        return Ok(());
+    };
+    let Ok(mut file) = toml::from_str::<SessionFile>(&s) else {
+        return Ok(());
+    };
+    file.clean_exit = true;
+    write(path, &file)
+}
@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::config::NumberFormat;
+use crate::workspace::project_key;
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// What was looked at during a review session, so that it can be handed in afterward.
+#[derive(Debug, Serialize)]
+pub struct SessionLog {
+    pub reviewer: Option<String>,
+    pub reference: String,
+    // Unix time in seconds
+    pub started: u64,
+    pub rooms: Vec<RoomReview>,
+    #[serde(skip)]
+    current: Option<(usize, Instant)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomReview {
+    pub project: String,
+    pub room: String,
+    // Unix time in seconds
+    pub first_visited: u64,
+    pub seconds_spent: u64,
+    #[serde(skip)]
+    time_spent: Duration,
+    // Sources (working copy, reference, difference) in the order they were first viewed
+    pub sources_viewed: Vec<String>,
+    pub notes: String,
+    // The room's note from the repository, as of when the log was exported
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub room_note: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<ScreenBookmark>,
+}
+
+// A screen of a room state marked as needing attention
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScreenBookmark {
+    pub state_idx: usize,
+    // Position in screens
+    pub x: usize,
+    pub y: usize,
+    pub note: String,
+}
+
+impl RoomReview {
+    // Where the exported image of a bookmarked screen goes, relative to the Markdown log.
+    // `version` is "working" or "reference".
+    pub fn bookmark_image_path(&self, bookmark: &ScreenBookmark, version: &str) -> PathBuf {
+        PathBuf::from("review")
+            .join(project_key(None, Path::new(&self.project)))
+            .join(&self.room)
+            .join(format!(
+                "{}_{}_{}_{}.png",
+                bookmark.state_idx, bookmark.x, bookmark.y, version
+            ))
+    }
+}
+
+impl SessionLog {
+    pub fn new(reviewer: Option<String>, reference: String) -> Self {
+        Self {
+            reviewer,
+            reference,
+            started: unix_time(SystemTime::now()),
+            rooms: vec![],
+            current: None,
+        }
+    }
+
+    // Add the time since the current room was opened (or last checked) to its total.
+    fn update_time_spent(&mut self) {
+        if let Some((idx, since)) = &mut self.current {
+            let now = Instant::now();
+            let room = &mut self.rooms[*idx];
+            room.time_spent += now.duration_since(*since);
+            room.seconds_spent = room.time_spent.as_secs();
+            *since = now;
+        }
+    }
+
+    pub fn visit(&mut self, project: &str, room: &str, source: &str) {
+        self.update_time_spent();
+        let idx = match self
+            .rooms
+            .iter()
+            .position(|r| r.project == project && r.room == room)
+        {
+            Some(idx) => idx,
+            None => {
+                self.rooms.push(RoomReview {
+                    project: project.to_string(),
+                    room: room.to_string(),
+                    first_visited: unix_time(SystemTime::now()),
+                    seconds_spent: 0,
+                    time_spent: Duration::ZERO,
+                    sources_viewed: vec![],
+                    notes: String::new(),
+                    room_note: String::new(),
+                    bookmarks: vec![],
+                });
+                self.rooms.len() - 1
+            }
+        };
+        if self.current.is_none_or(|(i, _)| i != idx) {
+            self.current = Some((idx, Instant::now()));
+        }
+        self.source_viewed(source);
+    }
+
+    pub fn source_viewed(&mut self, source: &str) {
+        if let Some((idx, _)) = self.current {
+            let sources = &mut self.rooms[idx].sources_viewed;
+            if !sources.iter().any(|s| s == source) {
+                sources.push(source.to_string());
+            }
+        }
+    }
+
+    pub fn current_notes(&self) -> Option<&str> {
+        self.current.map(|(idx, _)| self.rooms[idx].notes.as_str())
+    }
+
+    pub fn set_notes(&mut self, notes: String) {
+        if let Some((idx, _)) = self.current {
+            self.rooms[idx].notes = notes;
+        }
+    }
+
+    pub fn current_bookmarks(&self) -> &[ScreenBookmark] {
+        match self.current {
+            Some((idx, _)) => &self.rooms[idx].bookmarks,
+            None => &[],
+        }
+    }
+
+    // Bookmark a screen of the current room, unless it already is.
+    pub fn add_bookmark(&mut self, state_idx: usize, x: usize, y: usize) {
+        if let Some((idx, _)) = self.current {
+            let bookmarks = &mut self.rooms[idx].bookmarks;
+            if !bookmarks
+                .iter()
+                .any(|b| (b.state_idx, b.x, b.y) == (state_idx, x, y))
+            {
+                bookmarks.push(ScreenBookmark {
+                    state_idx,
+                    x,
+                    y,
+                    note: String::new(),
+                });
+            }
+        }
+    }
+
+    pub fn set_bookmark_note(&mut self, bookmark_idx: usize, note: String) {
+        let bookmark = self
+            .current
+            .and_then(|(idx, _)| self.rooms[idx].bookmarks.get_mut(bookmark_idx));
+        if let Some(bookmark) = bookmark {
+            bookmark.note = note;
+        }
+    }
+
+    pub fn remove_bookmark(&mut self, bookmark_idx: usize) {
+        if let Some((idx, _)) = self.current {
+            let bookmarks = &mut self.rooms[idx].bookmarks;
+            if bookmark_idx < bookmarks.len() {
+                bookmarks.remove(bookmark_idx);
+            }
+        }
+    }
+
+    pub fn export_json(&mut self) -> Result<String> {
+        self.update_time_spent();
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn export_markdown(&mut self, format: NumberFormat) -> String {
+        self.update_time_spent();
+        let mut out = String::from("# Review log\n\n");
+        if let Some(reviewer) = &self.reviewer {
+            out += &format!("Reviewer: {}\n\n", reviewer);
+        }
+        out += &format!("Compared against: {}\n\n", self.reference);
+        out += "| Room | Time spent | Sources viewed | Notes |\n|---|---|---|---|\n";
+        for room in &self.rooms {
+            out += &format!(
+                "| {}/{} | {}m {:02}s | {} | {} |\n",
+                room.project,
+                room.room,
+                room.seconds_spent / 60,
+                room.seconds_spent % 60,
+                room.sources_viewed.join(", "),
+                room.notes.replace('|', "\\|").replace('\n', " "),
+            );
+        }
+        for room in self.rooms.iter().filter(|r| !r.room_note.is_empty()) {
+            out += &format!(
+                "\n## {}/{}\n\n{}\n",
+                room.project,
+                room.room,
+                room.room_note.trim_end()
+            );
+        }
+        // Images of bookmarked screens are saved alongside the log, except for versions that
+        // aren't available (e.g. a screen of a new room has no reference image).
+        let bookmarked = self.rooms.iter().filter(|r| !r.bookmarks.is_empty());
+        for (i, room) in bookmarked.enumerate() {
+            if i == 0 {
+                out += "\n## Needs attention\n";
+            }
+            for bookmark in &room.bookmarks {
+                out += &format!(
+                    "\n### {}/{}, state {}, screen ({}, {})\n\n",
+                    room.project,
+                    room.room,
+                    bookmark.state_idx,
+                    format.format(bookmark.x, 0),
+                    format.format(bookmark.y, 0)
+                );
+                if !bookmark.note.is_empty() {
+                    out += &format!("{}\n\n", bookmark.note);
+                }
+                out += &format!(
+                    "| Working copy | Reference |\n|---|---|\n| ![]({}) | ![]({}) |\n",
+                    room.bookmark_image_path(bookmark, "working").display(),
+                    room.bookmark_image_path(bookmark, "reference").display()
+                );
+            }
+        }
+        out
+    }
+}
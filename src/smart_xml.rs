@@ -1,11 +1,28 @@
 use serde::{Deserialize, Deserializer, de::Error};
 
+// Parse a hex number, allowing an optional "0x" prefix and surrounding whitespace, as
+// hand-edited or older exports may contain. An empty string is 0.
+fn parse_hex<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let token = s.trim();
+    let digits = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+        .unwrap_or(token);
+    if digits.is_empty() {
+        return T::try_from(0).map_err(|_| format!("invalid hex value {:?}", token));
+    }
+    u64::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|x| T::try_from(x).ok())
+        .ok_or_else(|| format!("invalid hex value {:?}", token))
+}
+
 fn from_hex<'de, D>(deserializer: D) -> Result<usize, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s: String = Deserialize::deserialize(deserializer)?;
-    usize::from_str_radix(&s, 16).map_err(D::Error::custom)
+    parse_hex(&s).map_err(D::Error::custom)
 }
 
 pub fn from_hex_words<'de, D>(deserializer: D) -> Result<Vec<u16>, D::Error>
@@ -15,7 +32,7 @@ where
     let s: String = Deserialize::deserialize(deserializer)?;
     let mut out: Vec<u16> = vec![];
     for word in s.split_ascii_whitespace() {
-        out.push(u16::from_str_radix(word, 16).map_err(D::Error::custom)?);
+        out.push(parse_hex(word).map_err(D::Error::custom)?);
     }
     Ok(out)
 }
@@ -27,7 +44,7 @@ where
     let s: String = Deserialize::deserialize(deserializer)?;
     let mut out: Vec<u32> = vec![];
     for word in s.split_ascii_whitespace() {
-        out.push(u32::from_str_radix(word, 16).map_err(D::Error::custom)?);
+        out.push(parse_hex(word).map_err(D::Error::custom)?);
     }
     Ok(out)
 }
@@ -60,10 +77,19 @@ pub struct Layer2 {
     pub screen: Vec<Screen>,
 }
 
+// Block type-specific data (one byte per 16x16 tile of layer 1)
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Bts {
+    #[serde(rename = "Screen")]
+    pub screen: Vec<Screen>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct LevelData {
     #[serde(rename = "Layer1")]
     pub layer_1: Layer1,
+    #[serde(rename = "BTS", default)]
+    pub bts: Bts,
     #[serde(rename = "Layer2", default)]
     pub layer_2: Layer2,
 }
@@ -86,6 +112,80 @@ pub struct BGData {
     pub data: Vec<BGDataData>,
 }
 
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq, Hash)]
+pub struct PLM {
+    #[serde(rename = "type", deserialize_with = "from_hex", default)]
+    pub type_: usize,
+    #[serde(rename = "X", deserialize_with = "from_hex", default)]
+    pub x: usize,
+    #[serde(rename = "Y", deserialize_with = "from_hex", default)]
+    pub y: usize,
+    #[serde(rename = "arg", deserialize_with = "from_hex", default)]
+    pub arg: usize,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PLMList {
+    #[serde(rename = "PLM", default)]
+    pub plm: Vec<PLM>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Enemy {
+    #[serde(rename = "ID", deserialize_with = "from_hex", default)]
+    pub id: usize,
+    #[serde(rename = "X", deserialize_with = "from_hex", default)]
+    pub x: usize,
+    #[serde(rename = "Y", deserialize_with = "from_hex", default)]
+    pub y: usize,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct EnemyList {
+    #[serde(rename = "Enemy", default)]
+    pub enemy: Vec<Enemy>,
+}
+
+// An entry of a state's enemy set: the graphics of an enemy species to load, and the
+// palette line to load them into.
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq, Hash)]
+pub struct EnemyGFX {
+    #[serde(rename = "ID", deserialize_with = "from_hex", default)]
+    pub id: usize,
+    #[serde(rename = "palette", deserialize_with = "from_hex", default)]
+    pub palette: usize,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct EnemyGFXList {
+    #[serde(rename = "Enemy", default)]
+    pub enemy: Vec<EnemyGFX>,
+}
+
+fn no_surface() -> usize {
+    0xFFFF
+}
+
+// Layer 3 effect of a state (e.g. water, fog, or rain)
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct FX {
+    #[serde(rename = "type", deserialize_with = "from_hex", default)]
+    pub type_: usize,
+    // Y position of a liquid's surface in pixels, or FFFF for none
+    #[serde(
+        rename = "surfacestart",
+        deserialize_with = "from_hex",
+        default = "no_surface"
+    )]
+    pub surface_start: usize,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct FXList {
+    #[serde(rename = "FX1", default)]
+    pub fx: Vec<FX>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct RoomState {
     pub condition: String,
@@ -93,10 +193,28 @@ pub struct RoomState {
     pub arg: usize,
     #[serde(rename = "GFXset", deserialize_with = "from_hex")]
     pub gfx_set: usize,
+    #[serde(default)]
+    pub music: String,
+    #[serde(default)]
+    pub layer2_xscroll: String,
+    #[serde(default)]
+    pub layer2_yscroll: String,
+    #[serde(rename = "setupASM", default)]
+    pub setup_asm: String,
+    #[serde(rename = "mainASM", default)]
+    pub main_asm: String,
     #[serde(rename = "LevelData")]
     pub level_data: LevelData,
     #[serde(rename = "BGData")]
     pub bg_data: BGData,
+    #[serde(rename = "PLMs", default)]
+    pub plms: PLMList,
+    #[serde(rename = "Enemies", default)]
+    pub enemies: EnemyList,
+    #[serde(rename = "EnemyGFX", default)]
+    pub enemy_gfx: EnemyGFXList,
+    #[serde(rename = "FX1s", default)]
+    pub fx: FXList,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -105,12 +223,55 @@ pub struct RoomStateList {
     pub state: Vec<RoomState>,
 }
 
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Door {
+    #[serde(rename = "roomarea", deserialize_with = "from_hex", default)]
+    pub room_area: usize,
+    #[serde(rename = "roomindex", deserialize_with = "from_hex", default)]
+    pub room_index: usize,
+    #[serde(rename = "direction", deserialize_with = "from_hex", default)]
+    pub direction: usize,
+    #[serde(rename = "tilex", deserialize_with = "from_hex", default)]
+    pub tile_x: usize,
+    #[serde(rename = "tiley", deserialize_with = "from_hex", default)]
+    pub tile_y: usize,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Doors {
+    #[serde(rename = "Door", default)]
+    pub door: Vec<Door>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Room {
+    #[serde(deserialize_with = "from_hex", default)]
+    pub area: usize,
+    #[serde(deserialize_with = "from_hex", default)]
+    pub index: usize,
+    #[serde(deserialize_with = "from_hex", default)]
+    pub x: usize,
+    #[serde(deserialize_with = "from_hex", default)]
+    pub y: usize,
     #[serde(deserialize_with = "from_hex")]
     pub width: usize,
     #[serde(deserialize_with = "from_hex")]
     pub height: usize,
+    #[serde(default)]
+    pub upscroller: String,
+    #[serde(default)]
+    pub dnscroller: String,
+    #[serde(rename = "specialGFX", default)]
+    pub special_gfx: String,
+    #[serde(rename = "Doors", default)]
+    pub doors: Doors,
     #[serde(rename = "States")]
     pub states: RoomStateList,
 }
+
+impl RoomState {
+    // Whether the state has anything to draw on layer 2 (tiles or BG data).
+    pub fn has_layer2(&self) -> bool {
+        !self.level_data.layer_2.screen.is_empty() || !self.bg_data.data.is_empty()
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use log::{error, info, warn};
+
+use crate::{
+    export::{check_output_dir, image_hash, list_files, planned_png, remove_empty_dirs},
+    file_system::LocalFileSystem,
+    guarded_write::{PlannedWrite, WriteGuard},
+    render_session::RenderSession,
+    room::list_rooms,
+};
+
+const MANIFEST_FILENAME: &str = "hashes.txt";
+// Where `--verify` writes the current renders of the snapshots that failed
+const ACTUAL_DIR: &str = "actual";
+
+#[derive(clap::Args)]
+pub struct SnapshotArgs {
+    /// Overwrite the baseline with the current renders
+    #[arg(long, conflicts_with = "verify", required_unless_present = "verify")]
+    update: bool,
+    /// Check the current renders against the baseline
+    #[arg(long)]
+    verify: bool,
+    /// Directory containing the baseline
+    #[arg(long, default_value = ".smartdiff/snapshots")]
+    dir: PathBuf,
+}
+
+struct Snapshot {
+    // Key of the form "project/room/state_idx"
+    key: String,
+    image: crate::room::Image,
+}
+
+fn render_all(projects: &[PathBuf]) -> Result<Vec<Snapshot>> {
+    let fs = LocalFileSystem {};
+    let session = RenderSession::default();
+    let mut out: Vec<Snapshot> = vec![];
+    for project in projects {
+        for room in list_rooms(project)? {
+            let images = session.render(project, &room, &fs)?;
+            for warning in &images.warnings {
+                warn!("{}/{}: {}", project.display(), room, warning);
+            }
+            for i in 0..images.layer1.len() {
+                let Some(image) = images.composite_state(i) else {
+                    continue;
+                };
+                out.push(Snapshot {
+                    key: format!("{}/{}/{}", project.display(), room, i),
+                    image,
+                });
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn load_manifest(path: &Path) -> Result<BTreeMap<String, u64>> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read snapshot manifest {}", path.display()))?;
+    let mut out: BTreeMap<String, u64> = BTreeMap::new();
+    for line in s.lines().filter(|x| !x.trim().is_empty()) {
+        let Some((key, hash)) = line.rsplit_once(' ') else {
+            bail!("Invalid line in {}: {}", path.display(), line);
+        };
+        out.insert(key.to_string(), u64::from_str_radix(hash, 16)?);
+    }
+    Ok(out)
+}
+
+// The files of a previous baseline: those listed in its manifest, and the renders of failed
+// snapshots. Anything else in the directory is left alone.
+fn baseline_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    check_output_dir(dir, MANIFEST_FILENAME)?;
+    let manifest_path = dir.join(MANIFEST_FILENAME);
+    if !manifest_path.exists() {
+        return Ok(vec![]);
+    }
+    let mut out: Vec<PathBuf> = load_manifest(&manifest_path)?
+        .keys()
+        .map(|key| dir.join(format!("{}.png", key)))
+        .collect();
+    out.push(manifest_path);
+    out.extend(list_files(&dir.join(ACTUAL_DIR)));
+    Ok(out)
+}
+
+fn update(dir: &Path, snapshots: &[Snapshot], guard: &WriteGuard) -> Result<()> {
+    let old_files = baseline_files(dir)?;
+    let mut manifest = String::new();
+    let mut writes: Vec<PlannedWrite> = vec![];
+    for snapshot in snapshots {
+        writes.push(planned_png(
+            &snapshot.image,
+            dir.join(format!("{}.png", snapshot.key)),
+        )?);
+        manifest += &format!("{} {:016x}\n", snapshot.key, image_hash(&snapshot.image));
+    }
+    writes.push(PlannedWrite {
+        path: dir.join(MANIFEST_FILENAME),
+        contents: Some(manifest.into_bytes()),
+    });
+    guard.replace("snapshot update", old_files, writes)?;
+    if !guard.dry_run {
+        remove_empty_dirs(dir);
+    }
+    info!("Wrote {} snapshots to {}", snapshots.len(), dir.display());
+    Ok(())
+}
+
+fn verify(dir: &Path, snapshots: &[Snapshot], guard: &WriteGuard) -> Result<()> {
+    let mut baseline = load_manifest(&dir.join(MANIFEST_FILENAME))?;
+    let actual_dir = dir.join(ACTUAL_DIR);
+    let mut failures = 0;
+    let mut writes: Vec<PlannedWrite> = vec![];
+    for snapshot in snapshots {
+        let hash = image_hash(&snapshot.image);
+        match baseline.remove(&snapshot.key) {
+            Some(h) if h == hash => continue,
+            Some(_) => error!("Snapshot differs: {}", snapshot.key),
+            None => error!("Snapshot not in baseline: {}", snapshot.key),
+        }
+        writes.push(planned_png(
+            &snapshot.image,
+            actual_dir.join(format!("{}.png", snapshot.key)),
+        )?);
+        failures += 1;
+    }
+    // Renders of failures from an earlier run would be mistaken for current ones.
+    guard.replace("snapshot verify", list_files(&actual_dir), writes)?;
+    if !guard.dry_run {
+        remove_empty_dirs(&actual_dir);
+    }
+    for key in baseline.keys() {
+        error!("Snapshot missing from current renders: {}", key);
+        failures += 1;
+    }
+    if failures > 0 {
+        bail!(
+            "{} of {} snapshots failed verification (current renders written to {})",
+            failures,
+            snapshots.len(),
+            actual_dir.display()
+        );
+    }
+    info!("All {} snapshots match the baseline", snapshots.len());
+    Ok(())
+}
+
+pub fn run(args: &SnapshotArgs, projects: &[PathBuf], guard: &WriteGuard) -> Result<()> {
+    let snapshots = render_all(projects)?;
+    if args.update {
+        update(&args.dir, &snapshots, guard)
+    } else {
+        verify(&args.dir, &snapshots, guard)
+    }
+}
@@ -0,0 +1,134 @@
+// Low-resolution previews (of room states and tilesets), which are rendered on a background
+// thread so that views showing many of them don't hold up the UI. Thumbnails are cached by
+// what they show; a stale thumbnail stays visible until it has been rendered again.
+
+use std::path::PathBuf;
+
+use hashbrown::HashMap;
+use iced::widget::image;
+
+use crate::room::Image;
+
+// Maximum width and height of a thumbnail, in pixels
+pub const THUMBNAIL_SIZE: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ThumbnailKey {
+    // Difference of a room state against the reference (or just the working copy, if the
+    // reference doesn't have the state)
+    RoomState {
+        project: PathBuf,
+        room: String,
+        state_idx: usize,
+    },
+    Tileset {
+        project: PathBuf,
+        idx: usize,
+    },
+}
+
+#[derive(Default)]
+struct Entry {
+    handle: Option<image::Handle>,
+    error: Option<String>,
+    pending: bool,
+    stale: bool,
+}
+
+#[derive(Default)]
+pub struct ThumbnailCache {
+    entries: HashMap<ThumbnailKey, Entry>,
+}
+
+impl ThumbnailCache {
+    pub fn get(&self, key: &ThumbnailKey) -> Option<&image::Handle> {
+        self.entries.get(key)?.handle.as_ref()
+    }
+
+    pub fn error(&self, key: &ThumbnailKey) -> Option<&str> {
+        self.entries.get(key)?.error.as_deref()
+    }
+
+    // The given keys which need to be rendered (being missing or stale, and not already in
+    // progress), which are then marked as in progress.
+    pub fn request(&mut self, keys: impl IntoIterator<Item = ThumbnailKey>) -> Vec<ThumbnailKey> {
+        let mut out = vec![];
+        for key in keys {
+            let entry = self.entries.entry(key.clone()).or_default();
+            if entry.pending || ((entry.handle.is_some() || entry.error.is_some()) && !entry.stale)
+            {
+                continue;
+            }
+            entry.pending = true;
+            entry.stale = false;
+            out.push(key);
+        }
+        out
+    }
+
+    pub fn insert(&mut self, key: ThumbnailKey, result: Result<Image, String>) {
+        let entry = self.entries.entry(key).or_default();
+        // If the thumbnail was invalidated while it was rendering, it stays stale.
+        entry.pending = false;
+        match result {
+            Ok(image) => {
+                entry.handle = Some(image::Handle::from_rgba(
+                    image.width as u32,
+                    image.height as u32,
+                    image.pixels,
+                ));
+                entry.error = None;
+            }
+            Err(e) => {
+                entry.handle = None;
+                entry.error = Some(e);
+            }
+        }
+    }
+
+    // Mark thumbnails as needing to be rendered again.
+    pub fn invalidate(&mut self, f: impl Fn(&ThumbnailKey) -> bool) {
+        for (_, entry) in self.entries.iter_mut().filter(|(k, _)| f(k)) {
+            entry.stale = true;
+        }
+    }
+}
+
+// Shrink an image to fit within `max_size` by `max_size` pixels, averaging the pixels
+// covered by each output pixel.
+pub fn downscale(image: &Image, max_size: usize) -> Image {
+    downscale_by(
+        image,
+        image.width.max(image.height).div_ceil(max_size).max(1),
+    )
+}
+
+// Shrink an image by a whole factor, averaging the pixels covered by each output pixel.
+// Colors are weighted by opacity, so that transparent pixels don't darken their neighbors.
+pub fn downscale_by(image: &Image, factor: usize) -> Image {
+    let mut out = Image::new(image.width.div_ceil(factor), image.height.div_ceil(factor));
+    for y in 0..out.height {
+        for x in 0..out.width {
+            let mut sum = [0usize; 4];
+            let mut count = 0;
+            for sy in y * factor..((y + 1) * factor).min(image.height) {
+                for sx in x * factor..((x + 1) * factor).min(image.width) {
+                    let i = (sy * image.width + sx) * 4;
+                    let p = &image.pixels[i..i + 4];
+                    let alpha = p[3] as usize;
+                    for c in 0..3 {
+                        sum[c] += p[c] as usize * alpha;
+                    }
+                    sum[3] += alpha;
+                    count += 1;
+                }
+            }
+            let i = (y * out.width + x) * 4;
+            for c in 0..3 {
+                out.pixels[i + c] = sum[c].checked_div(sum[3]).unwrap_or(0) as u8;
+            }
+            out.pixels[i + 3] = (sum[3] / count) as u8;
+        }
+    }
+    out
+}
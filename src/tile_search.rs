@@ -0,0 +1,191 @@
+// Finding where 16x16 tiles or BTS values are used in the level data of rooms.
+
+use std::fmt::Display;
+
+use crate::{
+    config::NumberFormat,
+    smart_xml::{LevelData, Room, Screen},
+};
+
+// Data of each 16x16 tile from a room state's screens (of layer 1 or BTS), in rows of the
+// room's width in tiles. Tiles not covered by any screen are None.
+fn screen_grid(
+    room: &Room,
+    state_idx: usize,
+    screens: impl Fn(&LevelData) -> &[Screen],
+) -> Vec<Option<u16>> {
+    let width = room.width * 16;
+    let mut out = vec![None; width * room.height * 16];
+    let Some(state) = room.states.state.get(state_idx) else {
+        return out;
+    };
+    for screen in screens(&state.level_data) {
+        for (i, &data) in screen.data.iter().take(256).enumerate() {
+            let x = screen.x * 16 + i % 16;
+            let y = screen.y * 16 + i / 16;
+            match out.get_mut(y * width + x) {
+                Some(d) if x < width => *d = Some(data),
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+pub fn bts_grid(room: &Room, state_idx: usize) -> Vec<Option<u16>> {
+    screen_grid(room, state_idx, |l| &l.bts.screen)
+}
+
+// Layer 1 data (tile index, flips, and block type) at each position.
+pub fn layer1_grid(room: &Room, state_idx: usize) -> Vec<Option<u16>> {
+    screen_grid(room, state_idx, |l| &l.layer_1.screen)
+}
+
+// Index of the 16x16 tile (from the tileset) at each position of layer 1.
+pub fn layer1_tile_grid(room: &Room, state_idx: usize) -> Vec<Option<u16>> {
+    layer1_grid(room, state_idx)
+        .into_iter()
+        .map(|x| x.map(|data| data & 0x3FF))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchKind {
+    #[default]
+    Tile,
+    Bts,
+}
+
+impl Display for SearchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchKind::Tile => write!(f, "Tile"),
+            SearchKind::Bts => write!(f, "BTS"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchTarget {
+    pub kind: SearchKind,
+    pub value: u16,
+}
+
+impl SearchKind {
+    // Largest value that can be searched for
+    pub fn max(self) -> u16 {
+        match self {
+            SearchKind::Tile => 0x3FF,
+            SearchKind::Bts => 0xFF,
+        }
+    }
+
+    // Number of hex digits values are shown with
+    pub fn hex_digits(self) -> usize {
+        match self {
+            SearchKind::Tile => 3,
+            SearchKind::Bts => 2,
+        }
+    }
+}
+
+impl SearchTarget {
+    // e.g. "tile 0A3" (or "tile 163" in decimal)
+    pub fn describe(&self, format: NumberFormat) -> String {
+        let value = format.format(self.value as usize, self.kind.hex_digits());
+        match self.kind {
+            SearchKind::Tile => format!("tile {}", value),
+            SearchKind::Bts => format!("BTS {}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub state_idx: usize,
+    // Position in 16x16 tiles
+    pub x: usize,
+    pub y: usize,
+}
+
+// Every position in every state of the room where the target appears, in reading order.
+pub fn search_room(room: &Room, target: SearchTarget) -> Vec<SearchHit> {
+    let width = room.width * 16;
+    let mut hits = vec![];
+    for state_idx in 0..room.states.state.len() {
+        let grid = match target.kind {
+            SearchKind::Tile => layer1_tile_grid(room, state_idx),
+            SearchKind::Bts => bts_grid(room, state_idx),
+        };
+        for (i, &data) in grid.iter().enumerate() {
+            if data == Some(target.value) {
+                hits.push(SearchHit {
+                    state_idx,
+                    x: i % width,
+                    y: i / width,
+                });
+            }
+        }
+    }
+    hits
+}
+
+// Block type of door tiles (in the top 4 bits of their layer 1 data)
+const DOOR_BLOCK: u16 = 0x9;
+
+// A door in a room state: a group of adjacent door tiles (positions in 16x16 tiles), and its
+// index in the room's door list (the tiles' BTS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoorTiles {
+    pub door_idx: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// The doors of a room state, as the bounding box of each group of adjacent door tiles with the
+// same BTS.
+pub fn door_tiles(room: &Room, state_idx: usize) -> Vec<DoorTiles> {
+    let width = room.width * 16;
+    let layer1 = layer1_grid(room, state_idx);
+    let bts = bts_grid(room, state_idx);
+    let door_at = |i: usize| match (layer1[i], bts[i]) {
+        (Some(data), Some(bts)) if data >> 12 == DOOR_BLOCK => Some(bts as usize),
+        _ => None,
+    };
+    let mut seen = vec![false; layer1.len()];
+    let mut out = vec![];
+    for start in 0..layer1.len() {
+        let Some(door_idx) = door_at(start).filter(|_| !seen[start]) else {
+            continue;
+        };
+        seen[start] = true;
+        let (mut x0, mut y0, mut x1, mut y1) = (width, usize::MAX, 0, 0);
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x), y1.max(y));
+            let neighbors = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                i.checked_sub(width),
+                Some(i + width).filter(|&j| j < layer1.len()),
+            ];
+            for j in neighbors.into_iter().flatten() {
+                if !seen[j] && door_at(j) == Some(door_idx) {
+                    seen[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        out.push(DoorTiles {
+            door_idx,
+            x: x0,
+            y: y0,
+            width: x1 - x0 + 1,
+            height: y1 - y0 + 1,
+        });
+    }
+    out
+}
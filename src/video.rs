@@ -0,0 +1,146 @@
+// Short videos which pan across a room while cross-fading between its two versions, e.g. for
+// showing off an overhauled area in release notes. Frames are rendered here and encoded by
+// ffmpeg, so the container and codec follow from the output's extension (.mp4 or .webm).
+
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result, bail};
+
+use crate::export::{crop, save_png};
+use crate::guarded_write::{PlannedWrite, WriteGuard};
+use crate::room::Image;
+
+// Size of the view panned across the room (two screens wide and high), in pixels
+pub const VIDEO_WIDTH: usize = 512;
+pub const VIDEO_HEIGHT: usize = 448;
+pub const VIDEO_FPS: usize = 30;
+pub const VIDEO_SECONDS: usize = 8;
+// Seconds for each fade from one version to the other and back
+const FADE_PERIOD: f32 = 2.0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    #[default]
+    Mp4,
+    WebM,
+}
+
+impl std::fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoFormat::Mp4 => write!(f, "MP4"),
+            VideoFormat::WebM => write!(f, "WebM"),
+        }
+    }
+}
+
+impl VideoFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::WebM => "webm",
+        }
+    }
+}
+
+fn blend(a: &Image, b: &Image, t: f32) -> Image {
+    let mut out = Image::new(a.width, a.height);
+    for ((o, &x), &y) in out.pixels.iter_mut().zip(&a.pixels).zip(&b.pixels) {
+        *o = (x as f32 * (1.0 - t) + y as f32 * t).round() as u8;
+    }
+    out
+}
+
+// Frame `i` of the video: the view moves smoothly from the top-left corner of the room to
+// the bottom-right, while fading between the reference and the working copy.
+fn frame(working: &Image, reference: &Image, i: usize) -> Image {
+    let frames = VIDEO_FPS * VIDEO_SECONDS;
+    let progress = i as f32 / (frames - 1) as f32;
+    let eased = progress * progress * (3.0 - 2.0 * progress);
+    // Dimensions must be even for the usual (4:2:0) pixel formats.
+    let width = VIDEO_WIDTH.min(working.width) & !1;
+    let height = VIDEO_HEIGHT.min(working.height) & !1;
+    let x = ((working.width - width) as f32 * eased).round() as usize;
+    let y = ((working.height - height) as f32 * eased).round() as usize;
+    let time = i as f32 / VIDEO_FPS as f32;
+    let fade = 0.5 - 0.5 * (time * std::f32::consts::TAU / FADE_PERIOD).cos();
+    blend(
+        &crop(reference, x, y, width, height),
+        &crop(working, x, y, width, height),
+        fade,
+    )
+}
+
+// Numbers the temporary directories of videos being encoded at the same time
+static NEXT_VIDEO: AtomicUsize = AtomicUsize::new(0);
+
+// Write the video to `output` (whose extension picks the format), using `ffmpeg` to encode.
+pub fn export_comparison_video(
+    working: &Image,
+    reference: &Image,
+    output: &Path,
+    ffmpeg: &str,
+    guard: &WriteGuard,
+) -> Result<()> {
+    if (working.width, working.height) != (reference.width, reference.height) {
+        bail!("The working copy and reference have different dimensions");
+    }
+    // The frames and the encoded video go to a temporary directory, and the video is then
+    // written to `output` through the guard.
+    let frames_dir = std::env::temp_dir().join(format!(
+        "smartdiff-video-{}-{}",
+        std::process::id(),
+        NEXT_VIDEO.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = encode_video(working, reference, output, ffmpeg, &frames_dir).and_then(|video| {
+        guard.apply(
+            "comparison video",
+            &[PlannedWrite {
+                path: output.to_path_buf(),
+                contents: Some(video),
+            }],
+        )
+    });
+    // The frames are only an intermediate step.
+    let _ = std::fs::remove_dir_all(&frames_dir);
+    result.map(|_| ())
+}
+
+// Encode the video in `frames_dir`, returning its contents.
+fn encode_video(
+    working: &Image,
+    reference: &Image,
+    output: &Path,
+    ffmpeg: &str,
+    frames_dir: &Path,
+) -> Result<Vec<u8>> {
+    let encoded =
+        frames_dir.join(Path::new("video").with_extension(output.extension().unwrap_or_default()));
+    for i in 0..VIDEO_FPS * VIDEO_SECONDS {
+        save_png(
+            &frame(working, reference, i),
+            &frames_dir.join(format!("{:05}.png", i)),
+        )?;
+    }
+    let status = Command::new(ffmpeg)
+        .arg("-y")
+        .args(["-loglevel", "error"])
+        .args(["-framerate", &VIDEO_FPS.to_string()])
+        .arg("-i")
+        .arg(frames_dir.join("%05d.png"))
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(&encoded)
+        .status()
+        .with_context(|| format!("Unable to run {} (needed to encode videos)", ffmpeg))?;
+    if !status.success() {
+        bail!(
+            "{} failed to encode {} ({})",
+            ffmpeg,
+            output.display(),
+            status
+        );
+    }
+    std::fs::read(&encoded).with_context(|| format!("Unable to read {}", encoded.display()))
+}
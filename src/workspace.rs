@@ -0,0 +1,222 @@
+// A workspace file lists several directories to find projects in, possibly outside the
+// current directory (e.g. separate checkouts of related hacks), each with the git repository
+// it belongs to and the reference to compare its projects against:
+//
+//   [[root]]
+//   path = "../other-hack"
+//   repo = "../other-hack"  # default: the repository containing `path`
+//   reference = "origin/main"  # default: the usual reference
+//
+// Relative paths are relative to the directory containing the workspace file.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use git2::Repository;
+use serde::Deserialize;
+
+use crate::file_system::FileSystem;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceRoot {
+    pub path: PathBuf,
+    pub repo: Option<PathBuf>,
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Workspace {
+    #[serde(rename = "root", default)]
+    pub roots: Vec<WorkspaceRoot>,
+}
+
+// A path relative to the current directory if it is inside it (so that projects there are
+// named as usual), and otherwise absolute (as local paths may not go above the current
+// directory).
+fn resolve(dir: &Path, path: &Path) -> Result<PathBuf> {
+    let path = dir.join(path);
+    let absolute = path
+        .canonicalize()
+        .with_context(|| format!("Unable to find {}", path.display()))?;
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    Ok(match absolute.strip_prefix(&cwd) {
+        Ok(p) if p.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(p) => p.to_path_buf(),
+        Err(_) => absolute,
+    })
+}
+
+impl Workspace {
+    pub fn load(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read workspace at {}", path.display()))?;
+        let mut workspace: Workspace = toml::from_str(&s)
+            .with_context(|| format!("Unable to parse workspace at {}", path.display()))?;
+        if workspace.roots.is_empty() {
+            bail!("The workspace at {} has no roots", path.display());
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for root in &mut workspace.roots {
+            root.path = resolve(dir, &root.path)?;
+            root.repo = root.repo.as_deref().map(|r| resolve(dir, r)).transpose()?;
+        }
+        Ok(workspace)
+    }
+
+    // The root containing a path (the innermost one, if roots are nested).
+    pub fn root_of(&self, path: &Path) -> Option<&WorkspaceRoot> {
+        self.root_index_of(path).map(|i| &self.roots[i])
+    }
+
+    fn root_index_of(&self, path: &Path) -> Option<usize> {
+        self.roots
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.path == Path::new(".") || path.starts_with(&r.path))
+            .max_by_key(|(_, r)| r.depth())
+            .map(|(i, _)| i)
+    }
+}
+
+// The relative path to keep a project's files under in our output directories (notes and
+// exports). Roots may be outside the current directory, so projects in a workspace are
+// keyed by the index of their root and their path within it.
+pub fn project_key(workspace: Option<&Workspace>, project: &Path) -> PathBuf {
+    let (prefix, relative) = match workspace.and_then(|w| Some((w, w.root_index_of(project)?))) {
+        Some((workspace, i)) => {
+            let root = &workspace.roots[i];
+            let relative = if root.path == Path::new(".") {
+                project
+            } else {
+                project.strip_prefix(&root.path).unwrap_or(project)
+            };
+            (PathBuf::from(i.to_string()), relative)
+        }
+        None => (PathBuf::new(), project),
+    };
+    // Anything that could lead outside the output directory is dropped.
+    prefix.join(
+        relative
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect::<PathBuf>(),
+    )
+}
+
+impl WorkspaceRoot {
+    // Nesting depth of the root's path, with the current directory at the top.
+    fn depth(&self) -> usize {
+        if self.path == Path::new(".") {
+            0
+        } else {
+            self.path.components().count()
+        }
+    }
+
+    pub fn open_repo(&self) -> Result<Repository> {
+        let dir = self.repo.as_ref().unwrap_or(&self.path);
+        Repository::discover(dir)
+            .with_context(|| format!("No git repository found for {}", self.path.display()))
+    }
+
+    // Where the root is within its repository, e.g. "" if it is the top level.
+    pub fn prefix(&self, repo: &Repository) -> Result<PathBuf> {
+        let Some(workdir) = repo.workdir() else {
+            bail!("The repository for {} is bare", self.path.display());
+        };
+        let root = self.path.canonicalize()?;
+        match root.strip_prefix(workdir.canonicalize()?) {
+            Ok(prefix) => Ok(prefix.to_path_buf()),
+            Err(_) => bail!(
+                "{} is not inside the repository at {}",
+                self.path.display(),
+                workdir.display()
+            ),
+        }
+    }
+
+    // Convert a path in the repository to our path for it, if it's inside the root.
+    pub fn from_repo_path(&self, prefix: &Path, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(prefix).ok()?;
+        if self.path == Path::new(".") {
+            Some(relative.to_path_buf())
+        } else {
+            Some(self.path.join(relative))
+        }
+    }
+
+    // Convert our path for a file under the root to its path in the repository.
+    pub fn to_repo_path(&self, prefix: &Path, path: &Path) -> Option<PathBuf> {
+        if self.path == Path::new(".") {
+            Some(prefix.join(path))
+        } else {
+            Some(prefix.join(path.strip_prefix(&self.path).ok()?))
+        }
+    }
+}
+
+// A file system of a root's repository, accessed by our paths for its files.
+pub struct RootFileSystem<'a, F: FileSystem + ?Sized> {
+    pub root: &'a WorkspaceRoot,
+    pub prefix: PathBuf,
+    pub fs: &'a F,
+}
+
+impl<F: FileSystem + ?Sized> FileSystem for RootFileSystem<'_, F> {
+    fn load(&self, path: &Path) -> Result<Vec<u8>> {
+        let Some(repo_path) = self.root.to_repo_path(&self.prefix, path) else {
+            bail!(
+                "{} is outside the workspace root {}",
+                path.display(),
+                self.root.path.display()
+            );
+        };
+        self.fs.load(&repo_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace(paths: &[&str]) -> Workspace {
+        Workspace {
+            roots: paths
+                .iter()
+                .map(|p| WorkspaceRoot {
+                    path: PathBuf::from(p),
+                    repo: None,
+                    reference: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn root_of_prefers_innermost_root() {
+        for paths in [[".", "hackA"], ["hackA", "."]] {
+            let workspace = workspace(&paths);
+            let root_of = |p: &str| workspace.root_of(Path::new(p)).map(|r| r.path.clone());
+            assert_eq!(root_of("hackA/project"), Some(PathBuf::from("hackA")));
+            assert_eq!(root_of("hackB/project"), Some(PathBuf::from(".")));
+        }
+    }
+
+    #[test]
+    fn project_key_stays_relative() {
+        let workspace = workspace(&[".", "/home/other-hack", "../third-hack"]);
+        let key = |p: &str| project_key(Some(&workspace), Path::new(p));
+        assert_eq!(key("project"), PathBuf::from("0/project"));
+        assert_eq!(key("/home/other-hack/project"), PathBuf::from("1/project"));
+        assert_eq!(key("../third-hack/a/project"), PathBuf::from("2/a/project"));
+        assert_eq!(
+            project_key(None, Path::new("a/project")),
+            PathBuf::from("a/project")
+        );
+        assert_eq!(
+            project_key(None, Path::new("/a/../project")),
+            PathBuf::from("a/project")
+        );
+    }
+}